@@ -0,0 +1,190 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Free-running counters that can back the monotonic clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClocksourceKind {
+    /// Time-stamp counter; only trusted when CPUID reports it invariant.
+    Tsc,
+    /// HPET main counter.
+    Hpet,
+    /// Timer tick count. Always there, but only tick-granular.
+    Pit,
+}
+
+impl ClocksourceKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tsc => "tsc",
+            Self::Hpet => "hpet",
+            Self::Pit => "pit",
+        }
+    }
+}
+
+/// What the boot CPU measured about each counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClockCaps {
+    /// CPUID.80000007H:EDX.InvariantTSC.
+    pub invariant_tsc: bool,
+    pub tsc_hz: Option<u64>,
+    pub hpet_hz: Option<u64>,
+    pub tick_hz: u32,
+}
+
+/// Picks the best counter and its rate: an invariant TSC, then the HPET,
+/// then the tick count.
+pub fn choose_clocksource(caps: ClockCaps) -> (ClocksourceKind, u64) {
+    match caps {
+        ClockCaps {
+            invariant_tsc: true,
+            tsc_hz: Some(hz),
+            ..
+        } if hz != 0 => (ClocksourceKind::Tsc, hz),
+        ClockCaps {
+            hpet_hz: Some(hz), ..
+        } if hz != 0 => (ClocksourceKind::Hpet, hz),
+        _ => (ClocksourceKind::Pit, caps.tick_hz.max(1) as u64),
+    }
+}
+
+/// Fixed-point cycles-to-nanoseconds factor: `ns = cycles * mult >> shift`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CycleScale {
+    mult: u64,
+    shift: u32,
+}
+
+impl CycleScale {
+    const SHIFT: u32 = 32;
+
+    pub fn from_hz(hz: u64) -> Self {
+        Self {
+            mult: ((NSEC_PER_SEC as u128) << Self::SHIFT).div_ceil(hz.max(1) as u128) as u64,
+            shift: Self::SHIFT,
+        }
+    }
+
+    pub fn to_ns(self, cycles: u64) -> u64 {
+        ((cycles as u128 * self.mult as u128) >> self.shift) as u64
+    }
+}
+
+/// Clamps readings so the clock never goes backwards, even when CPUs
+/// disagree slightly about the counter.
+pub struct MonotonicClamp {
+    last_ns: AtomicU64,
+}
+
+impl MonotonicClamp {
+    pub const fn new() -> Self {
+        Self {
+            last_ns: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, ns: u64) -> u64 {
+        let previous = self.last_ns.fetch_max(ns, Ordering::AcqRel);
+        previous.max(ns)
+    }
+}
+
+impl Default for MonotonicClamp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime,
+    Monotonic,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timespec {
+    pub sec: u64,
+    pub nsec: u32,
+}
+
+impl Timespec {
+    pub fn from_ns(ns: u64) -> Self {
+        Self {
+            sec: ns / NSEC_PER_SEC,
+            nsec: (ns % NSEC_PER_SEC) as u32,
+        }
+    }
+}
+
+/// `clock_gettime` over a monotonic reading and the wall-clock time, in
+/// Unix nanoseconds, at which that reading was zero.
+pub fn clock_gettime(id: ClockId, monotonic_ns: u64, boot_epoch_ns: u64) -> Timespec {
+    match id {
+        ClockId::Monotonic => Timespec::from_ns(monotonic_ns),
+        ClockId::Realtime => Timespec::from_ns(boot_epoch_ns.saturating_add(monotonic_ns)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invariant_tsc_wins_then_hpet_then_tick() {
+        let mut caps = ClockCaps {
+            tsc_hz: Some(3_000_000_000),
+            tick_hz: 100,
+            ..ClockCaps::default()
+        };
+        assert_eq!(choose_clocksource(caps), (ClocksourceKind::Pit, 100));
+
+        caps.hpet_hz = Some(14_318_180);
+        assert_eq!(
+            choose_clocksource(caps),
+            (ClocksourceKind::Hpet, 14_318_180)
+        );
+
+        caps.invariant_tsc = true;
+        assert_eq!(
+            choose_clocksource(caps),
+            (ClocksourceKind::Tsc, 3_000_000_000)
+        );
+    }
+
+    #[test]
+    fn cycle_scale_converts_without_overflow() {
+        let tsc = CycleScale::from_hz(3_000_000_000);
+        assert_eq!(tsc.to_ns(3_000_000_000), NSEC_PER_SEC);
+        // A year of 3 GHz cycles still converts.
+        let year = 365 * 24 * 3600 * 3_000_000_000u64;
+        assert_eq!(tsc.to_ns(year) / NSEC_PER_SEC, 365 * 24 * 3600);
+
+        let tick = CycleScale::from_hz(100);
+        assert_eq!(tick.to_ns(150), 1_500_000_000);
+    }
+
+    #[test]
+    fn clamp_never_goes_backwards() {
+        let clamp = MonotonicClamp::new();
+        assert_eq!(clamp.observe(100), 100);
+        assert_eq!(clamp.observe(90), 100);
+        assert_eq!(clamp.observe(120), 120);
+    }
+
+    #[test]
+    fn realtime_is_boot_epoch_plus_monotonic() {
+        let epoch = 1_700_000_000 * NSEC_PER_SEC;
+        assert_eq!(
+            clock_gettime(ClockId::Realtime, 2_500_000_000, epoch),
+            Timespec {
+                sec: 1_700_000_002,
+                nsec: 500_000_000
+            }
+        );
+        assert_eq!(
+            clock_gettime(ClockId::Monotonic, 2_500_000_000, epoch).sec,
+            2
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use core::fmt;
+
+/// How the CPU reports an exception, which decides whether the faulting
+/// instruction can simply be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionClass {
+    /// Reported before the instruction; returning retries it.
+    Fault,
+    /// Reported after the instruction; returning continues past it.
+    Trap,
+    /// Machine state is not reliably restartable.
+    Abort,
+    /// Non-maskable interrupt.
+    Interrupt,
+    Reserved,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionInfo {
+    pub vector: u8,
+    pub mnemonic: &'static str,
+    pub name: &'static str,
+    pub class: ExceptionClass,
+    /// Whether the CPU pushes an error code.
+    pub error_code: bool,
+}
+
+const fn entry(
+    vector: u8,
+    mnemonic: &'static str,
+    name: &'static str,
+    class: ExceptionClass,
+    error_code: bool,
+) -> ExceptionInfo {
+    ExceptionInfo {
+        vector,
+        mnemonic,
+        name,
+        class,
+        error_code,
+    }
+}
+
+const fn reserved(vector: u8) -> ExceptionInfo {
+    entry(vector, "#RES", "Reserved", ExceptionClass::Reserved, false)
+}
+
+use ExceptionClass::{Abort, Fault, Interrupt, Trap};
+
+/// The 32 architectural exception vectors (Intel SDM vol. 3, table 6-1,
+/// plus AMD's #VC and #SX).
+pub const EXCEPTIONS: [ExceptionInfo; 32] = [
+    entry(0, "#DE", "Divide Error", Fault, false),
+    entry(1, "#DB", "Debug", Trap, false),
+    entry(2, "NMI", "Non-Maskable Interrupt", Interrupt, false),
+    entry(3, "#BP", "Breakpoint", Trap, false),
+    entry(4, "#OF", "Overflow", Trap, false),
+    entry(5, "#BR", "BOUND Range Exceeded", Fault, false),
+    entry(6, "#UD", "Invalid Opcode", Fault, false),
+    entry(7, "#NM", "Device Not Available", Fault, false),
+    entry(8, "#DF", "Double Fault", Abort, true),
+    entry(9, "#CSO", "Coprocessor Segment Overrun", Abort, false),
+    entry(10, "#TS", "Invalid TSS", Fault, true),
+    entry(11, "#NP", "Segment Not Present", Fault, true),
+    entry(12, "#SS", "Stack-Segment Fault", Fault, true),
+    entry(13, "#GP", "General Protection", Fault, true),
+    entry(14, "#PF", "Page Fault", Fault, true),
+    reserved(15),
+    entry(16, "#MF", "x87 Floating-Point Error", Fault, false),
+    entry(17, "#AC", "Alignment Check", Fault, true),
+    entry(18, "#MC", "Machine Check", Abort, false),
+    entry(19, "#XM", "SIMD Floating-Point Exception", Fault, false),
+    entry(20, "#VE", "Virtualization Exception", Fault, false),
+    entry(21, "#CP", "Control Protection", Fault, true),
+    reserved(22),
+    reserved(23),
+    reserved(24),
+    reserved(25),
+    reserved(26),
+    reserved(27),
+    entry(28, "#HV", "Hypervisor Injection", Fault, false),
+    entry(29, "#VC", "VMM Communication", Fault, true),
+    entry(30, "#SX", "Security Exception", Fault, true),
+    reserved(31),
+];
+
+pub fn info(vector: u8) -> Option<&'static ExceptionInfo> {
+    EXCEPTIONS.get(vector as usize)
+}
+
+/// Signal a faulting user process receives, with its Linux number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultSignal {
+    Ill = 4,
+    Trap = 5,
+    Bus = 7,
+    Fpe = 8,
+    Segv = 11,
+}
+
+impl FaultSignal {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ill => "SIGILL",
+            Self::Trap => "SIGTRAP",
+            Self::Bus => "SIGBUS",
+            Self::Fpe => "SIGFPE",
+            Self::Segv => "SIGSEGV",
+        }
+    }
+}
+
+/// What to do with an exception once it has been logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Harmless in the kernel (breakpoints, NMIs): return and carry on.
+    Resume,
+    /// Caused by a user process: hand it the signal.
+    Deliver(FaultSignal),
+    /// Caused by the kernel, or the machine is in trouble.
+    Panic,
+}
+
+/// Decides who pays for an exception: the user process that raised it, or
+/// the kernel, which cannot recover from its own faults.
+pub fn policy(vector: u8, from_user: bool) -> FaultAction {
+    let signal = match vector {
+        0 | 16 | 19 => FaultSignal::Fpe,
+        1 | 3 => FaultSignal::Trap,
+        6 | 7 => FaultSignal::Ill,
+        17 => FaultSignal::Bus,
+        _ => FaultSignal::Segv,
+    };
+    match vector {
+        // Machine-wide conditions, whoever happened to be running.
+        8 | 9 | 18 => FaultAction::Panic,
+        2 => FaultAction::Resume,
+        _ if from_user => FaultAction::Deliver(signal),
+        1 | 3 => FaultAction::Resume,
+        _ => FaultAction::Panic,
+    }
+}
+
+/// Error code of #TS, #NP, #SS, #GP and friends: the selector involved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorErrorCode(pub u32);
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("none");
+        }
+        let table = match (self.0 >> 1) & 0b11 {
+            0b00 => "gdt",
+            0b01 | 0b11 => "idt",
+            _ => "ldt",
+        };
+        write!(f, "{}[{}]", table, (self.0 >> 3) & 0x1FFF)?;
+        if self.0 & 1 != 0 {
+            f.write_str(" external")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error code of #PF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFaultErrorCode(pub u32);
+
+impl fmt::Display for PageFaultErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.0;
+        f.write_str(if code & 1 != 0 {
+            "protection"
+        } else {
+            "not-present"
+        })?;
+        f.write_str(if code & 2 != 0 { " write" } else { " read" })?;
+        f.write_str(if code & 4 != 0 { " user" } else { " kernel" })?;
+        for (bit, name) in [
+            (3, " reserved-bit"),
+            (4, " fetch"),
+            (5, " pkey"),
+            (6, " shadow-stack"),
+        ] {
+            if code & (1 << bit) != 0 {
+                f.write_str(name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats an exception's error code the way it is meant to be read.
+pub fn describe_error_code(vector: u8, code: u32) -> ErrorCode {
+    ErrorCode { vector, code }
+}
+
+pub struct ErrorCode {
+    vector: u8,
+    code: u32,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.code)?;
+        match self.vector {
+            14 => write!(f, " ({})", PageFaultErrorCode(self.code)),
+            10 | 11 | 12 | 13 | 17 => write!(f, " ({})", SelectorErrorCode(self.code)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn table_covers_every_vector_with_the_right_error_codes() {
+        for (vector, exception) in EXCEPTIONS.iter().enumerate() {
+            assert_eq!(exception.vector as usize, vector);
+        }
+        let with_code: alloc::vec::Vec<u8> = EXCEPTIONS
+            .iter()
+            .filter(|e| e.error_code)
+            .map(|e| e.vector)
+            .collect();
+        assert_eq!(with_code, [8, 10, 11, 12, 13, 14, 17, 21, 29, 30]);
+        assert_eq!(info(13).unwrap().mnemonic, "#GP");
+        assert!(info(32).is_none());
+    }
+
+    #[test]
+    fn user_faults_are_delivered_and_kernel_faults_panic() {
+        assert_eq!(policy(13, true), FaultAction::Deliver(FaultSignal::Segv));
+        assert_eq!(policy(0, true), FaultAction::Deliver(FaultSignal::Fpe));
+        assert_eq!(policy(6, true), FaultAction::Deliver(FaultSignal::Ill));
+        assert_eq!(policy(17, true), FaultAction::Deliver(FaultSignal::Bus));
+        assert_eq!(policy(3, true), FaultAction::Deliver(FaultSignal::Trap));
+
+        assert_eq!(policy(13, false), FaultAction::Panic);
+        assert_eq!(policy(14, false), FaultAction::Panic);
+        assert_eq!(policy(3, false), FaultAction::Resume);
+        assert_eq!(policy(2, true), FaultAction::Resume);
+        assert_eq!(policy(18, true), FaultAction::Panic);
+        assert_eq!(policy(8, false), FaultAction::Panic);
+    }
+
+    #[test]
+    fn fault_signals_carry_their_linux_numbers_and_names() {
+        for (signal, number, name) in [
+            (FaultSignal::Ill, 4, "SIGILL"),
+            (FaultSignal::Trap, 5, "SIGTRAP"),
+            (FaultSignal::Bus, 7, "SIGBUS"),
+            (FaultSignal::Fpe, 8, "SIGFPE"),
+            (FaultSignal::Segv, 11, "SIGSEGV"),
+        ] {
+            assert_eq!((signal as u32, signal.name()), (number, name));
+        }
+    }
+
+    #[test]
+    fn error_codes_are_decoded() {
+        assert_eq!(format!("{}", describe_error_code(13, 0)), "0x0 (none)");
+        assert_eq!(
+            format!("{}", describe_error_code(13, 0x6a)),
+            "0x6a (idt[13])"
+        );
+        assert_eq!(
+            format!("{}", describe_error_code(11, 0x19)),
+            "0x19 (gdt[3] external)"
+        );
+        assert_eq!(
+            format!("{}", describe_error_code(14, 0b10111)),
+            "0x17 (protection write user fetch)"
+        );
+        assert_eq!(format!("{}", describe_error_code(8, 0)), "0x0");
+    }
+}
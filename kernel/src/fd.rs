@@ -0,0 +1,176 @@
+//! Per-process file descriptor tables.
+//!
+//! A descriptor names an open file description, `T`. `dup` and `dup2`
+//! copy the handle rather than the description, so with a shared `T` such
+//! as `Arc<Mutex<..>>` both descriptors move the same offset.
+
+pub const MAX_FDS: usize = 32;
+
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// Cloning a table, as `fork` does, shares every open file with the copy.
+#[derive(Clone)]
+pub struct FdTable<T> {
+    slots: [Option<T>; MAX_FDS],
+}
+
+impl<T: Clone> Default for FdTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> FdTable<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: core::array::from_fn(|_| None),
+        }
+    }
+
+    /// Stores `file` in the lowest free descriptor.
+    pub fn insert(&mut self, file: T) -> Result<usize, &'static str> {
+        let fd = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or("descriptor table full")?;
+        self.slots[fd] = Some(file);
+        Ok(fd)
+    }
+
+    pub fn get(&self, fd: usize) -> Option<&T> {
+        self.slots.get(fd)?.as_ref()
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<T, &'static str> {
+        self.slots
+            .get_mut(fd)
+            .and_then(Option::take)
+            .ok_or("bad descriptor")
+    }
+
+    /// A second descriptor, the lowest free one, for `fd`'s file.
+    pub fn dup(&mut self, fd: usize) -> Result<usize, &'static str> {
+        let file = self.get(fd).ok_or("bad descriptor")?.clone();
+        self.insert(file)
+    }
+
+    /// Makes `new` another descriptor for `old`'s file, closing whatever
+    /// `new` held. Duplicating a descriptor onto itself changes nothing.
+    pub fn dup2(&mut self, old: usize, new: usize) -> Result<usize, &'static str> {
+        let file = self.get(old).ok_or("bad descriptor")?.clone();
+        let slot = self.slots.get_mut(new).ok_or("bad descriptor")?;
+        if old != new {
+            *slot = Some(file);
+        }
+        Ok(new)
+    }
+
+    /// Empties the table, handing back every open file.
+    pub fn clear(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.slots.iter_mut().filter_map(Option::take)
+    }
+}
+
+/// The offset `lseek` moves to: `delta` from the start, from `current`, or
+/// from the end of a file `len` bytes long. Offsets past the end are
+/// allowed; negative ones are not.
+pub fn seek(current: usize, len: usize, delta: i64, whence: u32) -> Result<usize, &'static str> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => current,
+        SEEK_END => len,
+        _ => return Err("bad whence"),
+    };
+    let target = (base as i64).checked_add(delta).ok_or("offset overflow")?;
+    usize::try_from(target).map_err(|_| "negative offset")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    #[test]
+    fn descriptors_take_the_lowest_free_slot() {
+        let mut table = FdTable::new();
+        assert_eq!(table.insert('a'), Ok(0));
+        assert_eq!(table.insert('b'), Ok(1));
+        assert_eq!(table.insert('c'), Ok(2));
+        assert_eq!(table.close(1), Ok('b'));
+        assert_eq!(table.close(1), Err("bad descriptor"));
+        assert_eq!(table.insert('d'), Ok(1));
+        assert_eq!(table.get(1), Some(&'d'));
+        assert_eq!(table.get(MAX_FDS), None);
+    }
+
+    #[test]
+    fn a_full_table_rejects_new_descriptors() {
+        let mut table = FdTable::new();
+        for fd in 0..MAX_FDS {
+            assert_eq!(table.insert(fd), Ok(fd));
+        }
+        assert_eq!(table.insert(99), Err("descriptor table full"));
+        assert_eq!(table.dup(0), Err("descriptor table full"));
+    }
+
+    #[test]
+    fn duplicates_share_the_open_file() {
+        let mut table = FdTable::new();
+        let offset = Rc::new(Cell::new(0usize));
+        let fd = table.insert(offset.clone()).unwrap();
+        let copy = table.dup(fd).unwrap();
+        assert_eq!(copy, 1);
+
+        table.get(copy).unwrap().set(42);
+        assert_eq!(table.get(fd).unwrap().get(), 42);
+        table.close(fd).unwrap();
+        assert_eq!(table.get(copy).unwrap().get(), 42);
+    }
+
+    #[test]
+    fn a_cloned_table_shares_open_files() {
+        let mut parent = FdTable::new();
+        let offset = Rc::new(Cell::new(0usize));
+        parent.insert(offset.clone()).unwrap();
+        let mut child = parent.clone();
+
+        child.get(0).unwrap().set(7);
+        assert_eq!(parent.get(0).unwrap().get(), 7);
+        child.close(0).unwrap();
+        assert!(parent.get(0).is_some());
+        assert_eq!(Rc::strong_count(&offset), 2);
+    }
+
+    #[test]
+    fn dup2_replaces_the_target_and_ignores_self_copies() {
+        let mut table = FdTable::new();
+        table.insert("console").unwrap();
+        table.insert("file").unwrap();
+        assert_eq!(table.dup2(1, 0), Ok(0));
+        assert_eq!(table.get(0), Some(&"file"));
+        assert_eq!(table.dup2(1, 1), Ok(1));
+        assert_eq!(table.get(1), Some(&"file"));
+        assert_eq!(table.dup2(1, 5), Ok(5));
+        assert_eq!(table.dup2(7, 2), Err("bad descriptor"));
+        assert_eq!(table.dup2(1, MAX_FDS), Err("bad descriptor"));
+
+        let closed: alloc::vec::Vec<_> = table.clear().collect();
+        assert_eq!(closed, ["file", "file", "file"]);
+        assert_eq!(table.get(0), None);
+    }
+
+    #[test]
+    fn seek_is_relative_to_start_current_or_end() {
+        assert_eq!(seek(10, 100, 5, SEEK_SET), Ok(5));
+        assert_eq!(seek(10, 100, 5, SEEK_CUR), Ok(15));
+        assert_eq!(seek(10, 100, -20, SEEK_END), Ok(80));
+        assert_eq!(seek(10, 100, 50, SEEK_END), Ok(150));
+        assert_eq!(seek(10, 100, -11, SEEK_CUR), Err("negative offset"));
+        assert_eq!(seek(10, 100, 0, 3), Err("bad whence"));
+        assert_eq!(seek(10, 100, i64::MAX, SEEK_CUR), Err("offset overflow"));
+    }
+}
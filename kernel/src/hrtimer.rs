@@ -0,0 +1,283 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BinaryHeap};
+use core::cmp::Reverse;
+
+pub type Callback = Box<dyn FnMut() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+struct Timer {
+    deadline_ns: u64,
+    period_ns: Option<u64>,
+    /// `None` while the callback is out being run by `pop_expired`.
+    callback: Option<Callback>,
+    /// Bumped on every rearm so stale heap entries can be skipped.
+    generation: u64,
+}
+
+/// A timer taken off the queue by `pop_expired`. Run it, then hand it back
+/// to `complete` so periodic timers are queued again.
+pub struct Expired {
+    pub id: TimerId,
+    pub deadline_ns: u64,
+    callback: Callback,
+}
+
+impl Expired {
+    pub fn run(&mut self) {
+        (self.callback)();
+    }
+}
+
+/// One-shot and periodic timers ordered by deadline in nanoseconds.
+///
+/// A binary min-heap keyed on `(deadline, id, generation)`; cancel and
+/// rearm leave the old heap entry behind and bump the generation, and
+/// stale entries are dropped when they reach the top.
+#[derive(Default)]
+pub struct TimerQueue {
+    heap: BinaryHeap<Reverse<(u64, TimerId, u64)>>,
+    timers: BTreeMap<TimerId, Timer>,
+    next_id: u64,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            timers: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn arm(&mut self, deadline_ns: u64, callback: Callback) -> TimerId {
+        self.insert(deadline_ns, None, callback)
+    }
+
+    /// Fires first at `deadline_ns`, then every `period_ns` until cancelled.
+    pub fn arm_periodic(
+        &mut self,
+        deadline_ns: u64,
+        period_ns: u64,
+        callback: Callback,
+    ) -> Result<TimerId, &'static str> {
+        if period_ns == 0 {
+            return Err("timer period must be non-zero");
+        }
+        Ok(self.insert(deadline_ns, Some(period_ns), callback))
+    }
+
+    /// Returns false if the timer already fired (one-shot) or was cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        self.timers.remove(&id).is_some()
+    }
+
+    /// Moves a pending timer to a new deadline.
+    pub fn rearm(&mut self, id: TimerId, deadline_ns: u64) -> bool {
+        let Some(timer) = self.timers.get_mut(&id) else {
+            return false;
+        };
+        timer.deadline_ns = deadline_ns;
+        timer.generation += 1;
+        if timer.callback.is_some() {
+            self.heap.push(Reverse((deadline_ns, id, timer.generation)));
+        }
+        true
+    }
+
+    pub fn is_pending(&self, id: TimerId) -> bool {
+        self.timers.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Earliest pending deadline, for programming the next interrupt.
+    pub fn next_deadline(&mut self) -> Option<u64> {
+        self.drop_stale();
+        self.heap.peek().map(|Reverse((deadline, _, _))| *deadline)
+    }
+
+    /// Takes the earliest timer due at `now_ns`, if any.
+    pub fn pop_expired(&mut self, now_ns: u64) -> Option<Expired> {
+        if self.next_deadline()? > now_ns {
+            return None;
+        }
+        let Reverse((deadline_ns, id, _)) = self.heap.pop()?;
+        let timer = self.timers.get_mut(&id)?;
+        let callback = timer.callback.take()?;
+        if timer.period_ns.is_none() {
+            self.timers.remove(&id);
+        }
+        Some(Expired {
+            id,
+            deadline_ns,
+            callback,
+        })
+    }
+
+    /// Requeues a periodic timer after its callback ran. Periods missed
+    /// while the callback was late are skipped rather than replayed.
+    pub fn complete(&mut self, expired: Expired, now_ns: u64) {
+        let Some(timer) = self.timers.get_mut(&expired.id) else {
+            return;
+        };
+        let Some(period) = timer.period_ns else {
+            return;
+        };
+        // A rearm while the callback ran already picked the next deadline.
+        if timer.deadline_ns == expired.deadline_ns {
+            let behind = now_ns.saturating_sub(timer.deadline_ns) / period;
+            timer.deadline_ns += (behind + 1) * period;
+        }
+        timer.callback = Some(expired.callback);
+        timer.generation += 1;
+        self.heap
+            .push(Reverse((timer.deadline_ns, expired.id, timer.generation)));
+    }
+
+    /// Runs every timer due at `now_ns` in deadline order; returns how many
+    /// fired. Callbacks must not touch this queue; code that needs them to
+    /// should use `pop_expired` and `complete` with the queue unlocked.
+    pub fn run_expired(&mut self, now_ns: u64) -> usize {
+        let mut fired = 0;
+        while let Some(mut expired) = self.pop_expired(now_ns) {
+            expired.run();
+            self.complete(expired, now_ns);
+            fired += 1;
+        }
+        fired
+    }
+
+    fn insert(&mut self, deadline_ns: u64, period_ns: Option<u64>, callback: Callback) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.timers.insert(
+            id,
+            Timer {
+                deadline_ns,
+                period_ns,
+                callback: Some(callback),
+                generation: 0,
+            },
+        );
+        self.heap.push(Reverse((deadline_ns, id, 0)));
+        id
+    }
+
+    fn drop_stale(&mut self) {
+        while let Some(Reverse((_, id, generation))) = self.heap.peek() {
+            let live = self
+                .timers
+                .get(id)
+                .is_some_and(|t| t.generation == *generation && t.callback.is_some());
+            if live {
+                break;
+            }
+            self.heap.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::sync::SpinLock;
+
+    fn recorder() -> (Arc<SpinLock<Vec<u32>>>, impl Fn(u32) -> Callback) {
+        let log = Arc::new(SpinLock::new(Vec::new()));
+        let make = {
+            let log = log.clone();
+            move |tag: u32| {
+                let log = log.clone();
+                Box::new(move || log.lock().push(tag)) as Callback
+            }
+        };
+        (log, make)
+    }
+
+    #[test]
+    fn fires_in_deadline_order_and_only_when_due() {
+        let (log, make) = recorder();
+        let mut queue = TimerQueue::new();
+        queue.arm(300, make(3));
+        queue.arm(100, make(1));
+        queue.arm(200, make(2));
+
+        assert_eq!(queue.next_deadline(), Some(100));
+        assert_eq!(queue.run_expired(99), 0);
+        assert_eq!(queue.run_expired(250), 2);
+        assert_eq!(*log.lock(), [1, 2]);
+        assert_eq!(queue.next_deadline(), Some(300));
+    }
+
+    #[test]
+    fn cancel_and_rearm_skip_stale_entries() {
+        let (log, make) = recorder();
+        let mut queue = TimerQueue::new();
+        let early = queue.arm(100, make(1));
+        let moved = queue.arm(150, make(2));
+
+        assert!(queue.cancel(early));
+        assert!(!queue.cancel(early));
+        assert!(queue.rearm(moved, 500));
+        assert_eq!(queue.next_deadline(), Some(500));
+        assert_eq!(queue.run_expired(400), 0);
+        assert_eq!(queue.run_expired(500), 1);
+        assert_eq!(*log.lock(), [2]);
+        assert!(!queue.is_pending(moved));
+        assert!(!queue.rearm(moved, 600));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn periodic_timers_requeue_and_skip_missed_periods() {
+        let fired = Arc::new(AtomicUsize::new(0));
+        let counter = fired.clone();
+        let mut queue = TimerQueue::new();
+        let id = queue
+            .arm_periodic(
+                100,
+                50,
+                Box::new(move || {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(queue.run_expired(100), 1);
+        assert_eq!(queue.next_deadline(), Some(150));
+        // Late by three periods: one callback, next deadline in the future.
+        assert_eq!(queue.run_expired(320), 1);
+        assert_eq!(queue.next_deadline(), Some(350));
+        assert_eq!(fired.load(Ordering::Relaxed), 2);
+
+        assert!(queue.cancel(id));
+        assert_eq!(queue.next_deadline(), None);
+        assert!(queue.arm_periodic(0, 0, Box::new(|| ())).is_err());
+    }
+
+    #[test]
+    fn popped_timer_can_be_rearmed_before_completion() {
+        let (_, make) = recorder();
+        let mut queue = TimerQueue::new();
+        let id = queue.arm_periodic(10, 10, make(0)).unwrap();
+
+        let expired = queue.pop_expired(10).unwrap();
+        assert_eq!(queue.next_deadline(), None);
+        assert!(queue.rearm(id, 1000));
+        queue.complete(expired, 10);
+        assert_eq!(queue.next_deadline(), Some(1000));
+        assert_eq!(queue.len(), 1);
+    }
+}
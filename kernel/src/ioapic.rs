@@ -0,0 +1,289 @@
+const MADT_ENTRIES_OFFSET: usize = 44;
+const MADT_IO_APIC: u8 = 1;
+const MADT_SOURCE_OVERRIDE: u8 = 2;
+
+pub const MAX_IO_APICS: usize = 4;
+/// ISA IRQs 0..16 are the only bus MADT overrides can name.
+const MAX_OVERRIDES: usize = 16;
+
+/// Register select and data window, relative to an IOAPIC's MMIO base.
+pub const IOREGSEL: usize = 0x00;
+pub const IOWIN: usize = 0x10;
+pub const REG_ID: u32 = 0x00;
+pub const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_BASE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicDescriptor {
+    pub id: u8,
+    pub address: u32,
+    /// First global system interrupt (GSI) wired to pin 0.
+    pub gsi_base: u32,
+}
+
+/// Where an ISA IRQ actually arrives, after MADT source overrides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsaRoute {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: Trigger,
+}
+
+impl IsaRoute {
+    /// ISA interrupts are edge-triggered and active high unless overridden.
+    const fn identity(irq: u8) -> Self {
+        Self {
+            gsi: irq as u32,
+            polarity: Polarity::ActiveHigh,
+            trigger: Trigger::Edge,
+        }
+    }
+}
+
+/// IOAPICs and ISA interrupt source overrides from the ACPI MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqRouting {
+    io_apics: [Option<IoApicDescriptor>; MAX_IO_APICS],
+    /// Indexed by ISA IRQ.
+    overrides: [Option<IsaRoute>; MAX_OVERRIDES],
+}
+
+impl IrqRouting {
+    /// Parses the MADT entries. Only the signature is checked here; callers
+    /// run `CpuList::from_madt` on the same table first for its length and
+    /// checksum.
+    pub fn from_madt(table: &[u8]) -> Result<Self, &'static str> {
+        if table.len() < MADT_ENTRIES_OFFSET || &table[..4] != b"APIC" {
+            return Err("not a MADT");
+        }
+        let mut routing = Self {
+            io_apics: [None; MAX_IO_APICS],
+            overrides: [None; MAX_OVERRIDES],
+        };
+
+        let mut offset = MADT_ENTRIES_OFFSET;
+        while offset + 2 <= table.len() {
+            let kind = table[offset];
+            let len = table[offset + 1] as usize;
+            if len < 2 || offset + len > table.len() {
+                return Err("truncated MADT entry");
+            }
+            let entry = &table[offset..offset + len];
+
+            match kind {
+                MADT_IO_APIC if len >= 12 => {
+                    let io_apic = IoApicDescriptor {
+                        id: entry[2],
+                        address: read_u32(entry, 4),
+                        gsi_base: read_u32(entry, 8),
+                    };
+                    if let Some(slot) = routing.io_apics.iter_mut().find(|s| s.is_none()) {
+                        *slot = Some(io_apic);
+                    }
+                }
+                // Bus 0 is ISA, the only bus source overrides are defined for.
+                MADT_SOURCE_OVERRIDE if len >= 10 && entry[2] == 0 => {
+                    let irq = entry[3];
+                    let flags = u16::from_le_bytes([entry[8], entry[9]]);
+                    if let Some(slot) = routing.overrides.get_mut(irq as usize) {
+                        *slot = Some(route_with_flags(irq, read_u32(entry, 4), flags));
+                    }
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+
+        if routing.io_apics.iter().all(Option::is_none) {
+            return Err("MADT lists no IOAPIC");
+        }
+        Ok(routing)
+    }
+
+    pub fn io_apics(&self) -> impl Iterator<Item = &IoApicDescriptor> {
+        self.io_apics.iter().flatten()
+    }
+
+    pub fn isa_route(&self, irq: u8) -> IsaRoute {
+        self.overrides
+            .get(irq as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(IsaRoute::identity(irq))
+    }
+
+    /// The IOAPIC serving `gsi` (the one with the highest base not above
+    /// it) and the pin on that IOAPIC.
+    pub fn pin_for(&self, gsi: u32) -> Option<(IoApicDescriptor, u8)> {
+        let io_apic = self
+            .io_apics()
+            .filter(|io_apic| io_apic.gsi_base <= gsi)
+            .max_by_key(|io_apic| io_apic.gsi_base)?;
+        u8::try_from(gsi - io_apic.gsi_base)
+            .ok()
+            .map(|pin| (*io_apic, pin))
+    }
+}
+
+/// MPS INTI flags: bits 0-1 polarity, bits 2-3 trigger mode; 0 means
+/// "conforms to the bus", which for ISA is active high, edge.
+fn route_with_flags(irq: u8, gsi: u32, flags: u16) -> IsaRoute {
+    let default = IsaRoute::identity(irq);
+    IsaRoute {
+        gsi,
+        polarity: match flags & 0b11 {
+            0b11 => Polarity::ActiveLow,
+            0b01 => Polarity::ActiveHigh,
+            _ => default.polarity,
+        },
+        trigger: match (flags >> 2) & 0b11 {
+            0b11 => Trigger::Level,
+            0b01 => Trigger::Edge,
+            _ => default.trigger,
+        },
+    }
+}
+
+/// IOREGSEL index of the low half of `pin`'s redirection entry; the high
+/// half follows it.
+pub fn redirection_register(pin: u8) -> u32 {
+    REG_REDIRECTION_BASE + 2 * pin as u32
+}
+
+/// Number of pins, from the IOAPIC version register.
+pub fn pin_count(version: u32) -> u8 {
+    ((version >> 16) & 0xFF) as u8 + 1
+}
+
+/// Fixed delivery in physical destination mode to one local APIC.
+pub fn redirection_entry(vector: u8, destination: u8, route: IsaRoute, masked: bool) -> u64 {
+    let mut entry = vector as u64 | (destination as u64) << 56;
+    if route.polarity == Polarity::ActiveLow {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if route.trigger == Trigger::Level {
+        entry |= REDIRECTION_LEVEL;
+    }
+    if masked {
+        entry |= REDIRECTION_MASKED;
+    }
+    entry
+}
+
+pub fn set_masked(entry: u64, masked: bool) -> u64 {
+    if masked {
+        entry | REDIRECTION_MASKED
+    } else {
+        entry & !REDIRECTION_MASKED
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn madt(entries: &[&[u8]]) -> ([u8; 128], usize) {
+        let mut table = [0u8; 128];
+        table[..4].copy_from_slice(b"APIC");
+        let mut len = MADT_ENTRIES_OFFSET;
+        for entry in entries {
+            table[len..len + entry.len()].copy_from_slice(entry);
+            len += entry.len();
+        }
+        (table, len)
+    }
+
+    #[test]
+    fn overrides_remap_isa_irqs_with_their_polarity_and_trigger() {
+        let (table, len) = madt(&[
+            &[1, 12, 2, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0],
+            // IRQ0 -> GSI 2, conforming flags.
+            &[2, 10, 0, 0, 2, 0, 0, 0, 0, 0],
+            // IRQ9 (ACPI SCI) -> GSI 9, level, active low.
+            &[2, 10, 0, 9, 9, 0, 0, 0, 0x0F, 0],
+        ]);
+        let routing = IrqRouting::from_madt(&table[..len]).unwrap();
+
+        let io_apic = *routing.io_apics().next().unwrap();
+        assert_eq!(io_apic.address, 0xFEC0_0000);
+        assert_eq!(io_apic.id, 2);
+        assert_eq!(
+            routing.isa_route(0),
+            IsaRoute {
+                gsi: 2,
+                polarity: Polarity::ActiveHigh,
+                trigger: Trigger::Edge
+            }
+        );
+        assert_eq!(
+            routing.isa_route(9),
+            IsaRoute {
+                gsi: 9,
+                polarity: Polarity::ActiveLow,
+                trigger: Trigger::Level
+            }
+        );
+        assert_eq!(routing.isa_route(1), IsaRoute::identity(1));
+        assert_eq!(
+            IrqRouting::from_madt(&madt(&[]).0[..MADT_ENTRIES_OFFSET]),
+            Err("MADT lists no IOAPIC")
+        );
+    }
+
+    #[test]
+    fn gsis_map_to_the_covering_io_apic() {
+        let (table, len) = madt(&[
+            &[1, 12, 0, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0],
+            &[1, 12, 1, 0, 0x00, 0x10, 0xC0, 0xFE, 24, 0, 0, 0],
+        ]);
+        let routing = IrqRouting::from_madt(&table[..len]).unwrap();
+
+        let (io_apic, pin) = routing.pin_for(30).unwrap();
+        assert_eq!((io_apic.id, pin), (1, 6));
+        let (io_apic, pin) = routing.pin_for(4).unwrap();
+        assert_eq!((io_apic.id, pin), (0, 4));
+    }
+
+    #[test]
+    fn redirection_entries_encode_route_and_mask() {
+        let level_low = IsaRoute {
+            gsi: 9,
+            polarity: Polarity::ActiveLow,
+            trigger: Trigger::Level,
+        };
+        let entry = redirection_entry(0x29, 3, level_low, true);
+        assert_eq!(entry, 0x0300_0000_0001_A029);
+        assert_eq!(set_masked(entry, false), 0x0300_0000_0000_A029);
+        assert_eq!(
+            redirection_entry(0x20, 0, IsaRoute::identity(0), false),
+            0x20
+        );
+        assert_eq!(redirection_register(2), 0x14);
+        assert_eq!(pin_count(0x0017_0011), 24);
+    }
+}
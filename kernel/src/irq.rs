@@ -0,0 +1,423 @@
+/// Legacy ISA lines behind the 8259 pair.
+pub const IRQ_LINES: usize = 16;
+/// Handlers one line can carry. Fixed so registering works before the
+/// heap is up and dispatch never allocates.
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// Whether a handler recognised the interrupt as coming from its device.
+/// Handlers on a shared line must return `NotMine` for the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    NotMine,
+    /// Handled, but the device is serviced elsewhere (a userspace driver):
+    /// leave the line masked until that is done.
+    Deferred,
+}
+
+/// Runs in interrupt context with the line's `ctx`. Must not register or
+/// unregister handlers.
+pub type Handler = fn(ctx: usize) -> IrqReturn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HandlerId {
+    line: u8,
+    serial: u32,
+}
+
+impl HandlerId {
+    pub fn line(self) -> u8 {
+        self.line
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IrqStats {
+    /// Interrupts raised on the line.
+    pub count: u64,
+    /// Interrupts no handler claimed.
+    pub unhandled: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Action {
+    serial: u32,
+    handler: Handler,
+    ctx: usize,
+}
+
+struct Line {
+    actions: [Option<Action>; MAX_SHARED_HANDLERS],
+    stats: IrqStats,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            actions: [None; MAX_SHARED_HANDLERS],
+            stats: IrqStats {
+                count: 0,
+                unhandled: 0,
+            },
+        }
+    }
+}
+
+/// Handlers hooked onto each interrupt line. A line may carry several
+/// handlers, and every one of them runs on each interrupt.
+pub struct IrqTable {
+    lines: [Line; IRQ_LINES],
+    next_serial: u32,
+}
+
+impl Default for IrqTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IrqTable {
+    pub const fn new() -> Self {
+        Self {
+            lines: [const { Line::new() }; IRQ_LINES],
+            next_serial: 0,
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        line: u8,
+        handler: Handler,
+        ctx: usize,
+    ) -> Result<HandlerId, &'static str> {
+        let slot = self
+            .lines
+            .get_mut(line as usize)
+            .ok_or("IRQ line out of range")?
+            .actions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or("IRQ line has no free handler slot")?;
+        let serial = self.next_serial;
+        self.next_serial += 1;
+        *slot = Some(Action {
+            serial,
+            handler,
+            ctx,
+        });
+        Ok(HandlerId { line, serial })
+    }
+
+    /// Returns false if `id` was not registered.
+    pub fn unregister(&mut self, id: HandlerId) -> bool {
+        let Some(line) = self.lines.get_mut(id.line as usize) else {
+            return false;
+        };
+        let Some(slot) = line
+            .actions
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|action| action.serial == id.serial))
+        else {
+            return false;
+        };
+        *slot = None;
+        true
+    }
+
+    /// Number of handlers on `line`; the line only needs unmasking while
+    /// this is non-zero.
+    pub fn handlers(&self, line: u8) -> usize {
+        self.lines
+            .get(line as usize)
+            .map_or(0, |line| line.actions.iter().flatten().count())
+    }
+
+    /// Runs every handler on `line`. The result is `Deferred` if any
+    /// handler deferred, so the caller masks the line, else `Handled` if
+    /// any claimed the interrupt.
+    pub fn dispatch(&mut self, line: u8) -> IrqReturn {
+        let Some(line) = self.lines.get_mut(line as usize) else {
+            return IrqReturn::NotMine;
+        };
+        line.stats.count += 1;
+        let mut outcome = IrqReturn::NotMine;
+        for action in line.actions.iter().flatten() {
+            match (action.handler)(action.ctx) {
+                IrqReturn::Deferred => outcome = IrqReturn::Deferred,
+                IrqReturn::Handled if outcome == IrqReturn::NotMine => outcome = IrqReturn::Handled,
+                _ => {}
+            }
+        }
+        if outcome == IrqReturn::NotMine {
+            line.stats.unhandled += 1;
+        }
+        outcome
+    }
+
+    pub fn stats(&self, line: u8) -> IrqStats {
+        self.lines
+            .get(line as usize)
+            .map_or_else(IrqStats::default, |line| line.stats)
+    }
+}
+
+/// A line handed to a userspace driver server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserBinding {
+    pub pid: u32,
+    /// Notification bits the server receives for this line.
+    pub bits: u64,
+    /// An interrupt was forwarded and the line stays masked until the
+    /// server acknowledges it.
+    pub in_flight: bool,
+}
+
+/// Which lines are bound to which driver servers. The kernel masks a bound
+/// line when it fires, notifies the server, and unmasks it on `ack`.
+pub struct UserIrqTable {
+    bindings: [Option<UserBinding>; IRQ_LINES],
+}
+
+impl Default for UserIrqTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UserIrqTable {
+    pub const fn new() -> Self {
+        Self {
+            bindings: [None; IRQ_LINES],
+        }
+    }
+
+    /// `driver_io` is whether `pid` holds the `DriverIo` capability, and
+    /// `kernel_handlers` how many in-kernel handlers the line has. A line
+    /// is only handed over whole: the forwarder defers every interrupt, so
+    /// it cannot tell the server's device from a kernel driver's.
+    pub fn bind(
+        &mut self,
+        line: u8,
+        pid: u32,
+        bits: u64,
+        driver_io: bool,
+        kernel_handlers: usize,
+    ) -> Result<(), &'static str> {
+        if !driver_io {
+            return Err("binding an IRQ needs the DriverIo capability");
+        }
+        if kernel_handlers != 0 {
+            return Err("IRQ line is used by the kernel");
+        }
+        if bits == 0 {
+            return Err("IRQ notification bits must be non-zero");
+        }
+        let slot = self
+            .bindings
+            .get_mut(line as usize)
+            .ok_or("IRQ line out of range")?;
+        if slot.is_some() {
+            return Err("IRQ line already bound");
+        }
+        *slot = Some(UserBinding {
+            pid,
+            bits,
+            in_flight: false,
+        });
+        Ok(())
+    }
+
+    pub fn binding(&self, line: u8) -> Option<UserBinding> {
+        self.bindings.get(line as usize).copied().flatten()
+    }
+
+    /// The line fired: returns who to notify with which bits, and marks the
+    /// interrupt in flight.
+    pub fn on_interrupt(&mut self, line: u8) -> Option<(u32, u64)> {
+        let binding = self.bindings.get_mut(line as usize)?.as_mut()?;
+        binding.in_flight = true;
+        Some((binding.pid, binding.bits))
+    }
+
+    /// The server is done with the interrupt; the caller unmasks the line.
+    pub fn ack(&mut self, line: u8, pid: u32) -> Result<(), &'static str> {
+        let binding = self
+            .bindings
+            .get_mut(line as usize)
+            .and_then(Option::as_mut)
+            .filter(|binding| binding.pid == pid)
+            .ok_or("IRQ line is not bound to the caller")?;
+        if !binding.in_flight {
+            return Err("no interrupt to acknowledge");
+        }
+        binding.in_flight = false;
+        Ok(())
+    }
+
+    pub fn unbind(&mut self, line: u8, pid: u32) -> Result<UserBinding, &'static str> {
+        self.bindings
+            .get_mut(line as usize)
+            .filter(|slot| slot.is_some_and(|binding| binding.pid == pid))
+            .and_then(Option::take)
+            .ok_or("IRQ line is not bound to the caller")
+    }
+
+    /// Drops every binding of an exiting process; returns the freed lines
+    /// as a bitmask.
+    pub fn release(&mut self, pid: u32) -> u16 {
+        let mut freed = 0;
+        for (line, slot) in self.bindings.iter_mut().enumerate() {
+            if slot.is_some_and(|binding| binding.pid == pid) {
+                *slot = None;
+                freed |= 1 << line;
+            }
+        }
+        freed
+    }
+}
+
+/// How to acknowledge an interrupt that arrived through the 8259 pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicAck {
+    /// A real interrupt: EOI the PIC(s) it went through after handling.
+    Genuine,
+    /// IRQ 7 or 15 with its in-service bit clear: a glitch on the line. No
+    /// handler runs; only the primary PIC is acknowledged, and only when the
+    /// secondary raised it through the cascade.
+    Spurious { eoi_primary: bool },
+}
+
+/// Classifies `irq` using the PICs' combined in-service register (primary
+/// in the low byte).
+pub fn pic_ack(irq: u8, in_service: u16) -> PicAck {
+    if (irq == 7 || irq == 15) && in_service & (1 << irq) == 0 {
+        PicAck::Spurious {
+            eoi_primary: irq == 15,
+        }
+    } else {
+        PicAck::Genuine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    fn count(ctx: usize) -> IrqReturn {
+        let counter = unsafe { &*(ctx as *const AtomicUsize) };
+        counter.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    }
+
+    fn not_mine(ctx: usize) -> IrqReturn {
+        let counter = unsafe { &*(ctx as *const AtomicUsize) };
+        counter.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::NotMine
+    }
+
+    fn ctx(counter: &'static AtomicUsize) -> usize {
+        counter as *const AtomicUsize as usize
+    }
+
+    #[test]
+    fn handlers_run_with_their_context_and_are_counted() {
+        static KEYBOARD: AtomicUsize = AtomicUsize::new(0);
+        let mut table = IrqTable::new();
+        let id = table.register(1, count, ctx(&KEYBOARD)).unwrap();
+        assert_eq!(id.line(), 1);
+        assert_eq!(table.handlers(1), 1);
+
+        assert_eq!(table.dispatch(1), IrqReturn::Handled);
+        assert_eq!(table.dispatch(1), IrqReturn::Handled);
+        assert_eq!(KEYBOARD.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            table.stats(1),
+            IrqStats {
+                count: 2,
+                unhandled: 0
+            }
+        );
+        assert!(table.register(16, count, 0).is_err());
+        for _ in 1..MAX_SHARED_HANDLERS {
+            table.register(1, count, ctx(&KEYBOARD)).unwrap();
+        }
+        assert!(table.register(1, count, 0).is_err());
+    }
+
+    #[test]
+    fn shared_lines_run_every_handler() {
+        static NIC: AtomicUsize = AtomicUsize::new(0);
+        static DISK: AtomicUsize = AtomicUsize::new(0);
+        let mut table = IrqTable::new();
+        let nic = table.register(11, not_mine, ctx(&NIC)).unwrap();
+        let disk = table.register(11, count, ctx(&DISK)).unwrap();
+
+        assert_eq!(table.dispatch(11), IrqReturn::Handled);
+        assert_eq!(NIC.load(Ordering::Relaxed), 1);
+        assert_eq!(DISK.load(Ordering::Relaxed), 1);
+
+        assert!(table.unregister(disk));
+        assert!(!table.unregister(disk));
+        assert_eq!(table.dispatch(11), IrqReturn::NotMine);
+        assert_eq!(table.stats(11).unhandled, 1);
+
+        assert!(table.unregister(nic));
+        assert_eq!(table.handlers(11), 0);
+        assert_eq!(table.dispatch(11), IrqReturn::NotMine);
+        assert_eq!(table.stats(11).count, 3);
+    }
+
+    #[test]
+    fn a_deferring_handler_keeps_the_whole_line_masked() {
+        static TIMER: AtomicUsize = AtomicUsize::new(0);
+        fn defer(_: usize) -> IrqReturn {
+            IrqReturn::Deferred
+        }
+        let mut table = IrqTable::new();
+        table.register(5, defer, 0).unwrap();
+        table.register(5, count, ctx(&TIMER)).unwrap();
+        assert_eq!(table.dispatch(5), IrqReturn::Deferred);
+        assert_eq!(TIMER.load(Ordering::Relaxed), 1);
+        assert_eq!(table.stats(5).unhandled, 0);
+    }
+
+    #[test]
+    fn user_bindings_need_driver_io_and_are_acked_by_their_owner() {
+        let mut table = UserIrqTable::new();
+        assert!(table.bind(1, 7, 0b1, false, 0).is_err());
+        assert!(table.bind(1, 7, 0, true, 0).is_err());
+        table.bind(1, 7, 0b10, true, 0).unwrap();
+        assert_eq!(
+            table.bind(1, 8, 0b1, true, 0),
+            Err("IRQ line already bound")
+        );
+        assert_eq!(
+            table.bind(2, 7, 0b1, true, 1),
+            Err("IRQ line is used by the kernel")
+        );
+
+        assert_eq!(table.ack(1, 7), Err("no interrupt to acknowledge"));
+        assert_eq!(table.on_interrupt(1), Some((7, 0b10)));
+        assert!(table.binding(1).unwrap().in_flight);
+        assert!(table.ack(1, 8).is_err());
+        assert_eq!(table.ack(1, 7), Ok(()));
+        assert_eq!(table.on_interrupt(2), None);
+
+        table.bind(11, 7, 0b100, true, 0).unwrap();
+        table.bind(12, 9, 0b1, true, 0).unwrap();
+        assert!(table.unbind(12, 7).is_err());
+        assert_eq!(table.release(7), 1 << 1 | 1 << 11);
+        assert_eq!(table.binding(1), None);
+        assert_eq!(table.unbind(12, 9).unwrap().pid, 9);
+    }
+
+    #[test]
+    fn spurious_pic_irqs_are_recognised_by_their_in_service_bit() {
+        assert_eq!(pic_ack(7, 0), PicAck::Spurious { eoi_primary: false });
+        assert_eq!(pic_ack(15, 1 << 2), PicAck::Spurious { eoi_primary: true });
+        assert_eq!(pic_ack(7, 1 << 7), PicAck::Genuine);
+        assert_eq!(pic_ack(15, 1 << 15 | 1 << 2), PicAck::Genuine);
+        assert_eq!(pic_ack(1, 0), PicAck::Genuine);
+    }
+}
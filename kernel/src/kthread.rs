@@ -0,0 +1,406 @@
+use alloc::alloc::{alloc, dealloc};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::scheduler::{self, Task, MAX_TASKS};
+use crate::sync::SpinLock;
+
+pub const KTHREAD_STACK_SIZE: usize = 16 * 1024;
+/// Page below each stack. With a guard hook installed it is unmapped, so an
+/// overflow faults on the spot; without one it is filled with a canary
+/// that `dump` checks.
+pub const STACK_GUARD_SIZE: usize = 4096;
+
+const GUARD_BYTE: u8 = 0xa5;
+
+const STATE_READY: u8 = 0;
+const STATE_RUNNING: u8 = 1;
+/// The closure returned; the thread waits on its stack to be switched away.
+const STATE_EXITING: u8 = 2;
+const STATE_EXITED: u8 = 3;
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+/// Maps (`present`) or unmaps the 4 KiB kernel page at `page`. The
+/// platform's paging code installs one with `set_guard_hook`.
+pub type GuardHook = fn(page: u64, present: bool) -> Result<(), &'static str>;
+
+static GUARD_HOOK: SpinLock<Option<GuardHook>> = SpinLock::new(None);
+
+/// Every kernel thread that has not been joined or reaped yet. Holding the
+/// `Arc` here keeps the stack alive while the thread may still run on it.
+static THREADS: SpinLock<[Option<Arc<Kthread>>; MAX_TASKS]> =
+    SpinLock::new([const { None }; MAX_TASKS]);
+
+struct KernelStack {
+    base: NonNull<u8>,
+    /// The guard page is unmapped, not a canary.
+    unmapped: bool,
+}
+
+// Safety: the stack is plain memory; only its owning thread writes to it.
+unsafe impl Send for KernelStack {}
+unsafe impl Sync for KernelStack {}
+
+impl KernelStack {
+    const LAYOUT: Layout =
+        match Layout::from_size_align(STACK_GUARD_SIZE + KTHREAD_STACK_SIZE, 4096) {
+            Ok(layout) => layout,
+            Err(_) => panic!("invalid kernel stack layout"),
+        };
+
+    fn new() -> Option<Self> {
+        let base = NonNull::new(unsafe { alloc(Self::LAYOUT) })?;
+        unsafe { base.as_ptr().write_bytes(GUARD_BYTE, STACK_GUARD_SIZE) };
+        let unmapped =
+            (*GUARD_HOOK.lock()).is_some_and(|hook| hook(base.as_ptr() as u64, false).is_ok());
+        Some(Self { base, unmapped })
+    }
+
+    fn bottom(&self) -> u64 {
+        self.base.as_ptr() as u64 + STACK_GUARD_SIZE as u64
+    }
+
+    fn top(&self) -> u64 {
+        self.bottom() + KTHREAD_STACK_SIZE as u64
+    }
+
+    fn guard_state(&self) -> &'static str {
+        if self.unmapped {
+            return "unmapped";
+        }
+        let guard = unsafe { core::slice::from_raw_parts(self.base.as_ptr(), STACK_GUARD_SIZE) };
+        if guard.iter().all(|&b| b == GUARD_BYTE) {
+            "ok"
+        } else {
+            "CLOBBERED"
+        }
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        if self.unmapped {
+            let hook = *GUARD_HOOK.lock();
+            // Leak the stack rather than hand the allocator an unmapped page.
+            if !hook.is_some_and(|hook| hook(self.base.as_ptr() as u64, true).is_ok()) {
+                return;
+            }
+        }
+        unsafe { dealloc(self.base.as_ptr(), Self::LAYOUT) };
+    }
+}
+
+struct Kthread {
+    task_id: u64,
+    name: &'static str,
+    state: AtomicU8,
+    entry: SpinLock<Option<Entry>>,
+    stack: KernelStack,
+}
+
+impl Kthread {
+    fn state_str(&self) -> &'static str {
+        match self.state.load(Ordering::Acquire) {
+            STATE_READY => "ready",
+            STATE_RUNNING => "running",
+            STATE_EXITING => "exiting",
+            _ => "exited",
+        }
+    }
+}
+
+/// Slot for the closure's return value. Written once by the thread before
+/// it exits, read once by `join` after seeing it marked exited.
+struct Packet<T> {
+    value: UnsafeCell<Option<T>>,
+}
+
+// Safety: see the access protocol above; `T` crosses threads exactly once.
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+pub struct JoinHandle<T> {
+    thread: Arc<Kthread>,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn task_id(&self) -> u64 {
+        self.thread.task_id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.state.load(Ordering::Acquire) == STATE_EXITED
+    }
+
+    /// Waits for the thread to exit and returns what its closure returned.
+    pub fn join(self) -> T {
+        while !self.is_finished() {
+            core::hint::spin_loop();
+        }
+        unregister(self.thread.task_id);
+        unsafe { (*self.packet.value.get()).take() }.expect("kthread exited without a result")
+    }
+}
+
+/// Unmaps the guard page of every kernel stack allocated from now on
+/// through `hook`, which also maps it back before the stack is freed.
+pub fn set_guard_hook(hook: GuardHook) {
+    *GUARD_HOOK.lock() = Some(hook);
+}
+
+/// Starts `f` on a fresh kernel thread queued on the least loaded CPU.
+///
+/// Dropping the returned handle detaches the thread; its stack is then
+/// freed by `reap` once it exits.
+pub fn spawn<F, T>(name: &'static str, f: F) -> Result<JoinHandle<T>, &'static str>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let stack = KernelStack::new().ok_or("out of memory for kthread stack")?;
+    let packet = Arc::new(Packet {
+        value: UnsafeCell::new(None),
+    });
+    let result = packet.clone();
+    let entry: Entry = Box::new(move || {
+        let value = f();
+        unsafe { *result.value.get() = Some(value) };
+    });
+
+    // The thread records the task id and the task needs the thread's address,
+    // so `rdi` is filled in once both exist.
+    let entry_ip = kthread_entry as extern "C" fn(*const Kthread) -> ! as usize as u64;
+    let mut task = Task::kernel_thread(entry_ip, 0, stack.top());
+    let thread = Arc::new(Kthread {
+        task_id: task.id,
+        name,
+        state: AtomicU8::new(STATE_READY),
+        entry: SpinLock::new(Some(entry)),
+        stack,
+    });
+    task.registers.rdi = Arc::as_ptr(&thread) as u64;
+
+    register(thread.clone())?;
+    if let Err(err) = scheduler::spawn(task) {
+        unregister(thread.task_id);
+        return Err(err);
+    }
+
+    Ok(JoinHandle { thread, packet })
+}
+
+/// Frees the stacks of detached threads that have exited.
+pub fn reap() -> usize {
+    let mut threads = THREADS.lock();
+    let mut reaped = 0;
+    for slot in threads.iter_mut() {
+        let exited = slot
+            .as_ref()
+            .is_some_and(|t| t.state.load(Ordering::Acquire) == STATE_EXITED);
+        if exited {
+            *slot = None;
+            reaped += 1;
+        }
+    }
+    reaped
+}
+
+/// Name of the kernel thread backing scheduler task `task_id`, if any.
+pub fn name_of(task_id: u64) -> Option<&'static str> {
+    THREADS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|t| t.task_id == task_id)
+        .map(|t| t.name)
+}
+
+/// Lists every live kernel thread with its state and stack.
+pub fn dump(out: &mut impl fmt::Write) -> fmt::Result {
+    for thread in THREADS.lock().iter().flatten() {
+        writeln!(
+            out,
+            "kthread task={} name={} state={} stack={:#x}..{:#x} guard={}",
+            thread.task_id,
+            thread.name,
+            thread.state_str(),
+            thread.stack.bottom(),
+            thread.stack.top(),
+            thread.stack.guard_state()
+        )?;
+    }
+    Ok(())
+}
+
+fn register(thread: Arc<Kthread>) -> Result<(), &'static str> {
+    let mut threads = THREADS.lock();
+    let slot = threads
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or("kthread table full")?;
+    *slot = Some(thread);
+    Ok(())
+}
+
+fn unregister(task_id: u64) {
+    let mut threads = THREADS.lock();
+    if let Some(slot) = threads
+        .iter_mut()
+        .find(|slot| slot.as_ref().is_some_and(|t| t.task_id == task_id))
+    {
+        *slot = None;
+    }
+}
+
+/// Marks the thread of task `task_id` exited, letting `join` and `reap`
+/// free its stack. `scheduler::finish_switch` calls this once the CPU is
+/// off that stack.
+pub fn finished(task_id: u64) {
+    if let Some(thread) = THREADS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|t| t.task_id == task_id)
+    {
+        thread.state.store(STATE_EXITED, Ordering::Release);
+    }
+}
+
+/// Runs the thread's closure, then asks the scheduler to switch away from
+/// it for good.
+fn run(thread: &Kthread) {
+    thread.state.store(STATE_RUNNING, Ordering::Release);
+    let entry = thread.entry.lock().take();
+    if let Some(entry) = entry {
+        entry();
+    }
+    thread.state.store(STATE_EXITING, Ordering::Release);
+    let _ = scheduler::exit(thread.task_id);
+}
+
+/// First code a kernel thread executes, with its `Kthread` in `rdi`.
+///
+/// The thread stays queued, and its stack allocated, until the next tick
+/// switches away from it; only then is it marked exited.
+extern "C" fn kthread_entry(thread: *const Kthread) -> ! {
+    // Safety: `THREADS` keeps the thread alive until it is marked exited.
+    run(unsafe { &*thread });
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    static UNMAPPED: SpinLock<Vec<u64>> = SpinLock::new(Vec::new());
+
+    fn record_guard(page: u64, present: bool) -> Result<(), &'static str> {
+        let mut unmapped = UNMAPPED.lock();
+        if present {
+            unmapped.retain(|&p| p != page);
+        } else {
+            unmapped.push(page);
+        }
+        Ok(())
+    }
+
+    fn thread(task_id: u64) -> Arc<Kthread> {
+        THREADS
+            .lock()
+            .iter()
+            .flatten()
+            .find(|t| t.task_id == task_id)
+            .cloned()
+            .unwrap()
+    }
+
+    /// Runs the thread's closure, then plays the tick that switches away.
+    fn run_task(task_id: u64) {
+        let thread = thread(task_id);
+        run(&thread);
+        assert_eq!(thread.state_str(), "exiting");
+        let _ = scheduler::remove(task_id);
+        scheduler::finish_switch(&scheduler::ContextSwitch {
+            previous_task: task_id,
+            next_task: scheduler::IDLE_TASK,
+            previous_exited: true,
+        });
+    }
+
+    #[test]
+    fn spawned_thread_runs_closure_and_joins_with_its_result() {
+        set_guard_hook(record_guard);
+        let captured = 40;
+        let handle = spawn("answer", move || captured + 2).unwrap();
+        assert_eq!(name_of(handle.task_id()), Some("answer"));
+        assert!(!handle.is_finished());
+
+        run_task(handle.task_id());
+        assert!(handle.is_finished());
+        let task_id = handle.task_id();
+        assert_eq!(handle.join(), 42);
+        assert_eq!(name_of(task_id), None);
+        assert!(scheduler::remove(task_id).is_none());
+    }
+
+    #[test]
+    fn finished_thread_stays_unjoinable_until_switched_away() {
+        set_guard_hook(record_guard);
+        let handle = spawn("short-lived", || ()).unwrap();
+        let thread = thread(handle.task_id());
+        run(&thread);
+        assert!(!handle.is_finished());
+
+        let _ = scheduler::remove(handle.task_id());
+        finished(handle.task_id());
+        assert!(handle.is_finished());
+        handle.join();
+    }
+
+    #[test]
+    fn guard_page_is_unmapped_while_the_stack_lives() {
+        set_guard_hook(record_guard);
+        let handle = spawn("guarded", || ()).unwrap();
+        let guard = thread(handle.task_id()).stack.base.as_ptr() as u64;
+        assert!(UNMAPPED.lock().contains(&guard));
+
+        run_task(handle.task_id());
+        handle.join();
+        assert!(!UNMAPPED.lock().contains(&guard));
+    }
+
+    #[test]
+    fn dump_lists_kernel_threads_with_unmapped_guards() {
+        set_guard_hook(record_guard);
+        let handle = spawn("log-flusher", || ()).unwrap();
+        let mut out = String::new();
+        scheduler::dump(&mut out).unwrap();
+
+        let task_line = alloc::format!("task id={} ", handle.task_id());
+        assert!(out
+            .lines()
+            .any(|l| l.contains(&task_line) && l.ends_with("kthread=log-flusher")));
+        assert!(out
+            .lines()
+            .any(|l| l.contains("name=log-flusher state=ready") && l.ends_with("guard=unmapped")));
+
+        run_task(handle.task_id());
+        drop(handle);
+        assert!(reap() >= 1);
+        assert!(!THREADS
+            .lock()
+            .iter()
+            .flatten()
+            .any(|t| t.name == "log-flusher"));
+    }
+}
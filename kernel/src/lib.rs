@@ -1,5 +1,18 @@
 #![no_std]
 
+extern crate alloc;
+
+pub mod clock;
+pub mod exception;
+pub mod fd;
+pub mod hrtimer;
+pub mod ioapic;
+pub mod irq;
+pub mod kthread;
 pub mod memory;
+pub mod rtc;
 pub mod scheduler;
+pub mod smp;
+pub mod sync;
 pub mod timer;
+pub mod tlb;
@@ -4,11 +4,10 @@
 
 extern crate alloc;
 
-mod memory;
-
 use core::panic::PanicInfo;
-use memory::{
-    allocator, frame_allocator::FrameAllocator, page_fault, paging, PhysicalAddress, VirtualAddress,
+use kernel::memory::{
+    self, allocator, frame_allocator::FrameAllocator, page_fault, paging, PhysicalAddress,
+    VirtualAddress,
 };
 
 #[no_mangle]
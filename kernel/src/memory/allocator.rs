@@ -14,7 +14,11 @@ static LAST_ALLOC_ERROR_ALIGN: AtomicUsize = AtomicUsize::new(0);
 
 pub struct BumpAllocator;
 
-#[global_allocator]
+// Only bare-metal builds install the bump heap. Host unit tests and host
+// tools linking this crate keep the system allocator: they allocate freely
+// and would exhaust (or, after `init`, corrupt) the bump heap.
+#[cfg_attr(target_os = "none", global_allocator)]
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
 static GLOBAL_ALLOCATOR: BumpAllocator = BumpAllocator;
 
 pub fn init() {
@@ -38,10 +42,14 @@ pub fn last_alloc_error() -> (usize, usize) {
 unsafe impl GlobalAlloc for BumpAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let align_mask = layout.align().saturating_sub(1);
+        let base = core::ptr::addr_of_mut!(HEAP.0) as *mut u8;
+        // Align the address, not the offset: the heap itself is only
+        // guaranteed 16-byte alignment.
+        let misalignment = base as usize & align_mask;
 
         let mut current = NEXT.load(Ordering::Relaxed);
         loop {
-            let aligned = (current + align_mask) & !align_mask;
+            let aligned = ((current + misalignment + align_mask) & !align_mask) - misalignment;
             let next = match aligned.checked_add(layout.size()) {
                 Some(v) => v,
                 None => return null_mut(),
@@ -52,10 +60,7 @@ unsafe impl GlobalAlloc for BumpAllocator {
             }
 
             match NEXT.compare_exchange_weak(current, next, Ordering::SeqCst, Ordering::Relaxed) {
-                Ok(_) => {
-                    let base = core::ptr::addr_of_mut!(HEAP.0) as *mut u8;
-                    return base.add(aligned);
-                }
+                Ok(_) => return base.add(aligned),
                 Err(observed) => current = observed,
             }
         }
@@ -19,7 +19,7 @@ impl PageTableEntry {
         self.0 = (addr.as_u64() & 0x000f_ffff_ffff_f000) | flags;
     }
 
-    fn is_present(self) -> bool {
+    pub fn is_present(self) -> bool {
         (self.0 & PRESENT) != 0
     }
 }
@@ -41,6 +41,12 @@ pub struct PageTables {
     pd: PageTable,
 }
 
+impl Default for PageTables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PageTables {
     pub const fn new() -> Self {
         Self {
@@ -0,0 +1,278 @@
+use core::fmt;
+
+/// Status register B: hours are 24h when set, 12h with bit 7 as PM otherwise.
+pub const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// Status register B: values are binary when set, BCD otherwise.
+pub const STATUS_B_BINARY: u8 = 1 << 2;
+/// Status register A: an update cycle is in progress.
+pub const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+const HOUR_PM: u8 = 1 << 7;
+
+/// How many times `read_stable` re-reads before giving up.
+const MAX_READ_ATTEMPTS: usize = 16;
+
+/// CMOS time registers exactly as read, before format decoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RtcRaw {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: u8,
+    /// Century register, when the FADT names one.
+    pub century: Option<u8>,
+    pub status_b: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01T00:00:00Z.
+    pub fn unix_seconds(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        days as u64 * 86_400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn bcd_to_binary(value: u8) -> Result<u8, &'static str> {
+    let (high, low) = (value >> 4, value & 0x0F);
+    if high > 9 || low > 9 {
+        return Err("invalid BCD digit in RTC register");
+    }
+    Ok(high * 10 + low)
+}
+
+/// Decodes CMOS registers in any BCD/binary and 12/24-hour combination.
+/// Without a century register, two-digit years are taken as 20xx.
+pub fn decode(raw: RtcRaw) -> Result<DateTime, &'static str> {
+    let binary = raw.status_b & STATUS_B_BINARY != 0;
+    let field = |value: u8| {
+        if binary {
+            Ok(value)
+        } else {
+            bcd_to_binary(value)
+        }
+    };
+
+    let pm = raw.hours & HOUR_PM != 0;
+    let mut hour = field(raw.hours & !HOUR_PM)?;
+    if raw.status_b & STATUS_B_24_HOUR == 0 {
+        if !(1..=12).contains(&hour) {
+            return Err("RTC hour out of range");
+        }
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (h, true) => h + 12,
+            (h, false) => h,
+        };
+    }
+
+    let century = match raw.century {
+        Some(value) => field(value)? as u16,
+        None => 20,
+    };
+    let time = DateTime {
+        year: century * 100 + field(raw.year)? as u16,
+        month: field(raw.month)?,
+        day: field(raw.day)?,
+        hour,
+        minute: field(raw.minutes)?,
+        second: field(raw.seconds)?,
+    };
+
+    let valid = (1..=12).contains(&time.month)
+        && (1..=days_in_month(time.year, time.month)).contains(&time.day)
+        && time.hour < 24
+        && time.minute < 60
+        && time.second < 60;
+    if valid {
+        Ok(time)
+    } else {
+        Err("RTC date out of range")
+    }
+}
+
+/// Reads the RTC outside of update cycles until two reads in a row agree,
+/// so no field is torn across a second boundary.
+pub fn read_stable(
+    mut update_in_progress: impl FnMut() -> bool,
+    mut read: impl FnMut() -> RtcRaw,
+) -> Result<RtcRaw, &'static str> {
+    let mut settled = || {
+        while update_in_progress() {
+            core::hint::spin_loop();
+        }
+        read()
+    };
+
+    let mut previous = settled();
+    for _ in 0..MAX_READ_ATTEMPTS {
+        let current = settled();
+        if current == previous {
+            return Ok(current);
+        }
+        previous = current;
+    }
+    Err("RTC kept changing while being read")
+}
+
+fn is_leap(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bcd_12h(hours: u8) -> RtcRaw {
+        RtcRaw {
+            seconds: 0x56,
+            minutes: 0x34,
+            hours,
+            day: 0x18,
+            month: 0x10,
+            year: 0x26,
+            century: None,
+            status_b: 0,
+        }
+    }
+
+    #[test]
+    fn decodes_bcd_and_binary() {
+        let binary = RtcRaw {
+            seconds: 56,
+            minutes: 34,
+            hours: 21,
+            day: 18,
+            month: 10,
+            year: 26,
+            century: Some(20),
+            status_b: STATUS_B_BINARY | STATUS_B_24_HOUR,
+        };
+        let time = decode(binary).unwrap();
+        assert_eq!(alloc::format!("{time}"), "2026-10-18T21:34:56Z");
+
+        let mut bcd = bcd_12h(0x21);
+        bcd.status_b = STATUS_B_24_HOUR;
+        bcd.century = Some(0x20);
+        assert_eq!(decode(bcd), Ok(time));
+    }
+
+    #[test]
+    fn twelve_hour_mode_handles_midnight_and_noon() {
+        assert_eq!(decode(bcd_12h(0x12)).unwrap().hour, 0);
+        assert_eq!(decode(bcd_12h(HOUR_PM | 0x12)).unwrap().hour, 12);
+        assert_eq!(decode(bcd_12h(HOUR_PM | 0x09)).unwrap().hour, 21);
+        assert_eq!(decode(bcd_12h(0x09)).unwrap().hour, 9);
+        assert!(decode(bcd_12h(0x13)).is_err());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let mut raw = bcd_12h(0x09);
+        raw.minutes = 0x7a;
+        assert!(decode(raw).is_err());
+
+        let mut raw = bcd_12h(0x09);
+        raw.month = 0x02;
+        raw.day = 0x29;
+        raw.year = 0x25;
+        assert!(decode(raw).is_err());
+        raw.year = 0x24;
+        assert!(decode(raw).is_ok());
+    }
+
+    #[test]
+    fn unix_seconds_match_known_dates() {
+        let epoch = DateTime {
+            year: 1970,
+            month: 1,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+        assert_eq!(epoch.unix_seconds(), 0);
+        let leap_day = DateTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 12,
+            minute: 0,
+            second: 0,
+        };
+        assert_eq!(leap_day.unix_seconds(), 1_709_208_000);
+    }
+
+    #[test]
+    fn read_waits_out_updates_and_torn_reads() {
+        let mut uip = [true, true, false, false, false, false].into_iter();
+        let mut seconds = [58, 59, 59].into_iter();
+        let raw = read_stable(
+            || uip.next().unwrap_or(false),
+            || RtcRaw {
+                seconds: seconds.next().unwrap(),
+                ..RtcRaw::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(raw.seconds, 59);
+
+        let mut ticking = 0;
+        let result = read_stable(
+            || false,
+            || {
+                ticking += 1;
+                RtcRaw {
+                    seconds: ticking,
+                    ..RtcRaw::default()
+                }
+            },
+        );
+        assert!(result.is_err());
+    }
+}
@@ -1,9 +1,22 @@
+use core::fmt;
 use core::sync::atomic::{AtomicU64, Ordering};
 
+use crate::kthread;
+use crate::smp::MAX_CPUS;
+use crate::sync::SpinLock;
+
 pub const MAX_TASKS: usize = 16;
 
 static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
 
+/// `next_task` of a switch that leaves the CPU nothing to run. Task ids
+/// start at 1.
+pub const IDLE_TASK: u64 = 0;
+
+/// The kernel's run queues. Starts out uniprocessor; `init` resizes it once
+/// the CPU count is known.
+static SCHEDULER: SpinLock<SmpScheduler> = SpinLock::new(SmpScheduler::new(1));
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RegisterState {
     pub rax: u64,
@@ -71,12 +84,24 @@ impl Task {
             registers: regs,
         }
     }
+
+    /// A task that starts at `entry` with `arg` in `rdi` on the stack ending
+    /// at `stack_top`, laid out as if `entry` had just been called.
+    pub fn kernel_thread(entry: u64, arg: u64, stack_top: u64) -> Self {
+        let stack_pointer = (stack_top & !0xf) - 8;
+        let mut task = Self::new(entry, stack_pointer);
+        task.registers.rdi = arg;
+        task
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ContextSwitch {
     pub previous_task: u64,
     pub next_task: u64,
+    /// The previous task exited and is off the run queue. Its stack is
+    /// free once the switch is done; see `finish_switch`.
+    pub previous_exited: bool,
 }
 
 pub struct RoundRobinScheduler {
@@ -84,6 +109,9 @@ pub struct RoundRobinScheduler {
     run_queue: [usize; MAX_TASKS],
     queue_len: usize,
     current_pos: usize,
+    /// The current task, once it has asked to exit. The next tick switches
+    /// away from it for good.
+    exiting: Option<u64>,
 }
 
 impl RoundRobinScheduler {
@@ -93,6 +121,7 @@ impl RoundRobinScheduler {
             run_queue: [0; MAX_TASKS],
             queue_len: 0,
             current_pos: 0,
+            exiting: None,
         }
     }
 
@@ -109,6 +138,9 @@ impl RoundRobinScheduler {
     }
 
     pub fn on_timer_tick(&mut self) -> Option<ContextSwitch> {
+        if self.exiting.is_some() && self.exiting == self.current_task().map(|t| t.id) {
+            return self.retire_current();
+        }
         if self.queue_len <= 1 {
             return None;
         }
@@ -123,6 +155,35 @@ impl RoundRobinScheduler {
         Some(ContextSwitch {
             previous_task: prev.id,
             next_task: next.id,
+            previous_exited: false,
+        })
+    }
+
+    /// Marks the running task `id` as exiting. It keeps its place, and its
+    /// stack, until the next tick switches away from it. False if `id` is
+    /// not the running task.
+    pub fn exit_current(&mut self, id: u64) -> bool {
+        if self.current_task().is_none_or(|task| task.id != id) {
+            return false;
+        }
+        self.exiting = Some(id);
+        true
+    }
+
+    /// Drops the exiting current task; the one queued after it runs next.
+    fn retire_current(&mut self) -> Option<ContextSwitch> {
+        self.exiting = None;
+        let prev = self.tasks[self.run_queue[self.current_pos]].take()?;
+        self.run_queue
+            .copy_within(self.current_pos + 1..self.queue_len, self.current_pos);
+        self.queue_len -= 1;
+        if self.current_pos >= self.queue_len {
+            self.current_pos = 0;
+        }
+        Some(ContextSwitch {
+            previous_task: prev.id,
+            next_task: self.current_task().map_or(IDLE_TASK, |task| task.id),
+            previous_exited: true,
         })
     }
 
@@ -150,6 +211,51 @@ impl RoundRobinScheduler {
         self.current_task().map(|t| t.registers)
     }
 
+    pub fn len(&self) -> usize {
+        self.queue_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue_len == 0
+    }
+
+    /// Queued tasks in run order.
+    pub fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.run_queue[..self.queue_len]
+            .iter()
+            .filter_map(|&slot| self.tasks[slot].as_ref())
+    }
+
+    pub fn remove_task(&mut self, id: u64) -> Option<Task> {
+        let pos = (0..self.queue_len)
+            .find(|&pos| self.tasks[self.run_queue[pos]].is_some_and(|task| task.id == id))?;
+        let task = self.tasks[self.run_queue[pos]].take();
+        if self.exiting == Some(id) {
+            self.exiting = None;
+        }
+
+        self.run_queue.copy_within(pos + 1..self.queue_len, pos);
+        self.queue_len -= 1;
+        if pos < self.current_pos {
+            self.current_pos -= 1;
+        }
+        if self.current_pos >= self.queue_len {
+            self.current_pos = 0;
+        }
+        task
+    }
+
+    /// Removes a queued task other than the one currently running, so it can
+    /// be handed to another CPU without a context switch on this one.
+    pub fn take_migratable(&mut self) -> Option<Task> {
+        if self.queue_len <= 1 {
+            return None;
+        }
+        let pos = (self.current_pos + self.queue_len - 1) % self.queue_len;
+        let id = self.tasks[self.run_queue[pos]]?.id;
+        self.remove_task(id)
+    }
+
     fn find_free_slot(&self) -> Option<usize> {
         let mut i = 0;
         while i < MAX_TASKS {
@@ -162,6 +268,216 @@ impl RoundRobinScheduler {
     }
 }
 
+impl Default for RoundRobinScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A task moved between per-CPU run queues. The destination CPU should be
+/// sent a reschedule IPI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub task: u64,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// One round-robin run queue per online CPU, with load balancing between them.
+pub struct SmpScheduler {
+    queues: [RoundRobinScheduler; MAX_CPUS],
+    cpu_count: usize,
+}
+
+impl SmpScheduler {
+    pub const fn new(cpu_count: usize) -> Self {
+        let cpu_count = if cpu_count == 0 {
+            1
+        } else if cpu_count > MAX_CPUS {
+            MAX_CPUS
+        } else {
+            cpu_count
+        };
+
+        Self {
+            queues: [const { RoundRobinScheduler::new() }; MAX_CPUS],
+            cpu_count,
+        }
+    }
+
+    pub fn cpu_count(&self) -> usize {
+        self.cpu_count
+    }
+
+    /// Queues `task` on the least loaded CPU and returns that CPU.
+    pub fn add_task(&mut self, task: Task) -> Result<usize, &'static str> {
+        let cpu = self.idlest();
+        self.add_task_on(cpu, task)?;
+        Ok(cpu)
+    }
+
+    pub fn add_task_on(&mut self, cpu: usize, task: Task) -> Result<u64, &'static str> {
+        self.queue_mut(cpu)?.add_task(task)
+    }
+
+    pub fn on_timer_tick(&mut self, cpu: usize) -> Option<ContextSwitch> {
+        self.queue_mut(cpu).ok()?.on_timer_tick()
+    }
+
+    pub fn current_task(&self, cpu: usize) -> Option<Task> {
+        self.queue(cpu)?.current_task()
+    }
+
+    pub fn load(&self, cpu: usize) -> usize {
+        self.queue(cpu).map_or(0, RoundRobinScheduler::len)
+    }
+
+    /// Tasks queued on `cpu`, in run order.
+    pub fn tasks(&self, cpu: usize) -> impl Iterator<Item = &Task> {
+        self.queue(cpu)
+            .into_iter()
+            .flat_map(RoundRobinScheduler::tasks)
+    }
+
+    /// Marks task `id` as exiting on the CPU it is running on; false if it
+    /// is not running anywhere.
+    pub fn exit_task(&mut self, id: u64) -> bool {
+        self.queues[..self.cpu_count]
+            .iter_mut()
+            .any(|queue| queue.exit_current(id))
+    }
+
+    /// Removes task `id` from whichever CPU it is queued on. Not for the
+    /// task running on a CPU: that one leaves through `exit_task`.
+    pub fn remove_task(&mut self, id: u64) -> Option<Task> {
+        self.queues[..self.cpu_count]
+            .iter_mut()
+            .find_map(|queue| queue.remove_task(id))
+    }
+
+    /// Moves one task from the busiest to the idlest CPU when their queue
+    /// lengths differ by two or more. Meant to run periodically on one CPU.
+    pub fn balance(&mut self) -> Option<Migration> {
+        let from = self.busiest();
+        let to = self.idlest();
+        if self.load(from) < self.load(to) + 2 {
+            return None;
+        }
+        self.migrate(from, to)
+    }
+
+    /// Lets an idle `cpu` pull work from the busiest CPU.
+    pub fn steal(&mut self, cpu: usize) -> Option<Migration> {
+        if cpu >= self.cpu_count || self.load(cpu) != 0 {
+            return None;
+        }
+        let from = self.busiest();
+        if self.load(from) < 2 {
+            return None;
+        }
+        self.migrate(from, cpu)
+    }
+
+    fn migrate(&mut self, from: usize, to: usize) -> Option<Migration> {
+        let task = self.queues[from].take_migratable()?;
+        if self.queues[to].add_task(task).is_err() {
+            let _ = self.queues[from].add_task(task);
+            return None;
+        }
+        Some(Migration {
+            task: task.id,
+            from,
+            to,
+        })
+    }
+
+    fn busiest(&self) -> usize {
+        (0..self.cpu_count)
+            .rev()
+            .max_by_key(|&cpu| self.load(cpu))
+            .unwrap_or(0)
+    }
+
+    fn idlest(&self) -> usize {
+        (0..self.cpu_count)
+            .min_by_key(|&cpu| self.load(cpu))
+            .unwrap_or(0)
+    }
+
+    fn queue(&self, cpu: usize) -> Option<&RoundRobinScheduler> {
+        self.queues[..self.cpu_count].get(cpu)
+    }
+
+    fn queue_mut(&mut self, cpu: usize) -> Result<&mut RoundRobinScheduler, &'static str> {
+        self.queues[..self.cpu_count]
+            .get_mut(cpu)
+            .ok_or("no such CPU")
+    }
+}
+
+/// Resets the global run queues for `cpu_count` CPUs.
+pub fn init(cpu_count: usize) {
+    *SCHEDULER.lock() = SmpScheduler::new(cpu_count);
+}
+
+/// Queues `task` on the least loaded CPU of the global scheduler.
+pub fn spawn(task: Task) -> Result<usize, &'static str> {
+    SCHEDULER.lock().add_task(task)
+}
+
+/// Takes task `id` off the global run queues before it ever runs.
+pub fn remove(id: u64) -> Option<Task> {
+    SCHEDULER.lock().remove_task(id)
+}
+
+/// Called by task `id`, running, to leave its CPU for good at the next
+/// tick. It must then wait for that tick without touching its stack
+/// more than it has to.
+pub fn exit(id: u64) -> bool {
+    SCHEDULER.lock().exit_task(id)
+}
+
+/// Ticks `cpu`'s run queue.
+pub fn tick(cpu: usize) -> Option<ContextSwitch> {
+    SCHEDULER.lock().on_timer_tick(cpu)
+}
+
+/// Called on the next task's stack once `switch` is carried out. Only then
+/// is a task that exited off its stack, so its kernel thread is finished
+/// here and the stack may be freed.
+pub fn finish_switch(switch: &ContextSwitch) {
+    if switch.previous_exited {
+        kthread::finished(switch.previous_task);
+    }
+}
+
+/// Writes the global run queues, then every kernel thread, to `out`.
+pub fn dump(out: &mut impl fmt::Write) -> fmt::Result {
+    {
+        let scheduler = SCHEDULER.lock();
+        for cpu in 0..scheduler.cpu_count() {
+            let current = scheduler.current_task(cpu).map(|task| task.id);
+            writeln!(out, "cpu{} load={}", cpu, scheduler.load(cpu))?;
+            for task in scheduler.tasks(cpu) {
+                write!(
+                    out,
+                    "  {} task id={} rip={:#x} rsp={:#x}",
+                    if current == Some(task.id) { '*' } else { ' ' },
+                    task.id,
+                    task.registers.rip,
+                    task.registers.rsp
+                )?;
+                match kthread::name_of(task.id) {
+                    Some(name) => writeln!(out, " kthread={}", name)?,
+                    None => writeln!(out)?,
+                }
+            }
+        }
+    }
+
+    kthread::dump(out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -195,4 +511,89 @@ mod tests {
         assert_eq!(loaded.rax, 42);
         assert_eq!(loaded.rsp, 0xBEEF);
     }
+
+    #[test]
+    fn removing_a_task_keeps_rotation_consistent() {
+        let mut scheduler = RoundRobinScheduler::new();
+        let id1 = scheduler.add_task(Task::new(0x1000, 0x8000)).unwrap();
+        let id2 = scheduler.add_task(Task::new(0x2000, 0x9000)).unwrap();
+        let id3 = scheduler.add_task(Task::new(0x3000, 0xA000)).unwrap();
+
+        scheduler.on_timer_tick();
+        assert_eq!(scheduler.remove_task(id1).unwrap().id, id1);
+        assert_eq!(scheduler.current_task().unwrap().id, id2);
+        assert_eq!(scheduler.on_timer_tick().unwrap().next_task, id3);
+        assert!(scheduler.remove_task(id1).is_none());
+        assert_eq!(scheduler.len(), 2);
+    }
+
+    #[test]
+    fn exiting_task_leaves_at_the_next_tick() {
+        let mut scheduler = RoundRobinScheduler::new();
+        let id1 = scheduler.add_task(Task::new(0x1000, 0x8000)).unwrap();
+        let id2 = scheduler.add_task(Task::new(0x2000, 0x9000)).unwrap();
+        let id3 = scheduler.add_task(Task::new(0x3000, 0xA000)).unwrap();
+
+        scheduler.on_timer_tick();
+        assert!(!scheduler.exit_current(id1));
+        assert!(scheduler.exit_current(id2));
+        assert_eq!(scheduler.current_task().unwrap().id, id2);
+        assert_eq!(scheduler.len(), 3);
+
+        let sw = scheduler.on_timer_tick().unwrap();
+        assert_eq!((sw.previous_task, sw.next_task), (id2, id3));
+        assert!(sw.previous_exited);
+        assert_eq!(scheduler.len(), 2);
+        assert_eq!(scheduler.on_timer_tick().unwrap().next_task, id1);
+
+        assert!(scheduler.exit_current(id1));
+        let sw = scheduler.on_timer_tick().unwrap();
+        assert_eq!((sw.previous_task, sw.next_task), (id1, id3));
+        assert!(scheduler.exit_current(id3));
+        let sw = scheduler.on_timer_tick().unwrap();
+        assert_eq!((sw.previous_task, sw.next_task), (id3, IDLE_TASK));
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn smp_scheduler_spreads_and_balances_tasks() {
+        let mut smp = SmpScheduler::new(2);
+        for i in 0..4 {
+            smp.add_task(Task::new(0x1000 * i, 0x8000)).unwrap();
+        }
+        assert_eq!((smp.load(0), smp.load(1)), (2, 2));
+        assert!(smp.balance().is_none());
+
+        for i in 0..3 {
+            smp.add_task_on(0, Task::new(0x1000 * i, 0x8000)).unwrap();
+        }
+        let running = smp.current_task(0).unwrap().id;
+        let migration = smp.balance().unwrap();
+        assert_eq!((migration.from, migration.to), (0, 1));
+        assert_ne!(migration.task, running);
+        assert_eq!(smp.current_task(0).unwrap().id, running);
+        assert_eq!((smp.load(0), smp.load(1)), (4, 3));
+    }
+
+    #[test]
+    fn kernel_thread_entry_frame_is_call_aligned() {
+        let task = Task::kernel_thread(0x4000, 0xdead, 0x9_0007);
+        assert_eq!(task.registers.rip, 0x4000);
+        assert_eq!(task.registers.rdi, 0xdead);
+        assert_eq!(task.registers.rsp, 0x8_fff8);
+        assert_eq!((task.registers.rsp + 8) % 16, 0);
+    }
+
+    #[test]
+    fn idle_cpu_steals_from_busiest() {
+        let mut smp = SmpScheduler::new(3);
+        for i in 0..3 {
+            smp.add_task_on(1, Task::new(0x1000 * i, 0x8000)).unwrap();
+        }
+        let migration = smp.steal(2).unwrap();
+        assert_eq!((migration.from, migration.to), (1, 2));
+        assert_eq!(smp.current_task(2).unwrap().id, migration.task);
+        assert!(smp.steal(1).is_none());
+        assert!(smp.on_timer_tick(5).is_none());
+    }
 }
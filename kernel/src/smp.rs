@@ -0,0 +1,223 @@
+pub const MAX_CPUS: usize = 8;
+
+pub const RESCHEDULE_VECTOR: u8 = 0xF1;
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xF2;
+
+/// Delay between the INIT IPI and the first SIPI.
+pub const INIT_DELAY_US: u64 = 10_000;
+/// Delay between the two SIPIs of the startup sequence.
+pub const SIPI_DELAY_US: u64 = 200;
+
+const MADT_ENTRIES_OFFSET: usize = 44;
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LAPIC_ADDRESS_OVERRIDE: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+/// Local APIC flags bit 0. Bit 1, online capable, marks a CPU that is off
+/// now and may be hot-added later; it must not be started at boot.
+const MADT_CPU_ENABLED: u32 = 1 << 0;
+
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuDescriptor {
+    pub acpi_id: u32,
+    pub apic_id: u32,
+}
+
+/// Processors and local APIC base discovered in the ACPI MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuList {
+    pub lapic_address: u64,
+    cpus: [Option<CpuDescriptor>; MAX_CPUS],
+    len: usize,
+}
+
+impl CpuList {
+    /// Parses a complete MADT (header included) and collects usable CPUs.
+    pub fn from_madt(table: &[u8]) -> Result<Self, &'static str> {
+        if table.len() < MADT_ENTRIES_OFFSET || &table[..4] != b"APIC" {
+            return Err("not a MADT");
+        }
+        let declared_len = read_u32(table, 4) as usize;
+        if declared_len != table.len() {
+            return Err("MADT length mismatch");
+        }
+        if table.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err("MADT checksum mismatch");
+        }
+
+        let mut list = Self {
+            lapic_address: read_u32(table, 36) as u64,
+            cpus: [None; MAX_CPUS],
+            len: 0,
+        };
+
+        let mut offset = MADT_ENTRIES_OFFSET;
+        while offset + 2 <= table.len() {
+            let kind = table[offset];
+            let len = table[offset + 1] as usize;
+            if len < 2 || offset + len > table.len() {
+                return Err("truncated MADT entry");
+            }
+            let entry = &table[offset..offset + len];
+
+            match kind {
+                MADT_LOCAL_APIC if len >= 8 => {
+                    list.push(read_u32(entry, 4), entry[2] as u32, entry[3] as u32);
+                }
+                MADT_LOCAL_X2APIC if len >= 16 => {
+                    list.push(read_u32(entry, 8), read_u32(entry, 12), read_u32(entry, 4));
+                }
+                MADT_LAPIC_ADDRESS_OVERRIDE if len >= 12 => {
+                    list.lapic_address =
+                        ((read_u32(entry, 8) as u64) << 32) | read_u32(entry, 4) as u64;
+                }
+                _ => {}
+            }
+
+            offset += len;
+        }
+
+        if list.len == 0 {
+            return Err("MADT lists no usable CPU");
+        }
+        Ok(list)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CpuDescriptor> {
+        self.cpus[..self.len].iter().flatten()
+    }
+
+    fn push(&mut self, flags: u32, acpi_id: u32, apic_id: u32) {
+        if flags & MADT_CPU_ENABLED == 0 || self.len >= MAX_CPUS {
+            return;
+        }
+        if self.iter().any(|cpu| cpu.apic_id == apic_id) {
+            return;
+        }
+        self.cpus[self.len] = Some(CpuDescriptor { acpi_id, apic_id });
+        self.len += 1;
+    }
+}
+
+/// One write to the local APIC interrupt command register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcrCommand {
+    pub destination: u32,
+    pub low: u32,
+}
+
+/// INIT, SIPI, SIPI for one application processor. The caller waits
+/// `INIT_DELAY_US` after the first command and `SIPI_DELAY_US` after the second.
+pub fn ap_startup_sequence(
+    apic_id: u32,
+    trampoline_phys: u64,
+) -> Result<[IcrCommand; 3], &'static str> {
+    if trampoline_phys & 0xfff != 0 {
+        return Err("AP trampoline must be page aligned");
+    }
+    if trampoline_phys >= 0x10_0000 {
+        return Err("AP trampoline must sit below 1 MiB");
+    }
+
+    let sipi = IcrCommand {
+        destination: apic_id,
+        low: ICR_STARTUP | ICR_LEVEL_ASSERT | (trampoline_phys >> 12) as u32,
+    };
+    Ok([
+        IcrCommand {
+            destination: apic_id,
+            low: ICR_INIT | ICR_LEVEL_ASSERT,
+        },
+        sipi,
+        sipi,
+    ])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn madt(entries: &[&[u8]]) -> [u8; 128] {
+        let mut table = [0u8; 128];
+        table[..4].copy_from_slice(b"APIC");
+        table[36..40].copy_from_slice(&0xfee0_0000u32.to_le_bytes());
+
+        let mut len = MADT_ENTRIES_OFFSET;
+        for entry in entries {
+            table[len..len + entry.len()].copy_from_slice(entry);
+            len += entry.len();
+        }
+        table[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+        let sum = table[..len].iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        table[9] = 0u8.wrapping_sub(sum);
+        table
+    }
+
+    #[test]
+    fn madt_lists_enabled_cpus_only() {
+        let table = madt(&[
+            &[0, 8, 0, 0, 1, 0, 0, 0],
+            &[0, 8, 1, 2, 1, 0, 0, 0],
+            &[0, 8, 2, 4, 0, 0, 0, 0],
+            // Online capable but not enabled: hot-pluggable, not present.
+            &[0, 8, 3, 6, 2, 0, 0, 0],
+            &[9, 16, 0, 0, 9, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0],
+        ]);
+        let len = read_u32(&table, 4) as usize;
+
+        let cpus = CpuList::from_madt(&table[..len]).unwrap();
+        assert_eq!(cpus.lapic_address, 0xfee0_0000);
+        assert_eq!(cpus.len(), 3);
+        let apic_ids: [u32; 3] = {
+            let mut ids = cpus.iter().map(|cpu| cpu.apic_id);
+            [
+                ids.next().unwrap(),
+                ids.next().unwrap(),
+                ids.next().unwrap(),
+            ]
+        };
+        assert_eq!(apic_ids, [0, 2, 9]);
+    }
+
+    #[test]
+    fn madt_with_bad_checksum_is_rejected() {
+        let mut table = madt(&[&[0, 8, 0, 0, 1, 0, 0, 0]]);
+        let len = read_u32(&table, 4) as usize;
+        table[10] ^= 0xff;
+        assert_eq!(
+            CpuList::from_madt(&table[..len]),
+            Err("MADT checksum mismatch")
+        );
+    }
+
+    #[test]
+    fn startup_sequence_targets_trampoline_page() {
+        let seq = ap_startup_sequence(3, 0x8000).unwrap();
+        assert_eq!(seq[0].low, 0x4500);
+        assert_eq!(seq[1].low, 0x4608);
+        assert_eq!(seq[2], seq[1]);
+        assert!(seq.iter().all(|cmd| cmd.destination == 3));
+        assert!(ap_startup_sequence(3, 0x8800).is_err());
+        assert!(ap_startup_sequence(3, 0x10_0000).is_err());
+    }
+}
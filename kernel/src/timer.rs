@@ -21,6 +21,199 @@ pub fn configure_pit(frequency_hz: u32) -> PitConfig {
     }
 }
 
+/// Where periodic timer interrupts come from. Chosen once at boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    /// Legacy 8254, broadcast to the other CPUs. Always available.
+    Pit,
+    /// Local APIC timer in periodic mode.
+    LapicPeriodic,
+    /// Local APIC timer re-armed through `IA32_TSC_DEADLINE` every tick.
+    TscDeadline,
+}
+
+impl TickSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pit => "pit",
+            Self::LapicPeriodic => "lapic-periodic",
+            Self::TscDeadline => "tsc-deadline",
+        }
+    }
+}
+
+/// What the boot CPU found while probing timers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimerCaps {
+    /// Local APIC present and its timer calibrated.
+    pub lapic_hz: Option<u64>,
+    /// CPUID.01H:ECX.TSC_Deadline.
+    pub tsc_deadline: bool,
+    /// TSC rate, when it was measured against a reference clock.
+    pub tsc_hz: Option<u64>,
+}
+
+/// Prefers TSC-deadline, then the periodic LAPIC timer, then the PIT.
+pub fn choose_tick_source(caps: TimerCaps) -> TickSource {
+    match caps {
+        TimerCaps {
+            lapic_hz: Some(_),
+            tsc_deadline: true,
+            tsc_hz: Some(_),
+        } => TickSource::TscDeadline,
+        TimerCaps {
+            lapic_hz: Some(_), ..
+        } => TickSource::LapicPeriodic,
+        _ => TickSource::Pit,
+    }
+}
+
+/// Mode field (bits 17-18) of the LAPIC LVT timer register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LapicTimerMode {
+    OneShot = 0b00,
+    Periodic = 0b01,
+    TscDeadline = 0b10,
+}
+
+const LVT_MASKED: u32 = 1 << 16;
+
+/// Encodes the LAPIC LVT timer register.
+pub const fn lapic_lvt_timer(vector: u8, mode: LapicTimerMode, masked: bool) -> u32 {
+    let mask = if masked { LVT_MASKED } else { 0 };
+    (mode as u32) << 17 | mask | vector as u32
+}
+
+/// Encodes a LAPIC timer divide configuration; `divisor` must be a power of
+/// two from 1 to 128.
+pub const fn lapic_divide_config(divisor: u32) -> Option<u32> {
+    let encoded = match divisor {
+        1 => 0b111,
+        2 => 0b000,
+        4 => 0b001,
+        8 => 0b010,
+        16 => 0b011,
+        32 => 0b1000,
+        64 => 0b1001,
+        128 => 0b1010,
+        _ => return None,
+    };
+    Some(encoded)
+}
+
+/// Rates measured over one calibration window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    /// LAPIC timer input clock, before the divider.
+    pub lapic_hz: u64,
+    pub tsc_hz: u64,
+}
+
+/// Turns counts observed over `window_ns` of reference time into rates.
+/// `lapic_elapsed` was counted with the timer divided by `lapic_divisor`.
+pub fn calibrate(
+    lapic_elapsed: u32,
+    lapic_divisor: u32,
+    tsc_elapsed: u64,
+    window_ns: u64,
+) -> Result<Calibration, &'static str> {
+    if window_ns == 0 || lapic_elapsed == 0 {
+        return Err("empty calibration window");
+    }
+    let scale = |count: u64| (count as u128 * 1_000_000_000 / window_ns as u128) as u64;
+    Ok(Calibration {
+        lapic_hz: scale(lapic_elapsed as u64 * lapic_divisor as u64),
+        tsc_hz: scale(tsc_elapsed),
+    })
+}
+
+/// Initial count that makes a LAPIC timer divided by `divisor` fire at
+/// `tick_hz`.
+pub fn lapic_initial_count(lapic_hz: u64, divisor: u32, tick_hz: u32) -> Result<u32, &'static str> {
+    if divisor == 0 || tick_hz == 0 {
+        return Err("invalid LAPIC timer divisor or rate");
+    }
+    let count = lapic_hz / divisor as u64 / tick_hz as u64;
+    match count {
+        0 => Err("LAPIC timer too slow for the tick rate"),
+        c if c > u32::MAX as u64 => Err("LAPIC timer too fast for the divisor"),
+        c => Ok(c as u32),
+    }
+}
+
+/// TSC cycles between two deadlines at `tick_hz`.
+pub fn tsc_deadline_delta(tsc_hz: u64, tick_hz: u32) -> u64 {
+    (tsc_hz / tick_hz.max(1) as u64).max(1)
+}
+
+/// HPET main-counter ticks in `ns` nanoseconds, given the counter period
+/// in femtoseconds from the capabilities register.
+pub fn hpet_ticks(period_fs: u32, ns: u64) -> u64 {
+    (ns as u128 * 1_000_000 / period_fs.max(1) as u128) as u64
+}
+
+/// A tick this close to its due time counts as due, so an interrupt that
+/// fires a hair early does not cost a whole extra period.
+pub const TICK_SLACK_NS: u64 = 50_000;
+
+/// Longest a tickless idle CPU sleeps with no timer pending.
+pub const MAX_IDLE_NS: u64 = 1_000_000_000;
+
+/// Per-CPU tick bookkeeping once the local timer is programmed event by
+/// event instead of periodically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickState {
+    next_tick_ns: u64,
+    period_ns: u64,
+    stopped: bool,
+}
+
+impl TickState {
+    pub const fn new(period_ns: u64, now_ns: u64) -> Self {
+        Self {
+            next_tick_ns: now_ns + period_ns,
+            period_ns,
+            stopped: false,
+        }
+    }
+
+    /// How many ticks fell due by `now_ns`, counting any slept through
+    /// while stopped, and moves the next tick past `now_ns`.
+    pub fn due_ticks(&mut self, now_ns: u64) -> u64 {
+        if now_ns + TICK_SLACK_NS < self.next_tick_ns {
+            return 0;
+        }
+        let due = 1 + now_ns.saturating_sub(self.next_tick_ns) / self.period_ns;
+        self.next_tick_ns += due * self.period_ns;
+        due
+    }
+
+    /// Tickless idle: the next event ignores the tick until `restart`.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    pub fn restart(&mut self) {
+        self.stopped = false;
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// When the local timer should fire next: the earlier of the next tick
+    /// and the next timer deadline, or only the deadline (capped at
+    /// `MAX_IDLE_NS`) while the tick is stopped.
+    pub fn next_event(&self, now_ns: u64, next_timer_ns: Option<u64>) -> u64 {
+        let tick = if self.stopped {
+            now_ns + MAX_IDLE_NS
+        } else {
+            self.next_tick_ns
+        };
+        next_timer_ns.map_or(tick, |deadline| deadline.min(tick))
+    }
+}
+
 pub fn init(frequency_hz: u32) -> PitConfig {
     TICKS.store(0, Ordering::SeqCst);
     configure_pit(frequency_hz)
@@ -44,6 +237,97 @@ mod tests {
         assert_eq!(cfg.divisor, 11_931);
     }
 
+    #[test]
+    fn pit_divisor_is_clamped_to_16_bits() {
+        assert_eq!(configure_pit(1).divisor, u16::MAX);
+        assert_eq!(configure_pit(5_000_000).divisor, 1);
+    }
+
+    #[test]
+    fn tick_source_prefers_tsc_deadline_then_lapic() {
+        let mut caps = TimerCaps::default();
+        assert_eq!(choose_tick_source(caps), TickSource::Pit);
+
+        caps.tsc_deadline = true;
+        assert_eq!(choose_tick_source(caps), TickSource::Pit);
+
+        caps.lapic_hz = Some(1_000_000_000);
+        assert_eq!(choose_tick_source(caps), TickSource::LapicPeriodic);
+
+        caps.tsc_hz = Some(3_000_000_000);
+        assert_eq!(choose_tick_source(caps), TickSource::TscDeadline);
+        assert_eq!(TickSource::TscDeadline.name(), "tsc-deadline");
+    }
+
+    #[test]
+    fn lapic_timer_registers_are_encoded() {
+        assert_eq!(
+            lapic_lvt_timer(0xf0, LapicTimerMode::Periodic, false),
+            0x2_00f0
+        );
+        assert_eq!(
+            lapic_lvt_timer(0xf0, LapicTimerMode::TscDeadline, false),
+            0x4_00f0
+        );
+        assert_eq!(
+            lapic_lvt_timer(0x20, LapicTimerMode::OneShot, true),
+            0x1_0020
+        );
+        assert_eq!(lapic_divide_config(16), Some(0b011));
+        assert_eq!(lapic_divide_config(1), Some(0b111));
+        assert_eq!(lapic_divide_config(3), None);
+    }
+
+    #[test]
+    fn calibration_scales_counts_to_rates() {
+        // 10 ms window: 625_000 counts at divide-by-16 is a 1 GHz bus.
+        let cal = calibrate(625_000, 16, 30_000_000, 10_000_000).unwrap();
+        assert_eq!(cal.lapic_hz, 1_000_000_000);
+        assert_eq!(cal.tsc_hz, 3_000_000_000);
+        assert!(calibrate(0, 16, 1, 10_000_000).is_err());
+
+        assert_eq!(lapic_initial_count(cal.lapic_hz, 16, 100), Ok(625_000));
+        assert!(lapic_initial_count(1_000, 16, 100).is_err());
+        assert!(lapic_initial_count(u64::MAX, 1, 1).is_err());
+        assert_eq!(tsc_deadline_delta(cal.tsc_hz, 100), 30_000_000);
+    }
+
+    #[test]
+    fn hpet_ticks_follow_counter_period() {
+        // Common 14.318 MHz HPET: period 69_841_279 fs.
+        assert_eq!(hpet_ticks(69_841_279, 10_000_000), 143_181);
+        assert_eq!(hpet_ticks(10_000_000, 1_000), 100);
+    }
+
+    #[test]
+    fn due_ticks_tolerate_early_interrupts_and_catch_up() {
+        let mut state = TickState::new(10_000_000, 0);
+        assert_eq!(state.due_ticks(5_000_000), 0);
+        assert_eq!(state.due_ticks(10_000_000 - TICK_SLACK_NS), 1);
+        assert_eq!(state.due_ticks(15_000_000), 0);
+
+        // Slept through most of a second of ticks.
+        state.stop();
+        assert_eq!(state.due_ticks(995_000_000), 98);
+        assert_eq!(state.due_ticks(999_000_000), 0);
+        assert_eq!(state.due_ticks(1_000_000_000), 1);
+    }
+
+    #[test]
+    fn next_event_skips_the_tick_only_while_stopped() {
+        let mut state = TickState::new(10_000_000, 0);
+        assert_eq!(state.next_event(0, None), 10_000_000);
+        assert_eq!(state.next_event(0, Some(2_500_000)), 2_500_000);
+        assert_eq!(state.next_event(0, Some(50_000_000)), 10_000_000);
+
+        state.stop();
+        assert!(state.is_stopped());
+        assert_eq!(state.next_event(0, Some(50_000_000)), 50_000_000);
+        assert_eq!(state.next_event(0, None), MAX_IDLE_NS);
+        state.restart();
+        assert_eq!(state.next_event(0, None), 10_000_000);
+    }
+
     #[test]
     fn timer_ticks_increment() {
         init(100);
@@ -0,0 +1,93 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Address asking each target to flush its whole TLB rather than one page.
+pub const FLUSH_ALL: u64 = u64::MAX;
+
+/// Held in `pending` while the winning initiator publishes its address, so
+/// no target acknowledges before it is there. CPUs are 0 to 30.
+const CLAIMED: u32 = 1 << 31;
+
+/// Bitmask of CPUs that still have to acknowledge a TLB shootdown.
+pub struct TlbShootdown {
+    addr: AtomicU64,
+    pending: AtomicU32,
+}
+
+impl TlbShootdown {
+    pub const fn new() -> Self {
+        Self {
+            addr: AtomicU64::new(0),
+            pending: AtomicU32::new(0),
+        }
+    }
+
+    /// Publishes `addr` for the CPUs in `targets`. Fails while a previous
+    /// shootdown is still outstanding; of two initiators racing for an idle
+    /// one, exactly one wins. Send the IPIs once this returns.
+    pub fn begin(&self, addr: u64, targets: u32) -> Result<(), &'static str> {
+        if targets == 0 {
+            return Ok(());
+        }
+        self.pending
+            .compare_exchange(0, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| "TLB shootdown already in flight")?;
+        self.addr.store(addr, Ordering::Relaxed);
+        self.pending.store(targets & !CLAIMED, Ordering::Release);
+        Ok(())
+    }
+
+    /// Called from the shootdown IPI handler: runs `flush` on the published
+    /// address, and only then lets the initiator go on without `cpu`.
+    pub fn acknowledge(&self, cpu: usize, flush: impl FnOnce(u64)) {
+        flush(self.addr.load(Ordering::Acquire));
+        self.pending.fetch_and(!(1 << cpu), Ordering::AcqRel);
+    }
+
+    /// Whether `cpu` has yet to acknowledge the shootdown in flight.
+    pub fn is_pending(&self, cpu: usize) -> bool {
+        self.pending.load(Ordering::Acquire) & (1 << cpu) != 0
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+}
+
+impl Default for TlbShootdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shootdown_completes_after_all_targets_acknowledge() {
+        let shootdown = TlbShootdown::new();
+        shootdown.begin(0x4000, 0b110).unwrap();
+        assert!(shootdown.is_pending(1));
+        assert!(!shootdown.is_pending(0));
+
+        shootdown.acknowledge(1, |addr| assert_eq!(addr, 0x4000));
+        assert!(!shootdown.is_complete());
+        shootdown.acknowledge(2, |_| {});
+        assert!(shootdown.is_complete());
+    }
+
+    #[test]
+    fn second_initiator_waits_for_the_first() {
+        let shootdown = TlbShootdown::new();
+        shootdown.begin(0x4000, 0b10).unwrap();
+        assert!(shootdown.begin(0x5000, 0b1).is_err());
+        assert!(!shootdown.is_pending(0));
+        shootdown.acknowledge(1, |addr| assert_eq!(addr, 0x4000));
+
+        shootdown.begin(FLUSH_ALL, 0b1).unwrap();
+        let mut flushed = 0;
+        shootdown.acknowledge(0, |addr| flushed = addr);
+        assert_eq!(flushed, FLUSH_ALL);
+        assert!(shootdown.is_complete());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{Errno, Syscall, MAX_ARGS};
+
+/// Argument constraints one filter holds.
+pub const MAX_ARG_RULES: usize = 4;
+
+/// `SyscallFilter::mode`: whether `calls` lists the permitted calls or the
+/// forbidden ones.
+pub const FILTER_ALLOW: u32 = 0;
+pub const FILTER_DENY: u32 = 1;
+
+/// `SyscallFilter::action`: what a call the filter rejects gets.
+pub const FILTER_ERRNO: u32 = 0;
+pub const FILTER_KILL: u32 = 1;
+
+/// `ArgRule::op`. `ARG_NONE` marks an unused slot.
+pub const ARG_NONE: u16 = 0;
+pub const ARG_EQ: u16 = 1;
+pub const ARG_NE: u16 = 2;
+/// Unsigned comparison.
+pub const ARG_LT: u16 = 3;
+/// Every bit of `value` is clear in the argument, e.g. no `O_CREAT`.
+pub const ARG_CLEAR: u16 = 4;
+
+/// What happens to a call a filter rejects. The stricter action wins when
+/// several filters reject the same call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FilterAction {
+    /// The call fails with `EPERM`.
+    Errno,
+    /// The caller is killed with `SIGSYS`.
+    Kill,
+}
+
+/// A condition on argument `arg` of call `call`, checked whenever the
+/// filter lets that call through.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ArgRule {
+    pub value: u64,
+    pub call: u32,
+    pub arg: u16,
+    pub op: u16,
+}
+
+impl ArgRule {
+    fn holds(&self, arg: usize) -> bool {
+        let arg = arg as u64;
+        match self.op {
+            ARG_EQ => arg == self.value,
+            ARG_NE => arg != self.value,
+            ARG_LT => arg < self.value,
+            ARG_CLEAR => arg & self.value == 0,
+            _ => true,
+        }
+    }
+}
+
+/// A seccomp-style policy as the `filter` call installs it: a list of
+/// calls, one bit per call number, read as an allowlist or a denylist,
+/// plus argument rules for the calls it lets through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct SyscallFilter {
+    pub calls: u64,
+    pub rules: [ArgRule; MAX_ARG_RULES],
+    pub mode: u32,
+    pub action: u32,
+}
+
+impl SyscallFilter {
+    /// Permits only `calls`, and only when `rules` hold.
+    pub fn allow(calls: &[Syscall], action: u32) -> Self {
+        Self {
+            calls: calls
+                .iter()
+                .fold(0, |mask, call| mask | call_bit(*call as usize)),
+            rules: [ArgRule::default(); MAX_ARG_RULES],
+            mode: FILTER_ALLOW,
+            action,
+        }
+    }
+
+    /// Forbids `calls` and permits everything else.
+    pub fn deny(calls: &[Syscall], action: u32) -> Self {
+        Self {
+            mode: FILTER_DENY,
+            ..Self::allow(calls, action)
+        }
+    }
+
+    /// Rejects fields the kernel does not know, so a filter never means
+    /// less than its author intended.
+    pub fn validate(&self) -> Result<(), Errno> {
+        let known_rules = self.rules.iter().all(|rule| {
+            rule.op == ARG_NONE || (rule.op <= ARG_CLEAR && (rule.arg as usize) < MAX_ARGS)
+        });
+        if self.mode > FILTER_DENY || self.action > FILTER_KILL || !known_rules {
+            return Err(Errno::EINVAL);
+        }
+        Ok(())
+    }
+
+    /// `None` if call `number` with `args` passes, otherwise what the
+    /// caller gets for it.
+    pub fn check(&self, number: usize, args: &[usize; MAX_ARGS]) -> Option<FilterAction> {
+        let listed = self.calls & call_bit(number) != 0;
+        let permitted = listed == (self.mode == FILTER_ALLOW)
+            && self
+                .rules
+                .iter()
+                .filter(|rule| rule.op != ARG_NONE && rule.call as usize == number)
+                .all(|rule| rule.holds(args[rule.arg as usize % MAX_ARGS]));
+        if permitted {
+            None
+        } else if self.action == FILTER_KILL {
+            Some(FilterAction::Kill)
+        } else {
+            Some(FilterAction::Errno)
+        }
+    }
+}
+
+/// Bit for call `number` in a call mask; numbers past 63 have none.
+pub fn call_bit(number: usize) -> u64 {
+    1u64.checked_shl(number as u32).unwrap_or(0)
+}
@@ -1,9 +1,745 @@
-#![no_std]
-
-#[repr(u16)]
-pub enum Syscall {
-    Yield = 0,
-    Send = 1,
-    Receive = 2,
-    Spawn = 3,
+//! The system call ABI shared by the kernel and userspace.
+//!
+//! `syscalls!` below is the only place a call's number and arguments are
+//! written down. It expands into the `Syscall` numbers, the `Kernel` trait
+//! with `dispatch` for the kernel side, and the typed wrappers in `user`.
+//!
+//! Calls pass the number in RAX and up to six arguments in RDI, RSI, RDX,
+//! R10, R8 and R9. RAX comes back with the result, or a negated `Errno`.
+
+#![cfg_attr(not(test), no_std)]
+
+mod errno;
+mod filter;
+mod signal;
+
+use core::fmt;
+
+pub use errno::Errno;
+pub use filter::{
+    call_bit, ArgRule, FilterAction, SyscallFilter, ARG_CLEAR, ARG_EQ, ARG_LT, ARG_NE, ARG_NONE,
+    FILTER_ALLOW, FILTER_DENY, FILTER_ERRNO, FILTER_KILL, MAX_ARG_RULES,
+};
+pub use signal::*;
+
+pub const MAX_ARGS: usize = 6;
+
+/// Largest errno the kernel returns; raw results in `-MAX_ERRNO..0` are
+/// errors.
+const MAX_ERRNO: isize = 4095;
+
+pub type SysResult = Result<usize, Errno>;
+
+/// `open` flags. The low two bits are the access mode.
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_RDWR: u32 = 2;
+pub const O_ACCMODE: u32 = 3;
+pub const O_CREAT: u32 = 0o100;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+
+/// Where `lseek` measures its offset from.
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// Notification bit `notify_wait` reports when a child has exited, like
+/// `SIGCHLD`. IRQs cannot be bound to it.
+pub const NOTIFY_CHILD: u64 = 1 << 63;
+
+/// Aux vector keys `exec` places after envp, as in the SysV ABI.
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+/// Address of 16 random bytes.
+pub const AT_RANDOM: u64 = 25;
+
+/// Time as `ClockGettime` stores it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Timespec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+/// How a child ended, or that it stopped, as `wait` stores it. The
+/// encoding is Linux's: an exit code sits in bits 8 to 15, a killing
+/// signal in the low 7 bits with bit 7 for a core dump, and a stopping
+/// signal in bits 8 to 15 above 0x7F.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitStatus {
+    Exited(u8),
+    Killed(u8),
+    /// Killed by a signal whose default action dumps core.
+    Dumped(u8),
+    Stopped(u8),
+}
+
+impl WaitStatus {
+    pub fn encode(self) -> i32 {
+        match self {
+            WaitStatus::Exited(code) => (code as i32) << 8,
+            WaitStatus::Killed(signal) => (signal & 0x7F) as i32,
+            WaitStatus::Dumped(signal) => (signal & 0x7F) as i32 | 0x80,
+            WaitStatus::Stopped(signal) => (signal as i32) << 8 | 0x7F,
+        }
+    }
+
+    pub fn decode(raw: i32) -> Self {
+        match raw & 0x7F {
+            0 => WaitStatus::Exited((raw >> 8) as u8),
+            0x7F => WaitStatus::Stopped((raw >> 8) as u8),
+            signal if raw & 0x80 != 0 => WaitStatus::Dumped(signal as u8),
+            signal => WaitStatus::Killed(signal as u8),
+        }
+    }
+}
+
+/// Conversion between an argument's type and its register.
+pub trait Arg: Sized {
+    fn from_raw(raw: usize) -> Self;
+    fn into_raw(self) -> usize;
+    /// Writes the register as a value of this type, for traces.
+    fn fmt_raw(raw: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+macro_rules! int_arg {
+    ($($ty:ty),*) => {
+        $(impl Arg for $ty {
+            fn from_raw(raw: usize) -> Self {
+                raw as $ty
+            }
+
+            fn into_raw(self) -> usize {
+                self as usize
+            }
+
+            fn fmt_raw(raw: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&Self::from_raw(raw), f)
+            }
+        })*
+    };
+}
+
+int_arg!(u8, u16, u32, u64, usize, i32, i64, isize);
+
+impl<T> Arg for *const T {
+    fn from_raw(raw: usize) -> Self {
+        raw as *const T
+    }
+
+    fn into_raw(self) -> usize {
+        self as usize
+    }
+
+    fn fmt_raw(raw: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_pointer(raw, f)
+    }
+}
+
+impl<T> Arg for *mut T {
+    fn from_raw(raw: usize) -> Self {
+        raw as *mut T
+    }
+
+    fn into_raw(self) -> usize {
+        self as usize
+    }
+
+    fn fmt_raw(raw: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_pointer(raw, f)
+    }
+}
+
+fn fmt_pointer(raw: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if raw == 0 {
+        f.write_str("NULL")
+    } else {
+        write!(f, "{:#x}", raw)
+    }
+}
+
+/// A call with its raw arguments, shown as `name(arg=value, ...)`.
+#[derive(Clone, Copy, Debug)]
+pub struct Call {
+    pub syscall: Syscall,
+    pub args: [usize; MAX_ARGS],
+}
+
+/// The register value for `result`.
+pub fn encode(result: SysResult) -> isize {
+    match result {
+        Ok(value) => value as isize,
+        Err(errno) => -(errno as isize),
+    }
+}
+
+/// The result a raw register value stands for.
+pub fn decode(raw: isize) -> SysResult {
+    if (-MAX_ERRNO..0).contains(&raw) {
+        Err(Errno::from_raw((-raw) as u16).unwrap_or(Errno::EIO))
+    } else {
+        Ok(raw as usize)
+    }
+}
+
+macro_rules! syscalls {
+    ($(
+        $(#[$doc:meta])*
+        $number:literal => $variant:ident fn $name:ident($($arg:ident: $ty:ty),* $(,)?);
+    )*) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(usize)]
+        pub enum Syscall {
+            $($variant = $number,)*
+        }
+
+        impl Syscall {
+            pub const ALL: &'static [Syscall] = &[$(Syscall::$variant),*];
+
+            pub fn from_raw(number: usize) -> Option<Self> {
+                match number {
+                    $($number => Some(Syscall::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Syscall::$variant => stringify!($name),)*
+                }
+            }
+
+            /// Looks a call up by its `name`.
+            pub fn from_name(name: &str) -> Option<Self> {
+                Self::ALL.iter().copied().find(|call| call.name() == name)
+            }
+        }
+
+        impl fmt::Display for Call {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}(", self.syscall.name())?;
+                match self.syscall {
+                    $(Syscall::$variant => {
+                        let [$($arg,)* ..] = self.args;
+                        let mut _separator = "";
+                        $(
+                            write!(f, "{}{}=", _separator, stringify!($arg))?;
+                            <$ty as Arg>::fmt_raw($arg, f)?;
+                            _separator = ", ";
+                        )*
+                    })*
+                }
+                f.write_str(")")
+            }
+        }
+
+        /// The kernel's side of every call. Calls left unimplemented fail
+        /// with `ENOSYS`.
+        pub trait Kernel {
+            $(
+                $(#[$doc])*
+                fn $name(&self, $($arg: $ty),*) -> SysResult {
+                    $(let _ = $arg;)*
+                    Err(Errno::ENOSYS)
+                }
+            )*
+        }
+
+        /// Decodes call `number`, runs it on `kernel` and encodes the result
+        /// for RAX.
+        pub fn dispatch<K: Kernel + ?Sized>(
+            kernel: &K,
+            number: usize,
+            args: [usize; MAX_ARGS],
+        ) -> isize {
+            let result = match Syscall::from_raw(number) {
+                $(Some(Syscall::$variant) => {
+                    let [$($arg,)* ..] = args;
+                    kernel.$name($(<$ty as Arg>::from_raw($arg)),*)
+                })*
+                None => Err(Errno::ENOSYS),
+            };
+            encode(result)
+        }
+
+        /// Typed wrappers for userspace. They are unsafe because the kernel
+        /// acts on whatever memory the pointer arguments name.
+        pub mod user {
+            #[allow(unused_imports)]
+            use super::{Arg, SigAction, SysResult, Syscall, SyscallFilter, Timespec, MAX_ARGS};
+
+            $(
+                $(#[$doc])*
+                #[allow(clippy::missing_safety_doc)]
+                pub unsafe fn $name($($arg: $ty),*) -> SysResult {
+                    let given: &[usize] = &[$(Arg::into_raw($arg)),*];
+                    let mut args = [0; MAX_ARGS];
+                    args[..given.len()].copy_from_slice(given);
+                    super::decode(unsafe { super::raw::invoke(Syscall::$variant as usize, args) })
+                }
+            )*
+        }
+
+        /// Every wrapper in `user`, callable with raw arguments.
+        #[cfg(test)]
+        const USER_WRAPPERS: &[(Syscall, unsafe fn([usize; MAX_ARGS]) -> SysResult)] = &[$(
+            (Syscall::$variant, {
+                unsafe fn call(args: [usize; MAX_ARGS]) -> SysResult {
+                    let [$($arg,)* ..] = args;
+                    unsafe { user::$name($(<$ty as Arg>::from_raw($arg)),*) }
+                }
+                call
+            }),
+        )*];
+
+        /// A kernel that records each call it receives and returns its
+        /// number.
+        #[cfg(test)]
+        struct Echo;
+
+        #[cfg(test)]
+        impl Kernel for Echo {
+            $(
+                fn $name(&self, $($arg: $ty),*) -> SysResult {
+                    tests::record(Syscall::$variant, &[$(Arg::into_raw($arg)),*]);
+                    Ok($number)
+                }
+            )*
+        }
+    };
+}
+
+syscalls! {
+    /// Reads up to `len` bytes at the descriptor's offset and advances it.
+    /// Returns 0 at the end of the file.
+    0 => Read fn read(fd: u32, buf: *mut u8, len: usize);
+    /// Writes at the descriptor's offset, or at the end with `O_APPEND`,
+    /// and returns how many bytes were taken.
+    1 => Write fn write(fd: u32, buf: *const u8, len: usize);
+    /// Opens the NUL-terminated absolute `path` and returns the lowest free
+    /// descriptor.
+    2 => Open fn open(path: *const u8, flags: u32);
+    3 => Close fn close(fd: u32);
+    /// Copies the caller, sharing its memory copy-on-write and its open
+    /// files. Returns the child's pid, and 0 in the child.
+    4 => Fork fn fork();
+    /// Replaces the caller's image with the ELF executable at the
+    /// NUL-terminated `path`. `argv` and `envp` are NULL-terminated arrays
+    /// of strings, either may be NULL for none; the new image finds them on
+    /// its stack with the aux vector. Only returns on failure.
+    5 => Exec fn exec(path: *const u8, argv: *const *const u8, envp: *const *const u8);
+    /// Waits for child `pid`, or any child if 0, to exit or stop, stores
+    /// its `WaitStatus` at `status` unless that is null, and returns its
+    /// pid. Each stop is reported once; with only stopped children left
+    /// it fails with `EAGAIN`, as nothing could resume them meanwhile.
+    6 => Wait fn wait(pid: u32, status: *mut i32);
+    /// Ends the caller with `code`; the low 8 bits reach the parent.
+    7 => Exit fn exit(code: i32);
+    /// Blocks the caller for `ticks` timer ticks.
+    8 => Sleep fn sleep(ticks: u64);
+    9 => Socket fn socket(domain: u32, kind: u32);
+    10 => ClockGettime fn clock_gettime(clock: u32, ts: *mut Timespec);
+    /// Binds IRQ `line` to the caller, which must hold `DriverIo`. Each
+    /// interrupt masks the line and signals `bits` until `irq_ack`.
+    11 => IrqBind fn irq_bind(line: u8, bits: u64);
+    12 => IrqAck fn irq_ack(line: u8);
+    13 => IrqUnbind fn irq_unbind(line: u8);
+    /// Stores the caller's pending notification bits at `bits` and clears
+    /// them; `NOTIFY_CHILD` among them means a child exited. With none
+    /// pending it stores 0 and blocks; the caller retries once woken.
+    14 => NotifyWait fn notify_wait(bits: *mut u64);
+    /// Returns the lowest free descriptor, sharing `fd`'s file and offset.
+    15 => Dup fn dup(fd: u32);
+    /// Makes `new` share `old`'s file, closing whatever `new` held first.
+    16 => Dup2 fn dup2(old: u32, new: u32);
+    /// Moves the descriptor's offset and returns the new one.
+    17 => Lseek fn lseek(fd: u32, offset: i64, whence: u32);
+    /// Logs every call whose bit is set in `calls` (bit n for call n)
+    /// that process `pid`, or any process if 0, makes from now on. A zero
+    /// mask stops tracing `pid`. Tracing others needs `Capability::Trace`.
+    18 => Trace fn trace(pid: u32, calls: u64);
+    /// Adds `filter` to the caller's syscall filters. Every installed
+    /// filter must pass each later call, in the caller and in the children
+    /// it forks from then on; none can be removed.
+    19 => Filter fn filter(filter: *const SyscallFilter);
+    /// Sends `signal` to process `pid`. The caller must be root or have
+    /// the target's uid. Signal 0 only checks that it could. Init only
+    /// receives signals it has a handler for.
+    20 => Kill fn kill(pid: u32, signal: u32);
+    /// Installs `action` for `signal` unless it is null, after storing the
+    /// previous one at `old` unless that is null. `SIGKILL` and `SIGSTOP`
+    /// cannot be changed.
+    21 => Sigaction fn sigaction(signal: u32, action: *const SigAction, old: *mut SigAction);
+    /// Changes the blocked set with `set` as `how` says, unless `set` is
+    /// null, after storing the previous set at `old` unless that is null.
+    22 => Sigprocmask fn sigprocmask(how: u32, set: *const u64, old: *mut u64);
+    /// Returns from a signal handler: restores the registers and blocked
+    /// set saved on the signal frame below the stack pointer. A handler's
+    /// `restorer` calls it; it does not return to its caller.
+    23 => Sigreturn fn sigreturn();
+}
+
+mod raw {
+    use super::MAX_ARGS;
+
+    #[cfg(all(target_arch = "x86_64", not(test)))]
+    pub unsafe fn invoke(number: usize, args: [usize; MAX_ARGS]) -> isize {
+        let ret: isize;
+        unsafe {
+            core::arch::asm!(
+                "syscall",
+                inlateout("rax") number as isize => ret,
+                in("rdi") args[0],
+                in("rsi") args[1],
+                in("rdx") args[2],
+                in("r10") args[3],
+                in("r8") args[4],
+                in("r9") args[5],
+                lateout("rcx") _,
+                lateout("r11") _,
+                options(nostack)
+            );
+        }
+        ret
+    }
+
+    #[cfg(all(not(target_arch = "x86_64"), not(test)))]
+    pub unsafe fn invoke(_number: usize, _args: [usize; MAX_ARGS]) -> isize {
+        -(super::Errno::ENOSYS as isize)
+    }
+
+    /// Tests go straight into `dispatch` instead of a real kernel.
+    #[cfg(test)]
+    pub unsafe fn invoke(number: usize, args: [usize; MAX_ARGS]) -> isize {
+        super::dispatch(&super::Echo, number, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    std::thread_local! {
+        static RECEIVED: RefCell<Vec<(Syscall, Vec<usize>)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub(super) fn record(call: Syscall, args: &[usize]) {
+        RECEIVED.with(|received| received.borrow_mut().push((call, args.to_vec())));
+    }
+
+    fn take_received() -> Vec<(Syscall, Vec<usize>)> {
+        RECEIVED.with(|received| core::mem::take(&mut *received.borrow_mut()))
+    }
+
+    #[test]
+    fn numbers_are_the_abi() {
+        let pinned = [
+            (Syscall::Read, 0),
+            (Syscall::Write, 1),
+            (Syscall::Open, 2),
+            (Syscall::Close, 3),
+            (Syscall::Fork, 4),
+            (Syscall::Exec, 5),
+            (Syscall::Wait, 6),
+            (Syscall::Exit, 7),
+            (Syscall::Sleep, 8),
+            (Syscall::Socket, 9),
+            (Syscall::ClockGettime, 10),
+            (Syscall::IrqBind, 11),
+            (Syscall::IrqAck, 12),
+            (Syscall::IrqUnbind, 13),
+            (Syscall::NotifyWait, 14),
+            (Syscall::Dup, 15),
+            (Syscall::Dup2, 16),
+            (Syscall::Lseek, 17),
+            (Syscall::Trace, 18),
+            (Syscall::Filter, 19),
+            (Syscall::Kill, 20),
+            (Syscall::Sigaction, 21),
+            (Syscall::Sigprocmask, 22),
+            (Syscall::Sigreturn, 23),
+        ];
+        assert_eq!(Syscall::ALL.len(), pinned.len());
+        for (call, number) in pinned {
+            assert_eq!(call as usize, number, "{} moved", call.name());
+            assert_eq!(Syscall::from_raw(number), Some(call));
+        }
+    }
+
+    #[test]
+    fn every_user_wrapper_reaches_its_kernel_handler() {
+        assert_eq!(USER_WRAPPERS.len(), Syscall::ALL.len());
+        for (call, wrapper) in USER_WRAPPERS {
+            let args = core::array::from_fn(|i| *call as usize * 16 + i + 1);
+            let result = unsafe { wrapper(args) };
+            assert_eq!(result, Ok(*call as usize), "{}", call.name());
+
+            let received = take_received();
+            assert_eq!(received.len(), 1, "{}", call.name());
+            let (handled, seen) = &received[0];
+            assert_eq!(handled, call);
+            assert_eq!(seen[..], args[..seen.len()], "{} arguments", call.name());
+        }
+    }
+
+    #[test]
+    fn unknown_numbers_and_default_handlers_fail_with_enosys() {
+        struct Nothing;
+        impl Kernel for Nothing {}
+
+        let no_args = [0; MAX_ARGS];
+        assert_eq!(decode(dispatch(&Nothing, 64, no_args)), Err(Errno::ENOSYS));
+        for call in Syscall::ALL {
+            let raw = dispatch(&Nothing, *call as usize, no_args);
+            assert_eq!(decode(raw), Err(Errno::ENOSYS), "{}", call.name());
+        }
+    }
+
+    #[test]
+    fn results_round_trip_through_the_register() {
+        for result in [Ok(0), Ok(42), Ok(isize::MAX as usize)] {
+            assert_eq!(decode(encode(result)), result);
+        }
+        for errno in Errno::ALL {
+            assert_eq!(decode(encode(Err(errno))), Err(errno), "{}", errno);
+        }
+        // Large "negative" values outside the errno range are results.
+        assert_eq!(decode(-4096), Ok((-4096isize) as usize));
+    }
+
+    #[test]
+    fn calls_show_their_arguments_by_type() {
+        let call = |syscall, given: &[usize]| {
+            let mut args = [0; MAX_ARGS];
+            args[..given.len()].copy_from_slice(given);
+            Call { syscall, args }.to_string()
+        };
+        assert_eq!(
+            call(Syscall::Write, &[1, 0x8000_1000, 12]),
+            "write(fd=1, buf=0x80001000, len=12)"
+        );
+        assert_eq!(call(Syscall::Wait, &[0, 0]), "wait(pid=0, status=NULL)");
+        assert_eq!(
+            call(Syscall::Lseek, &[3, -8i64 as usize, 2]),
+            "lseek(fd=3, offset=-8, whence=2)"
+        );
+        assert_eq!(call(Syscall::Exit, &[u32::MAX as usize]), "exit(code=-1)");
+        assert_eq!(call(Syscall::Fork, &[7, 7]), "fork()");
+    }
+
+    #[test]
+    fn calls_are_found_by_name() {
+        for call in Syscall::ALL {
+            assert_eq!(Syscall::from_name(call.name()), Some(*call));
+        }
+        assert_eq!(Syscall::from_name("nonexistent"), None);
+    }
+
+    #[test]
+    fn allowlists_permit_only_their_calls() {
+        let filter = SyscallFilter::allow(&[Syscall::Read, Syscall::Write], FILTER_ERRNO);
+        let args = [0; MAX_ARGS];
+        assert_eq!(filter.check(Syscall::Write as usize, &args), None);
+        assert_eq!(
+            filter.check(Syscall::Open as usize, &args),
+            Some(FilterAction::Errno)
+        );
+        assert_eq!(filter.check(64, &args), Some(FilterAction::Errno));
+    }
+
+    #[test]
+    fn denylists_reject_only_their_calls() {
+        let filter = SyscallFilter::deny(&[Syscall::Fork, Syscall::Exec], FILTER_KILL);
+        let args = [0; MAX_ARGS];
+        assert_eq!(
+            filter.check(Syscall::Exec as usize, &args),
+            Some(FilterAction::Kill)
+        );
+        assert_eq!(filter.check(Syscall::Read as usize, &args), None);
+    }
+
+    #[test]
+    fn argument_rules_constrain_permitted_calls() {
+        let mut filter = SyscallFilter::allow(&[Syscall::Open, Syscall::Write], FILTER_ERRNO);
+        filter.rules[0] = ArgRule {
+            call: Syscall::Open as u32,
+            arg: 1,
+            op: ARG_CLEAR,
+            value: (O_CREAT | O_TRUNC | O_ACCMODE) as u64,
+        };
+        filter.rules[1] = ArgRule {
+            call: Syscall::Write as u32,
+            arg: 0,
+            op: ARG_LT,
+            value: 3,
+        };
+        let call = |syscall: Syscall, given: &[usize]| {
+            let mut args = [0; MAX_ARGS];
+            args[..given.len()].copy_from_slice(given);
+            filter.check(syscall as usize, &args)
+        };
+        assert_eq!(call(Syscall::Open, &[0x1000, O_RDONLY as usize]), None);
+        assert_eq!(
+            call(Syscall::Open, &[0x1000, (O_WRONLY | O_CREAT) as usize]),
+            Some(FilterAction::Errno)
+        );
+        assert_eq!(call(Syscall::Write, &[2, 0x1000, 4]), None);
+        assert_eq!(
+            call(Syscall::Write, &[3, 0x1000, 4]),
+            Some(FilterAction::Errno)
+        );
+    }
+
+    #[test]
+    fn filters_with_unknown_fields_are_rejected() {
+        let good = SyscallFilter::allow(&[Syscall::Read], FILTER_KILL);
+        assert_eq!(good.validate(), Ok(()));
+        for bad in [
+            SyscallFilter { mode: 2, ..good },
+            SyscallFilter { action: 2, ..good },
+        ] {
+            assert_eq!(bad.validate(), Err(Errno::EINVAL));
+        }
+        let mut bad_rule = good;
+        bad_rule.rules[3] = ArgRule {
+            call: 0,
+            arg: MAX_ARGS as u16,
+            op: ARG_EQ,
+            value: 0,
+        };
+        assert_eq!(bad_rule.validate(), Err(Errno::EINVAL));
+        bad_rule.rules[3] = ArgRule {
+            arg: 0,
+            op: ARG_CLEAR + 1,
+            ..bad_rule.rules[3]
+        };
+        assert_eq!(bad_rule.validate(), Err(Errno::EINVAL));
+    }
+
+    #[test]
+    fn wait_statuses_use_the_linux_encoding() {
+        assert_eq!(WaitStatus::Exited(0).encode(), 0);
+        assert_eq!(WaitStatus::Exited(3).encode(), 0x300);
+        assert_eq!(WaitStatus::Killed(11).encode(), 11);
+        assert_eq!(WaitStatus::Dumped(11).encode(), 0x8B);
+        assert_eq!(WaitStatus::Stopped(19).encode(), 0x137F);
+        for status in [
+            WaitStatus::Exited(0),
+            WaitStatus::Exited(255),
+            WaitStatus::Killed(9),
+            WaitStatus::Dumped(6),
+            WaitStatus::Stopped(20),
+        ] {
+            assert_eq!(WaitStatus::decode(status.encode()), status);
+        }
+    }
+
+    fn handler(address: usize) -> SigAction {
+        SigAction {
+            handler: address,
+            ..SigAction::default()
+        }
+    }
+
+    #[test]
+    fn signals_default_to_their_linux_actions() {
+        let mut state = SignalState::default();
+        for signal in [SIGCHLD, SIGCONT, SIGTERM, SIGSEGV, SIGTSTP, SIGKILL] {
+            state.send(signal);
+        }
+        // SIGCHLD and SIGCONT are ignored by default, so never pending.
+        assert_eq!(state.dequeue(), Some((SIGKILL, Delivery::Terminate)));
+        assert_eq!(state.dequeue(), Some((SIGSEGV, Delivery::Core)));
+        assert_eq!(state.dequeue(), Some((SIGTERM, Delivery::Terminate)));
+        assert_eq!(state.dequeue(), Some((SIGTSTP, Delivery::Stop)));
+        assert_eq!(state.dequeue(), None);
+        assert_eq!(state.pending, 0);
+
+        state.send(SIGSTOP);
+        state.send(SIGCONT);
+        assert_eq!(state.pending, 0);
+    }
+
+    #[test]
+    fn blocked_signals_wait_until_unblocked() {
+        let mut state = SignalState::default();
+        assert_eq!(
+            state.set_blocked(SIG_BLOCK, sig_bit(SIGUSR1) | sig_bit(SIGKILL)),
+            Ok(0)
+        );
+        assert_eq!(state.blocked, sig_bit(SIGUSR1));
+        state.send(SIGUSR1);
+        assert_eq!(state.dequeue(), None);
+        assert_eq!(
+            state.set_blocked(SIG_UNBLOCK, sig_bit(SIGUSR1)),
+            Ok(sig_bit(SIGUSR1))
+        );
+        assert_eq!(state.dequeue(), Some((SIGUSR1, Delivery::Terminate)));
+        assert_eq!(state.set_blocked(3, 0), Err(Errno::EINVAL));
+    }
+
+    #[test]
+    fn peeking_leaves_signals_pending() {
+        let mut state = SignalState::default();
+        state.set_action(SIGUSR1, handler(0x1000)).unwrap();
+        state.send(SIGUSR1);
+        state.send(SIGTERM);
+        state.set_blocked(SIG_BLOCK, sig_bit(SIGUSR1)).unwrap();
+        assert_eq!(state.peek(), Some((SIGTERM, Delivery::Terminate)));
+        assert_eq!(state.peek(), state.dequeue());
+
+        state.set_action(SIGUSR1, handler(SIG_IGN)).unwrap();
+        state.send(SIGUSR1);
+        assert_eq!(state.peek(), None);
+        assert_eq!(state.pending, sig_bit(SIGUSR1));
+    }
+
+    #[test]
+    fn handlers_run_with_their_mask_and_reset_on_exec() {
+        let mut state = SignalState::default();
+        let action = SigAction {
+            mask: sig_bit(SIGUSR2),
+            ..handler(0x1000)
+        };
+        assert_eq!(state.set_action(SIGUSR1, action), Ok(SigAction::default()));
+        assert_eq!(state.set_action(SIGKILL, action), Err(Errno::EINVAL));
+        assert_eq!(state.set_action(NSIG, action), Err(Errno::EINVAL));
+        state.send(SIGUSR1);
+        assert_eq!(state.dequeue(), Some((SIGUSR1, Delivery::Handle(action))));
+        assert_eq!(state.enter_handler(SIGUSR1, &action), 0);
+        assert_eq!(state.blocked, sig_bit(SIGUSR1) | sig_bit(SIGUSR2));
+
+        state.send(SIGUSR2);
+        let child = state.fork();
+        assert_eq!(child.pending, 0);
+        assert_eq!(child.action(SIGUSR1), action);
+
+        state.set_action(SIGINT, handler(SIG_IGN)).unwrap();
+        state.exec();
+        assert_eq!(state.action(SIGUSR1), SigAction::default());
+        assert!(state.ignores(SIGINT));
+        assert_eq!(state.pending, sig_bit(SIGUSR2));
+    }
+
+    #[test]
+    fn forced_signals_override_blocking_and_ignoring() {
+        let mut state = SignalState::default();
+        state.set_action(SIGSEGV, handler(SIG_IGN)).unwrap();
+        state.send(SIGSEGV);
+        assert_eq!(state.pending, 0);
+        state.set_blocked(SIG_SETMASK, sig_bit(SIGBUS)).unwrap();
+        state.force(SIGSEGV);
+        state.force(SIGBUS);
+        assert_eq!(state.blocked, 0);
+        assert_eq!(state.dequeue(), Some((SIGBUS, Delivery::Core)));
+        assert_eq!(state.dequeue(), Some((SIGSEGV, Delivery::Core)));
+    }
 }
@@ -0,0 +1,253 @@
+use crate::Errno;
+
+/// Signal numbers, as on Linux. Valid signals are 1 to `NSIG - 1`.
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGTTOU: u32 = 22;
+pub const SIGSYS: u32 = 31;
+pub const NSIG: u32 = 32;
+
+/// `SigAction::handler` values that are not addresses.
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// `SigAction::flags`.
+/// The signal is not blocked while its own handler runs.
+pub const SA_NODEFER: u64 = 0x4000_0000;
+/// The action goes back to `SIG_DFL` once the handler is entered.
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// `sigprocmask` operations.
+pub const SIG_BLOCK: u32 = 0;
+pub const SIG_UNBLOCK: u32 = 1;
+pub const SIG_SETMASK: u32 = 2;
+
+/// Signals that can be neither caught, ignored nor blocked.
+pub const UNBLOCKABLE: u64 = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+const STOPS: u64 = sig_bit(SIGSTOP) | sig_bit(SIGTSTP) | sig_bit(SIGTTIN) | sig_bit(SIGTTOU);
+
+/// Bit for `signal` in a signal set: bit n - 1 for signal n.
+pub const fn sig_bit(signal: u32) -> u64 {
+    1u64 << ((signal - 1) & 63)
+}
+
+/// How a process wants `signal` handled, as `sigaction` takes it. The
+/// handler runs with `mask` added to the blocked set and returns to
+/// `restorer`, which must issue `sigreturn`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: u64,
+    pub restorer: usize,
+    pub mask: u64,
+}
+
+/// What a signal does to a process that has not asked for anything else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminate, with the core flag set in the wait status.
+    Core,
+    Ignore,
+    Stop,
+    /// Resume if stopped, which happens as the signal is sent.
+    Continue,
+}
+
+pub fn default_action(signal: u32) -> DefaultAction {
+    match signal {
+        SIGCHLD => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGSYS => {
+            DefaultAction::Core
+        }
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// What delivering a signal comes down to, once ignored ones are dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Terminate,
+    Core,
+    Stop,
+    /// Run this handler on a signal frame.
+    Handle(SigAction),
+}
+
+/// The signal state of one process: what is pending, what is blocked and
+/// the action for each signal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SignalState {
+    pub pending: u64,
+    pub blocked: u64,
+    actions: [SigAction; NSIG as usize],
+}
+
+impl SignalState {
+    pub fn action(&self, signal: u32) -> SigAction {
+        self.actions[signal as usize % NSIG as usize]
+    }
+
+    /// Whether sending `signal` now would be dropped unseen.
+    pub fn ignores(&self, signal: u32) -> bool {
+        match self.action(signal).handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(
+                default_action(signal),
+                DefaultAction::Ignore | DefaultAction::Continue
+            ),
+            _ => false,
+        }
+    }
+
+    /// Marks `signal` pending, unless it is ignored and not blocked. A
+    /// stop signal cancels a pending `SIGCONT` and the other way round.
+    pub fn send(&mut self, signal: u32) {
+        let bit = sig_bit(signal);
+        if signal == SIGCONT {
+            self.pending &= !STOPS;
+        } else if STOPS & bit != 0 {
+            self.pending &= !sig_bit(SIGCONT);
+        }
+        if self.blocked & bit != 0 || !self.ignores(signal) {
+            self.pending |= bit;
+        }
+    }
+
+    /// Sends `signal` in a way the process cannot refuse, as for a fault:
+    /// if it is blocked or ignored, it gets its default action back.
+    pub fn force(&mut self, signal: u32) {
+        let bit = sig_bit(signal);
+        if self.blocked & bit != 0 || self.action(signal).handler == SIG_IGN {
+            self.blocked &= !bit;
+            self.actions[signal as usize] = SigAction::default();
+        }
+        self.send(signal);
+    }
+
+    /// Installs `action` for `signal` and returns the previous one.
+    /// Ignoring a signal discards it if pending.
+    pub fn set_action(&mut self, signal: u32, action: SigAction) -> Result<SigAction, Errno> {
+        if !(1..NSIG).contains(&signal) || UNBLOCKABLE & sig_bit(signal) != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let old = core::mem::replace(&mut self.actions[signal as usize], action);
+        if self.ignores(signal) {
+            self.pending &= !sig_bit(signal);
+        }
+        Ok(old)
+    }
+
+    /// Changes the blocked set as `sigprocmask` does and returns the old
+    /// one. `SIGKILL` and `SIGSTOP` stay unblocked whatever `set` says.
+    pub fn set_blocked(&mut self, how: u32, set: u64) -> Result<u64, Errno> {
+        let old = self.blocked;
+        self.blocked = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(Errno::EINVAL),
+        } & !UNBLOCKABLE;
+        Ok(old)
+    }
+
+    /// Takes the lowest pending signal that is not blocked and whose
+    /// delivery does something, dropping ignored ones on the way.
+    pub fn dequeue(&mut self) -> Option<(u32, Delivery)> {
+        loop {
+            let deliverable = self.pending & !self.blocked;
+            if deliverable == 0 {
+                return None;
+            }
+            let signal = deliverable.trailing_zeros() + 1;
+            self.pending &= !sig_bit(signal);
+            if let Some(delivery) = self.delivery(signal) {
+                return Some((signal, delivery));
+            }
+        }
+    }
+
+    /// What `dequeue` would return, leaving everything pending.
+    pub fn peek(&self) -> Option<(u32, Delivery)> {
+        let mut deliverable = self.pending & !self.blocked;
+        while deliverable != 0 {
+            let signal = deliverable.trailing_zeros() + 1;
+            deliverable &= !sig_bit(signal);
+            if let Some(delivery) = self.delivery(signal) {
+                return Some((signal, delivery));
+            }
+        }
+        None
+    }
+
+    /// What delivering `signal` does now; `None` if it is ignored.
+    fn delivery(&self, signal: u32) -> Option<Delivery> {
+        let action = self.action(signal);
+        match action.handler {
+            SIG_IGN => None,
+            SIG_DFL => match default_action(signal) {
+                DefaultAction::Terminate => Some(Delivery::Terminate),
+                DefaultAction::Core => Some(Delivery::Core),
+                DefaultAction::Stop => Some(Delivery::Stop),
+                DefaultAction::Ignore | DefaultAction::Continue => None,
+            },
+            _ => Some(Delivery::Handle(action)),
+        }
+    }
+
+    /// Blocks what the handler for `signal` asks for while it runs and
+    /// returns the mask `sigreturn` restores.
+    pub fn enter_handler(&mut self, signal: u32, action: &SigAction) -> u64 {
+        let old = self.blocked;
+        let own = if action.flags & SA_NODEFER != 0 {
+            0
+        } else {
+            sig_bit(signal)
+        };
+        self.blocked = (old | action.mask | own) & !UNBLOCKABLE;
+        if action.flags & SA_RESETHAND != 0 {
+            self.actions[signal as usize] = SigAction::default();
+        }
+        old
+    }
+
+    /// The state a forked child starts with: the same actions and mask,
+    /// nothing pending.
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: 0,
+            ..*self
+        }
+    }
+
+    /// Handlers belong to the old image: `exec` resets them to the default
+    /// action. Ignored signals stay ignored.
+    pub fn exec(&mut self) {
+        for action in &mut self.actions {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
 fn main() {
-    println!("cargo:rustc-link-arg=-T../../linker/linker.ld");
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rustc-link-arg=-T{manifest_dir}/../../linker/linker.ld");
+    println!("cargo:rerun-if-changed=../../linker/linker.ld");
 }
@@ -0,0 +1,35 @@
+use crate::drivers::ioapic::common::IrqRouting;
+use crate::smp::common::{CpuDescriptor, CpuList};
+
+/// Multiple APIC Description Table contents the kernel cares about.
+#[derive(Clone, Debug)]
+pub struct Madt {
+    cpus: CpuList,
+    /// IOAPICs and ISA source overrides; `None` when there is no IOAPIC.
+    routing: Option<IrqRouting>,
+}
+
+impl Madt {
+    /// Parses a MADT (signature `APIC`) into its enabled CPUs and interrupt
+    /// routing. The CPU list validates the length and checksum, so it goes
+    /// first.
+    pub fn parse(table: &[u8]) -> Result<Self, &'static str> {
+        let cpus = CpuList::from_madt(table)?;
+        Ok(Self {
+            cpus,
+            routing: IrqRouting::from_madt(table).ok(),
+        })
+    }
+
+    pub fn lapic_address(&self) -> u64 {
+        self.cpus.lapic_address
+    }
+
+    pub fn cpus(&self) -> impl Iterator<Item = &CpuDescriptor> {
+        self.cpus.iter()
+    }
+
+    pub fn routing(&self) -> Option<&IrqRouting> {
+        self.routing.as_ref()
+    }
+}
@@ -0,0 +1,176 @@
+pub mod madt;
+
+use multiboot2::BootInformation;
+use spin::Mutex;
+
+use crate::arch::x86::paging;
+
+pub use madt::Madt;
+
+const SDT_HEADER_LEN: usize = 36;
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const BIOS_AREA_START: usize = 0x000E_0000;
+const BIOS_AREA_END: usize = 0x0010_0000;
+
+/// Offset of the CMOS century register index in the FADT.
+const FADT_CENTURY_OFFSET: usize = 108;
+/// Offset of the base address in the HPET table's generic address structure.
+const HPET_BASE_OFFSET: usize = SDT_HEADER_LEN + 8;
+
+static MADT: Mutex<Option<Madt>> = Mutex::new(None);
+static HPET_ADDRESS: Mutex<Option<usize>> = Mutex::new(None);
+static CENTURY_REGISTER: Mutex<Option<u8>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug)]
+enum RootTable {
+    Rsdt(usize),
+    Xsdt(usize),
+}
+
+pub fn init(boot_info: &BootInformation) {
+    let root = match find_root_table(boot_info) {
+        Some(root) => root,
+        None => {
+            println!("acpi: no RSDP found, assuming uniprocessor");
+            return;
+        }
+    };
+
+    let madt = find_table(root, b"APIC")
+        .and_then(|addr| unsafe { map_table(addr) })
+        .ok_or("not found")
+        .and_then(Madt::parse);
+
+    match madt {
+        Ok(madt) => {
+            println!(
+                "acpi: MADT lapic={:#x} cpus={}",
+                madt.lapic_address(),
+                madt.cpus().count()
+            );
+            *MADT.lock() = Some(madt);
+        }
+        Err(err) => println!("acpi: no usable MADT ({})", err),
+    }
+
+    let hpet = find_table(root, b"HPET")
+        .and_then(|addr| unsafe { map_table(addr) })
+        .and_then(parse_hpet);
+    if let Some(address) = hpet {
+        println!("acpi: HPET at {:#x}", address);
+        *HPET_ADDRESS.lock() = Some(address);
+    }
+
+    let century = find_table(root, b"FACP")
+        .and_then(|addr| unsafe { map_table(addr) })
+        .and_then(|fadt| fadt.get(FADT_CENTURY_OFFSET).copied())
+        .filter(|&register| register != 0);
+    *CENTURY_REGISTER.lock() = century;
+}
+
+pub fn madt() -> Option<Madt> {
+    MADT.lock().clone()
+}
+
+pub fn hpet_address() -> Option<usize> {
+    *HPET_ADDRESS.lock()
+}
+
+/// CMOS index of the RTC century register, if the FADT names one.
+pub fn century_register() -> Option<u8> {
+    *CENTURY_REGISTER.lock()
+}
+
+/// Memory-mapped HPET register block, if it sits below 4 GiB.
+fn parse_hpet(table: &[u8]) -> Option<usize> {
+    let raw = table.get(HPET_BASE_OFFSET..HPET_BASE_OFFSET + 8)?;
+    let address = u64::from_le_bytes(raw.try_into().ok()?);
+    (address != 0 && address <= u32::MAX as u64).then_some(address as usize)
+}
+
+fn find_root_table(boot_info: &BootInformation) -> Option<RootTable> {
+    if let Some(tag) = boot_info.rsdp_v2_tag() {
+        return Some(RootTable::Xsdt(tag.xsdt_address()));
+    }
+    if let Some(tag) = boot_info.rsdp_v1_tag() {
+        return Some(RootTable::Rsdt(tag.rsdt_address()));
+    }
+
+    scan_bios_area()
+}
+
+fn scan_bios_area() -> Option<RootTable> {
+    let mut addr = BIOS_AREA_START;
+    while addr < BIOS_AREA_END {
+        let candidate = unsafe { core::slice::from_raw_parts(addr as *const u8, 36) };
+        if &candidate[..8] == RSDP_SIGNATURE && checksum_ok(&candidate[..20]) {
+            let revision = candidate[15];
+            let rsdt =
+                u32::from_le_bytes([candidate[16], candidate[17], candidate[18], candidate[19]]);
+            if revision >= 2 && checksum_ok(candidate) {
+                let xsdt = u64::from_le_bytes([
+                    candidate[24],
+                    candidate[25],
+                    candidate[26],
+                    candidate[27],
+                    candidate[28],
+                    candidate[29],
+                    candidate[30],
+                    candidate[31],
+                ]);
+                if xsdt != 0 && xsdt <= u32::MAX as u64 {
+                    return Some(RootTable::Xsdt(xsdt as usize));
+                }
+            }
+            return Some(RootTable::Rsdt(rsdt as usize));
+        }
+        addr += 16;
+    }
+    None
+}
+
+fn find_table(root: RootTable, signature: &[u8; 4]) -> Option<usize> {
+    let (addr, entry_len) = match root {
+        RootTable::Rsdt(addr) => (addr, 4),
+        RootTable::Xsdt(addr) => (addr, 8),
+    };
+    let table = unsafe { map_table(addr) }?;
+
+    for entry in table[SDT_HEADER_LEN..].chunks_exact(entry_len) {
+        let mut raw = [0u8; 8];
+        raw[..entry_len].copy_from_slice(entry);
+        let child = u64::from_le_bytes(raw);
+        if child == 0 || child > u32::MAX as u64 {
+            continue;
+        }
+
+        paging::identity_map_range(child as usize, SDT_HEADER_LEN, paging::PAGE_WRITABLE);
+        let header = unsafe { core::slice::from_raw_parts(child as *const u8, 4) };
+        if header == signature {
+            return Some(child as usize);
+        }
+    }
+    None
+}
+
+/// Maps an ACPI system description table and returns it as a byte slice
+/// once its length and checksum check out.
+///
+/// # Safety
+/// `addr` must be the physical address of an SDT reported by firmware.
+unsafe fn map_table(addr: usize) -> Option<&'static [u8]> {
+    paging::identity_map_range(addr, SDT_HEADER_LEN, paging::PAGE_WRITABLE);
+    let header = unsafe { core::slice::from_raw_parts(addr as *const u8, SDT_HEADER_LEN) };
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len < SDT_HEADER_LEN {
+        return None;
+    }
+
+    paging::identity_map_range(addr, len, paging::PAGE_WRITABLE);
+    let table = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    checksum_ok(table).then_some(table)
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
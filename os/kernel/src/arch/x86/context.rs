@@ -0,0 +1,35 @@
+core::arch::global_asm!(include_str!("context.S"), options(att_syntax));
+
+/// Registers `context_switch` keeps on the stack: RBP, RBX and R12-R15.
+const SAVED_REGISTERS: usize = 6;
+
+extern "C" {
+    fn context_switch(save: *mut usize, next: usize);
+}
+
+/// Lays out a context on the empty kernel stack ending at `stack_top`,
+/// which must be 16-byte aligned, and returns its stack pointer. Switching
+/// to it calls `entry` with the saved registers zeroed.
+pub fn prepare(stack_top: usize, entry: extern "C" fn() -> !) -> usize {
+    // `entry` starts as if called: RSP + 8 is 16-byte aligned.
+    let return_address = stack_top - 16;
+    let rsp = return_address - SAVED_REGISTERS * 8;
+    unsafe {
+        (return_address as *mut usize).write(entry as usize);
+        core::ptr::write_bytes(rsp as *mut usize, 0, SAVED_REGISTERS);
+    }
+    rsp
+}
+
+/// Stores the calling context's stack pointer at `save` and resumes the
+/// context whose stack pointer is `next`. Returns once another switch
+/// resumes the caller.
+///
+/// # Safety
+///
+/// Interrupts must be off. `next` must come from `prepare`, or be what a
+/// switch stored for a context not resumed since, on a stack that stays
+/// mapped and allocated.
+pub unsafe fn switch(save: *mut usize, next: usize) {
+    context_switch(save, next);
+}
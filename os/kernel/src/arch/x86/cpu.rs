@@ -0,0 +1,220 @@
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use x86::controlregs::{cr4, cr4_write, Cr4};
+use x86::cpuid::CpuId;
+use x86::msr::{rdmsr, wrmsr, IA32_EFER};
+
+use crate::logging::LogLevel;
+
+const EFER_NXE: u64 = 1 << 11;
+/// Intel's advice for how often to retry an RDRAND that returns nothing.
+const RDRAND_RETRIES: usize = 10;
+
+/// CPU features the kernel cares about, as reported by CPUID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Feature {
+    /// No-execute page bit (EFER.NXE).
+    Nx,
+    /// Supervisor-mode execution prevention.
+    Smep,
+    /// Supervisor-mode access prevention.
+    Smap,
+    /// User-mode instruction prevention: SGDT, SIDT, SLDT, SMSW and STR
+    /// fault in ring 3.
+    Umip,
+    Pcid,
+    Xsave,
+    Rdrand,
+    InvariantTsc,
+    TscDeadline,
+    X2Apic,
+    GigabytePages,
+}
+
+impl Feature {
+    const ALL: [Feature; 11] = [
+        Feature::Nx,
+        Feature::Smep,
+        Feature::Smap,
+        Feature::Umip,
+        Feature::Pcid,
+        Feature::Xsave,
+        Feature::Rdrand,
+        Feature::InvariantTsc,
+        Feature::TscDeadline,
+        Feature::X2Apic,
+        Feature::GigabytePages,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Nx => "nx",
+            Feature::Smep => "smep",
+            Feature::Smap => "smap",
+            Feature::Umip => "umip",
+            Feature::Pcid => "pcid",
+            Feature::Xsave => "xsave",
+            Feature::Rdrand => "rdrand",
+            Feature::InvariantTsc => "invariant-tsc",
+            Feature::TscDeadline => "tsc-deadline",
+            Feature::X2Apic => "x2apic",
+            Feature::GigabytePages => "1g-pages",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeatureSet(u32);
+
+impl FeatureSet {
+    pub fn has(self, feature: Feature) -> bool {
+        self.0 & (1 << feature as u8) != 0
+    }
+
+    fn insert(&mut self, feature: Feature) {
+        self.0 |= 1 << feature as u8;
+    }
+}
+
+/// Space-separated feature names, or "none".
+impl fmt::Display for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for feature in Feature::ALL.iter().filter(|feature| self.has(**feature)) {
+            if !first {
+                f.write_str(" ")?;
+            }
+            f.write_str(feature.name())?;
+            first = false;
+        }
+        if first {
+            f.write_str("none")?;
+        }
+        Ok(())
+    }
+}
+
+static DETECTED: AtomicU32 = AtomicU32::new(0);
+static ENABLED: AtomicU32 = AtomicU32::new(0);
+
+/// Detects the boot CPU's features, turns on the protections it has and
+/// logs both sets. Runs before anything else reads `features()`.
+pub fn init() {
+    let detected = detect();
+    DETECTED.store(detected.0, Ordering::Relaxed);
+
+    let mut enabled = FeatureSet::default();
+    for feature in [Feature::Nx, Feature::Smep, Feature::Smap, Feature::Umip] {
+        if detected.has(feature) {
+            enabled.insert(feature);
+        }
+    }
+    ENABLED.store(enabled.0, Ordering::Relaxed);
+    enable_protections();
+
+    klog!(LogLevel::Info, "cpu: features {}", detected);
+    klog!(LogLevel::Info, "cpu: enabled {}", enabled);
+}
+
+/// Applies the protections `init` chose on the calling CPU. Secondary
+/// CPUs are assumed to match the boot CPU.
+pub fn enable_protections() {
+    let enabled = enabled();
+    unsafe {
+        if enabled.has(Feature::Nx) {
+            wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE);
+        }
+        let mut flags = cr4();
+        if enabled.has(Feature::Smep) {
+            flags |= Cr4::CR4_ENABLE_SMEP;
+        }
+        if enabled.has(Feature::Smap) {
+            flags |= Cr4::CR4_ENABLE_SMAP;
+        }
+        if enabled.has(Feature::Umip) {
+            flags |= Cr4::CR4_ENABLE_UMIP;
+        }
+        cr4_write(flags);
+    }
+}
+
+/// Features the boot CPU reports.
+pub fn features() -> FeatureSet {
+    FeatureSet(DETECTED.load(Ordering::Relaxed))
+}
+
+/// Protections turned on in every CPU's control registers.
+pub fn enabled() -> FeatureSet {
+    FeatureSet(ENABLED.load(Ordering::Relaxed))
+}
+
+pub fn has(feature: Feature) -> bool {
+    features().has(feature)
+}
+
+/// Runs `f` with user pages accessible to the kernel. With SMAP on, every
+/// kernel read or write of user memory must go through here.
+pub fn user_access<R>(f: impl FnOnce() -> R) -> R {
+    let smap = enabled().has(Feature::Smap);
+    if smap {
+        unsafe { x86::bits64::rflags::stac() };
+    }
+    let result = f();
+    if smap {
+        unsafe { x86::bits64::rflags::clac() };
+    }
+    result
+}
+
+/// 64 random bits from RDRAND, or `None` without it or if it keeps
+/// running dry.
+pub fn rdrand() -> Option<u64> {
+    if !has(Feature::Rdrand) {
+        return None;
+    }
+    for _ in 0..RDRAND_RETRIES {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn detect() -> FeatureSet {
+    let cpuid = CpuId::new();
+    let mut set = FeatureSet::default();
+    let mut check = |present: bool, feature| {
+        if present {
+            set.insert(feature);
+        }
+    };
+
+    if let Some(info) = cpuid.get_feature_info() {
+        check(info.has_pcid(), Feature::Pcid);
+        check(info.has_xsave(), Feature::Xsave);
+        check(info.has_rdrand(), Feature::Rdrand);
+        check(info.has_tsc_deadline(), Feature::TscDeadline);
+        check(info.has_x2apic(), Feature::X2Apic);
+    }
+    if let Some(info) = cpuid.get_extended_feature_info() {
+        check(info.has_smep(), Feature::Smep);
+        check(info.has_smap(), Feature::Smap);
+        check(info.has_umip(), Feature::Umip);
+    }
+    if let Some(info) = cpuid.get_extended_processor_and_feature_identifiers() {
+        check(info.has_execute_disable(), Feature::Nx);
+        check(info.has_1gib_pages(), Feature::GigabytePages);
+    }
+    if let Some(info) = cpuid.get_advanced_power_mgmt_info() {
+        check(info.has_invariant_tsc(), Feature::InvariantTsc);
+    }
+    set
+}
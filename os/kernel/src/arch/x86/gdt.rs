@@ -1,9 +1,25 @@
 use core::arch::asm;
 
+use super::tss::{self, TaskStateSegment};
+use crate::smp::MAX_CPUS;
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+/// User data sits right below user code, the order SYSRET expects.
+pub const USER_DATA_SELECTOR: u16 = 0x18 | 3;
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
+
+/// First GDT slot holding a per-CPU TSS descriptor. System descriptors are
+/// 16 bytes in long mode, so each CPU takes two slots.
+const TSS_FIRST_ENTRY: usize = 5;
+const GDT_ENTRIES: usize = TSS_FIRST_ENTRY + 2 * MAX_CPUS;
+
+const ACCESS_TSS_AVAILABLE: u8 = 0x89;
+
 #[repr(C, packed)]
 struct GdtDescriptor {
     limit: u16,
-    base: u32,
+    base: u64,
 }
 
 #[repr(C, packed)]
@@ -39,32 +55,74 @@ impl GdtEntry {
             base_high: ((base >> 24) & 0xFF) as u8,
         }
     }
+
+    /// Second half of a 16-byte system descriptor: bits 32-63 of the base.
+    const fn upper_base(base: u64) -> Self {
+        let high = (base >> 32) as u32;
+        Self {
+            limit_low: (high & 0xFFFF) as u16,
+            base_low: (high >> 16) as u16,
+            base_mid: 0,
+            access: 0,
+            granularity: 0,
+            base_high: 0,
+        }
+    }
 }
 
-static mut GDT: [GdtEntry; 3] = [
-    GdtEntry::empty(),
-    GdtEntry::new(0, 0xFFFFF, 0x9A, 0xCF),
-    GdtEntry::new(0, 0xFFFFF, 0x92, 0xCF),
-];
+static mut GDT: [GdtEntry; GDT_ENTRIES] = {
+    let mut gdt = [GdtEntry::empty(); GDT_ENTRIES];
+    // Long-mode code (L set, D clear); base and limit are ignored.
+    gdt[1] = GdtEntry::new(0, 0xFFFFF, 0x9A, 0xA0);
+    gdt[2] = GdtEntry::new(0, 0xFFFFF, 0x92, 0xCF);
+    gdt[3] = GdtEntry::new(0, 0xFFFFF, 0xF2, 0xCF);
+    gdt[4] = GdtEntry::new(0, 0xFFFFF, 0xFA, 0xA0);
+    gdt
+};
 
 pub fn init() {
+    load();
+}
+
+/// Loads the shared GDT and reloads the kernel segments on the calling
+/// CPU. FS and GS are left alone: their bases live in MSRs.
+pub fn load() {
     let gdtr = GdtDescriptor {
-        limit: (core::mem::size_of::<[GdtEntry; 3]>() - 1) as u16,
-        base: unsafe { &GDT as *const _ as u32 },
+        limit: (core::mem::size_of::<[GdtEntry; GDT_ENTRIES]>() - 1) as u16,
+        base: core::ptr::addr_of!(GDT) as u64,
     };
 
     unsafe {
         asm!("lgdt [{0}]", in(reg) &gdtr, options(readonly, nostack, preserves_flags));
         asm!(
-            "mov ax, 0x10",
-            "mov ds, ax",
-            "mov es, ax",
-            "mov fs, ax",
-            "mov gs, ax",
-            "mov ss, ax",
-            "jmp 0x08:2f",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov ss, {data:x}",
+            // No direct far jump in 64-bit mode: return through CS instead.
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
             "2:",
-            options(nostack)
+            data = in(reg) KERNEL_DATA_SELECTOR,
+            code = in(reg) KERNEL_CODE_SELECTOR as u64,
+            tmp = lateout(reg) _,
         );
     }
 }
+
+/// Installs `cpu`'s TSS, with its IST stacks, and loads it into the task
+/// register of the calling CPU.
+pub fn load_tss(cpu: usize) {
+    let tss: &TaskStateSegment = tss::prepare(cpu);
+    let base = tss as *const TaskStateSegment as u64;
+    let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u32;
+    let index = TSS_FIRST_ENTRY + 2 * cpu;
+    let selector = (index * core::mem::size_of::<GdtEntry>()) as u16;
+
+    unsafe {
+        GDT[index] = GdtEntry::new(base as u32, limit, ACCESS_TSS_AVAILABLE, 0);
+        GDT[index + 1] = GdtEntry::upper_base(base);
+        asm!("ltr {0:x}", in(reg) selector, options(nostack, preserves_flags));
+    }
+}
@@ -1,2 +1,26 @@
+pub mod context;
+pub mod cpu;
 pub mod gdt;
 pub mod paging;
+pub mod smp;
+pub mod tss;
+pub mod usermode;
+
+use core::arch::asm;
+
+const EFLAGS_IF: usize = 1 << 9;
+
+/// Runs `f` with interrupts disabled on this CPU, restoring the previous
+/// state afterwards. For locks that interrupt handlers also take.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let flags: usize;
+    unsafe {
+        asm!("pushf", "pop {}", out(reg) flags, options(preserves_flags));
+        x86::irq::disable();
+    }
+    let result = f();
+    if flags & EFLAGS_IF != 0 {
+        unsafe { x86::irq::enable() };
+    }
+    result
+}
@@ -1,28 +1,196 @@
 use core::arch::asm;
 
-#[repr(align(4096))]
-struct PageDirectory([u32; 1024]);
+use spin::Mutex;
 
-static mut PAGE_DIRECTORY: PageDirectory = PageDirectory([0; 1024]);
+pub const PAGE_PRESENT: u64 = 1 << 0;
+pub const PAGE_WRITABLE: u64 = 1 << 1;
+pub const PAGE_USER: u64 = 1 << 2;
+pub const PAGE_WRITE_THROUGH: u64 = 1 << 3;
+pub const PAGE_CACHE_DISABLE: u64 = 1 << 4;
+pub const PAGE_HUGE: u64 = 1 << 7;
+/// Only honoured with EFER.NXE set; reserved otherwise.
+pub const PAGE_NO_EXECUTE: u64 = 1 << 63;
 
-pub fn init_identity_4mb() {
+pub const PAGE_SIZE: usize = 4096;
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+pub const ENTRIES: usize = 512;
+/// Page directories linked in by `start.S`, each covering 1 GiB.
+const DIRECTORIES: usize = 4;
+/// End of what the boot tables can map: the low 4 GiB.
+const MAPPABLE_END: usize = DIRECTORIES * ENTRIES * HUGE_PAGE_SIZE;
+/// 2 MiB pages that can be split into 4 KiB ones.
+const SPLIT_TABLES: usize = 8;
+pub const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const HUGE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFE0_0000;
+
+#[repr(C, align(4096))]
+struct PageTable([u64; ENTRIES]);
+
+static mut SPLIT_POOL: [PageTable; SPLIT_TABLES] =
+    [const { PageTable([0; ENTRIES]) }; SPLIT_TABLES];
+/// Tables handed out from `SPLIT_POOL`; also serialises splitting.
+static SPLIT_USED: Mutex<usize> = Mutex::new(0);
+
+extern "C" {
+    /// Root of the kernel address space, built by `start.S`.
+    static boot_pml4: PageTable;
+    /// Directories for the low 4 GiB, in address order. The first GiB is
+    /// identity-mapped at boot; the rest fills in through
+    /// `identity_map_range`.
+    static mut boot_page_directories: [PageTable; DIRECTORIES];
+}
+
+/// The 2 MiB directory entry covering `addr`, if the boot tables reach it.
+fn directory_entry(addr: usize) -> Option<*mut u64> {
+    if addr >= MAPPABLE_END {
+        return None;
+    }
+    let index = addr / HUGE_PAGE_SIZE;
+    unsafe {
+        let directories = core::ptr::addr_of_mut!(boot_page_directories);
+        let directory = core::ptr::addr_of_mut!((*directories)[index / ENTRIES]);
+        Some(core::ptr::addr_of_mut!((*directory).0[index % ENTRIES]))
+    }
+}
+
+/// Identity-maps every 2 MiB page overlapping `[phys, phys + len)`.
+///
+/// Used for firmware tables and MMIO windows (ACPI, local APIC) that live
+/// outside the boot identity map. Already-present entries are left alone,
+/// and so is anything above 4 GiB.
+pub fn identity_map_range(phys: usize, len: usize, flags: u64) {
+    let first = phys / HUGE_PAGE_SIZE;
+    let last = (phys + len.max(1) - 1) / HUGE_PAGE_SIZE;
+
+    for index in first..=last {
+        let base = index * HUGE_PAGE_SIZE;
+        let Some(entry) = directory_entry(base) else {
+            break;
+        };
+        unsafe {
+            if *entry & PAGE_PRESENT != 0 {
+                continue;
+            }
+            *entry = base as u64 | flags | PAGE_PRESENT | PAGE_HUGE;
+        }
+        invalidate(base);
+    }
+}
+
+/// The 4 KiB entry covering `addr` in a split directory entry.
+fn table_entry(directory_entry: u64, addr: usize) -> *mut u64 {
+    let table = (directory_entry & ADDRESS_MASK) as *mut PageTable;
+    unsafe { core::ptr::addr_of_mut!((*table).0[(addr / PAGE_SIZE) % ENTRIES]) }
+}
+
+/// Whether the kernel can read `addr` without faulting: present in the
+/// current address space and, since SMAP would trap it, not a user page.
+pub fn is_mapped(addr: usize) -> bool {
+    let upper = (addr as isize) >> 47;
+    if upper != 0 && upper != -1 {
+        return false;
+    }
+    leaf_entry(current_root(), addr).is_some_and(|(_, granted)| granted & PAGE_USER == 0)
+}
+
+/// The entry mapping `addr` under the PML4 at `root`, 4 KiB or huge, and
+/// the flags all levels on the way grant: `PAGE_USER` and `PAGE_WRITABLE`
+/// only count if every level sets them. Page tables are reached through
+/// the identity map.
+pub fn leaf_entry(root: usize, addr: usize) -> Option<(*mut u64, u64)> {
+    let mut table = root;
+    let mut granted = PAGE_USER | PAGE_WRITABLE;
+    for shift in [39, 30, 21, 12] {
+        let index = (addr >> shift) % ENTRIES;
+        let entry = unsafe { core::ptr::addr_of_mut!((*(table as *mut PageTable)).0[index]) };
+        let value = unsafe { *entry };
+        if value & PAGE_PRESENT == 0 {
+            return None;
+        }
+        granted &= value;
+        if shift == 12 || value & PAGE_HUGE != 0 {
+            return Some((entry, granted | PAGE_PRESENT));
+        }
+        table = (value & ADDRESS_MASK) as usize;
+    }
+    None
+}
+
+/// Whether ring 3 may read `addr` in the current address space, or write
+/// it when `write` is set.
+pub fn user_accessible(addr: usize, write: bool) -> bool {
+    let required = PAGE_PRESENT | PAGE_USER | if write { PAGE_WRITABLE } else { 0 };
+    leaf_entry(current_root(), addr).is_some_and(|(_, granted)| granted & required == required)
+}
+
+/// Opens the 4 KiB pages overlapping `[addr, addr + len)` to ring 3. The
+/// 2 MiB pages around them are split first, so their other pages stay
+/// kernel-only. The range must already be mapped.
+pub fn map_user(addr: usize, len: usize) -> Result<(), &'static str> {
+    let first = addr / PAGE_SIZE;
+    let last = (addr + len.max(1) - 1) / PAGE_SIZE;
+    let mut used = SPLIT_USED.lock();
+
+    for page in (first..=last).map(|index| index * PAGE_SIZE) {
+        let entry = directory_entry(page).ok_or("user page above the mappable range")?;
+        unsafe {
+            if *entry & PAGE_PRESENT == 0 {
+                return Err("user page is not mapped");
+            }
+            if *entry & PAGE_HUGE != 0 {
+                if *used == SPLIT_TABLES {
+                    return Err("no page table left to split a 2 MiB page");
+                }
+                let table = core::ptr::addr_of_mut!(SPLIT_POOL[*used]);
+                *used += 1;
+                split(entry, &mut *table);
+            }
+            *table_entry(*entry, page) |= PAGE_USER;
+        }
+        invalidate(page);
+    }
+    Ok(())
+}
+
+/// Replaces a 2 MiB entry by `table`, mapping the same memory with the
+/// same flags in 4 KiB pages.
+unsafe fn split(entry: *mut u64, table: &mut PageTable) {
+    let base = *entry & HUGE_ADDRESS_MASK;
+    // Bit 7 is PAT, not size, in a 4 KiB entry.
+    let flags = *entry & !ADDRESS_MASK & !PAGE_HUGE;
+    for (index, page) in table.0.iter_mut().enumerate() {
+        *page = (base + (index * PAGE_SIZE) as u64) | flags;
+    }
+    *entry = table as *mut PageTable as u64 | PAGE_PRESENT | PAGE_WRITABLE | PAGE_USER;
+    invalidate(base as usize);
+}
+
+/// Physical address of the kernel PML4, for loading into CR3 on application
+/// processors. It sits in the low 4 GiB, so a 32-bit CR3 load reaches it.
+pub fn root_table_address() -> usize {
+    core::ptr::addr_of!(boot_pml4) as usize
+}
+
+/// Physical address of the PML4 the calling CPU translates through.
+pub fn current_root() -> usize {
+    let cr3: u64;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    }
+    (cr3 & ADDRESS_MASK) as usize
+}
+
+/// Switches the calling CPU to the PML4 at `root`, flushing the TLB.
+///
+/// # Safety
+///
+/// `root` must map the kernel the way the boot tables do.
+pub unsafe fn load_root(root: usize) {
+    asm!("mov cr3, {}", in(reg) root as u64, options(nostack, preserves_flags));
+}
+
+pub fn invalidate(addr: usize) {
     unsafe {
-        PAGE_DIRECTORY.0[0] = 0x00000083;
-        let pd_addr = &PAGE_DIRECTORY as *const _ as u32;
-        asm!("mov cr3, {0:e}", in(reg) pd_addr, options(nostack, preserves_flags));
-        asm!(
-            "mov eax, cr4",
-            "or eax, 0x10",
-            "mov cr4, eax",
-            out("eax") _,
-            options(nostack)
-        );
-        asm!(
-            "mov eax, cr0",
-            "or eax, 0x80000000",
-            "mov cr0, eax",
-            out("eax") _,
-            options(nostack)
-        );
+        asm!("invlpg [{0}]", in(reg) addr, options(nostack, preserves_flags));
     }
 }
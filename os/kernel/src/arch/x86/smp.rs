@@ -0,0 +1,50 @@
+core::arch::global_asm!(include_str!("trampoline.S"), options(att_syntax));
+
+/// Physical page the AP trampoline is copied to. Must match `trampoline.S`
+/// and sit below 1 MiB so a SIPI can address it.
+pub const TRAMPOLINE_ADDR: usize = 0x8000;
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_mailbox: u8;
+}
+
+/// Start-up parameters read by the trampoline: CR3 in protected mode, the
+/// rest once it reaches long mode.
+#[repr(C)]
+struct Mailbox {
+    cr3: usize,
+    stack_top: usize,
+    cpu_index: usize,
+    entry: usize,
+}
+
+pub fn install_trampoline() {
+    unsafe {
+        let start = core::ptr::addr_of!(ap_trampoline_start);
+        let len = core::ptr::addr_of!(ap_trampoline_end) as usize - start as usize;
+        core::ptr::copy_nonoverlapping(start, TRAMPOLINE_ADDR as *mut u8, len);
+    }
+}
+
+/// SIPI vector: the page number of the trampoline.
+pub const fn startup_vector() -> u8 {
+    (TRAMPOLINE_ADDR >> 12) as u8
+}
+
+/// Fills the mailbox for the next AP. APs are started one at a time, so a
+/// single mailbox is enough.
+pub fn prepare(cr3: usize, stack_top: usize, cpu_index: usize, entry: extern "C" fn(usize) -> !) {
+    unsafe {
+        let offset = core::ptr::addr_of!(ap_mailbox) as usize
+            - core::ptr::addr_of!(ap_trampoline_start) as usize;
+        let mailbox = (TRAMPOLINE_ADDR + offset) as *mut Mailbox;
+        mailbox.write_volatile(Mailbox {
+            cr3,
+            stack_top,
+            cpu_index,
+            entry: entry as usize,
+        });
+    }
+}
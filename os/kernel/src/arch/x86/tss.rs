@@ -0,0 +1,72 @@
+use crate::smp::{percpu, MAX_CPUS};
+
+/// Interrupt stack table slots (1-based, as the IDT encodes them). These
+/// vectors can arrive with a broken or untrusted kernel stack, so they
+/// always switch to a known-good one.
+pub const DOUBLE_FAULT_IST: u8 = 1;
+pub const NMI_IST: u8 = 2;
+pub const MACHINE_CHECK_IST: u8 = 3;
+const IST_STACKS: usize = 3;
+
+const IST_STACK_SIZE: usize = 8 * 1024;
+
+/// The 64-bit task state segment. Long mode only uses it for stack
+/// pointers: RSP0-2 for privilege changes and the seven IST entries.
+#[repr(C, packed(4))]
+pub struct TaskStateSegment {
+    reserved0: u32,
+    pub rsp: [u64; 3],
+    reserved1: u64,
+    pub ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    /// Offset of the I/O permission bitmap; past the limit means none.
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        Self {
+            reserved0: 0,
+            rsp: [0; 3],
+            reserved1: 0,
+            ist: [0; 7],
+            reserved2: 0,
+            reserved3: 0,
+            iomap_base: core::mem::size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+#[repr(align(16))]
+struct IstStack([u8; IST_STACK_SIZE]);
+
+static mut TSS: [TaskStateSegment; MAX_CPUS] = [const { TaskStateSegment::new() }; MAX_CPUS];
+static mut STACKS: [[IstStack; IST_STACKS]; MAX_CPUS] =
+    [const { [const { IstStack([0; IST_STACK_SIZE]) }; IST_STACKS] }; MAX_CPUS];
+
+/// Sets the stack `cpu` switches to when an interrupt, exception or
+/// SYSCALL arrives from ring 3. The IST entries stay per CPU: the vectors
+/// using them must not trust whatever stack the current task left behind.
+pub fn set_kernel_stack(cpu: usize, top: usize) {
+    unsafe {
+        let tss = &mut *core::ptr::addr_of_mut!(TSS[cpu]);
+        tss.rsp[0] = top as u64;
+    }
+    if let Some(cpu) = percpu::get(cpu) {
+        cpu.set_kernel_stack(top);
+    }
+}
+
+/// Points `cpu`'s IST entries at its own stacks and returns its TSS, for
+/// the GDT descriptor.
+pub fn prepare(cpu: usize) -> &'static TaskStateSegment {
+    unsafe {
+        let tss = &mut *core::ptr::addr_of_mut!(TSS[cpu]);
+        for slot in 0..IST_STACKS {
+            let stack = core::ptr::addr_of_mut!(STACKS[cpu][slot].0);
+            tss.ist[slot] = (stack as usize + IST_STACK_SIZE) as u64;
+        }
+        tss
+    }
+}
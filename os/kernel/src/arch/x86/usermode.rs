@@ -0,0 +1,133 @@
+use core::mem::offset_of;
+use syscall::{WaitStatus, SIGSEGV};
+
+use super::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use super::{context, paging};
+use crate::logging::LogLevel;
+use crate::process::{ExecMode, KernelContext, UserRegisters};
+use crate::scheduler;
+use crate::smp::percpu;
+use crate::userspace;
+
+core::arch::global_asm!(include_str!("usermode.S"), options(att_syntax));
+
+/// IF set, IOPL 0: user code can be preempted but cannot touch I/O ports
+/// or the interrupt flag.
+const USER_RFLAGS: u64 = 0x202;
+/// Flags a process may carry into ring 3: the arithmetic ones and DF.
+const USER_RFLAGS_KEPT: u64 = 0xCD5;
+const SELFTEST_KERNEL_STACK_SIZE: usize = 16 * 1024;
+const SELFTEST_PRIORITY: u8 = 10;
+
+// `user_enter` hardcodes the selectors and loads registers by offset.
+const _: () = {
+    assert!(USER_CODE_SELECTOR == 0x23 && USER_DATA_SELECTOR == 0x1B);
+    assert!(offset_of!(UserRegisters, rax) == 0);
+    assert!(offset_of!(UserRegisters, rdi) == 40);
+    assert!(offset_of!(UserRegisters, r15) == 112);
+    assert!(offset_of!(UserRegisters, rip) == 120);
+    assert!(offset_of!(UserRegisters, rsp) == 128);
+    assert!(offset_of!(UserRegisters, rflags) == 136);
+};
+
+extern "C" {
+    fn user_enter(registers: *const UserRegisters) -> !;
+    static user_selftest_code: u8;
+    static user_selftest_code_end: u8;
+}
+
+/// Drops to ring 3 with every register taken from `registers`, RFLAGS
+/// passed through `user_rflags`. Interrupts must be off, and the CPU on
+/// the process's kernel stack and in its address space.
+pub fn enter(registers: &UserRegisters) -> ! {
+    let mut registers = *registers;
+    registers.rflags = user_rflags(registers.rflags);
+    unsafe { user_enter(&registers) }
+}
+
+/// `rflags` with only the flags a process may set itself kept, and
+/// interrupts on.
+pub fn user_rflags(rflags: u64) -> u64 {
+    rflags & USER_RFLAGS_KEPT | USER_RFLAGS
+}
+
+/// The kernel side of a process that has yet to run, on the empty stack
+/// ending at `stack_top` and in the address space at `root`. The first
+/// switch to it enters ring 3 with the registers the scheduler holds for
+/// it.
+pub fn new_context(stack_top: usize, root: usize) -> KernelContext {
+    KernelContext {
+        stack_top,
+        rsp: context::prepare(stack_top, first_entry),
+        root,
+    }
+}
+
+/// Where a new process's kernel stack starts: finishes the switch to it,
+/// acts on signals already pending and enters ring 3.
+extern "C" fn first_entry() -> ! {
+    scheduler::finish_switch();
+    let pid = percpu::current().current_pid();
+    let mut registers = scheduler::user_registers(pid).unwrap_or_default();
+    userspace::signal::deliver_on_return(pid, &mut registers);
+    scheduler::enter_mode(ExecMode::User);
+    enter(&registers)
+}
+
+#[repr(C, align(4096))]
+struct UserPage([u8; paging::PAGE_SIZE]);
+
+#[repr(align(16))]
+struct KernelStack([u8; SELFTEST_KERNEL_STACK_SIZE]);
+
+impl KernelStack {
+    fn top(&self) -> usize {
+        self.0.as_ptr() as usize + SELFTEST_KERNEL_STACK_SIZE
+    }
+}
+
+static mut SELFTEST_CODE: UserPage = UserPage([0; paging::PAGE_SIZE]);
+static mut SELFTEST_STACK: UserPage = UserPage([0; paging::PAGE_SIZE]);
+static mut SELFTEST_KERNEL_STACK: KernelStack = KernelStack([0; SELFTEST_KERNEL_STACK_SIZE]);
+
+/// Runs a user task that prints through both system call paths and
+/// reads `/etc/aurora.conf` to the console, then executes CLI, and checks
+/// that ring 3 traps with #GP instead of running it.
+pub fn self_test() {
+    let code = core::ptr::addr_of_mut!(SELFTEST_CODE) as usize;
+    let stack = core::ptr::addr_of_mut!(SELFTEST_STACK) as usize;
+    let kernel_stack_top = unsafe { (*core::ptr::addr_of!(SELFTEST_KERNEL_STACK)).top() };
+
+    unsafe {
+        let start = core::ptr::addr_of!(user_selftest_code);
+        let len = core::ptr::addr_of!(user_selftest_code_end) as usize - start as usize;
+        core::ptr::copy_nonoverlapping(start, code as *mut u8, len);
+    }
+    if let Err(err) =
+        paging::map_user(code, paging::PAGE_SIZE).and(paging::map_user(stack, paging::PAGE_SIZE))
+    {
+        klog!(LogLevel::Error, "usermode: self-test not run: {}", err);
+        return;
+    }
+
+    let registers = UserRegisters {
+        rip: code as u64,
+        rsp: (stack + paging::PAGE_SIZE) as u64,
+        ..UserRegisters::default()
+    };
+    let context = new_context(kernel_stack_top, paging::root_table_address());
+    let pid = scheduler::spawn_user("usertest", SELFTEST_PRIORITY, context, registers);
+    match scheduler::join(pid).map(WaitStatus::decode) {
+        Some(WaitStatus::Dumped(signal)) if signal as u32 == SIGSEGV => klog!(
+            LogLevel::Info,
+            "usermode: self-test pid {} trapped on a privileged instruction",
+            pid
+        ),
+        status => klog!(
+            LogLevel::Error,
+            "usermode: self-test pid {} ended with {:?}, expected SIGSEGV from #GP",
+            pid,
+            status
+        ),
+    }
+}
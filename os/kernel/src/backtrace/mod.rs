@@ -0,0 +1,80 @@
+pub mod symbols;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::x86::paging;
+use crate::logging::LogLevel;
+use symbols::Demangled;
+
+/// Frames printed before giving up on a chain that does not end.
+const MAX_FRAMES: usize = 32;
+
+/// Set while a backtrace is printing, so a fault in the walk itself does
+/// not recurse through the panic handler.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn init(multiboot_info: usize) {
+    match symbols::init(multiboot_info) {
+        Ok(count) => klog!(LogLevel::Info, "backtrace: {} kernel symbols", count),
+        Err(err) => klog!(LogLevel::Warn, "backtrace: addresses only: {}", err),
+    }
+}
+
+/// Logs the call chain leading to the caller.
+#[inline(never)]
+pub fn print_current() {
+    let rbp: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    print(None, rbp);
+}
+
+/// Logs `rip`, then the chain of frames starting at `rbp`: where a trap
+/// frame says the kernel was.
+pub fn print_from(rip: usize, rbp: usize) {
+    print(Some(rip), rbp);
+}
+
+fn print(rip: Option<usize>, mut rbp: usize) {
+    if ACTIVE.swap(true, Ordering::Acquire) {
+        return;
+    }
+    klog!(LogLevel::Error, "backtrace:");
+    let mut depth = 0;
+    if let Some(rip) = rip {
+        frame(depth, rip, rip);
+        depth += 1;
+    }
+    // Every frame starts with the caller's RBP, then the return address.
+    while depth < MAX_FRAMES && rbp != 0 && rbp % 8 == 0 {
+        if !paging::is_mapped(rbp) || !paging::is_mapped(rbp + 15) {
+            break;
+        }
+        let (next, ret) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+        if ret == 0 {
+            break;
+        }
+        // The call itself sits just before the return address.
+        frame(depth, ret, ret - 1);
+        depth += 1;
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+    ACTIVE.store(false, Ordering::Release);
+}
+
+fn frame(depth: usize, addr: usize, lookup: usize) {
+    match symbols::lookup(lookup) {
+        Some((name, offset)) => klog!(
+            LogLevel::Error,
+            "  #{:02} {:#018x} {}+{:#x}",
+            depth,
+            addr,
+            Demangled(name),
+            offset + (addr - lookup)
+        ),
+        None => klog!(LogLevel::Error, "  #{:02} {:#018x} ?", depth, addr),
+    }
+}
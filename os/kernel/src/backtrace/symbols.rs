@@ -0,0 +1,206 @@
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::x86::paging;
+
+const TAG_END: u32 = 0;
+const TAG_ELF_SECTIONS: u32 = 9;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+/// Multiboot2 ELF-symbols tag header; the section headers follow it.
+#[repr(C)]
+struct ElfSectionsTag {
+    kind: u32,
+    size: u32,
+    count: u32,
+    entry_size: u32,
+    string_index: u32,
+}
+
+#[repr(C)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+#[repr(C)]
+struct Symbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    section: u16,
+    value: u64,
+    size: u64,
+}
+
+// Plain atomics rather than a lock: lookups run from the panic handler.
+static SYMBOLS: AtomicUsize = AtomicUsize::new(0);
+static SYMBOL_COUNT: AtomicUsize = AtomicUsize::new(0);
+static STRINGS: AtomicUsize = AtomicUsize::new(0);
+static STRINGS_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Finds `.symtab` and its string table through the ELF-symbols tag. GRUB
+/// loads both sections and records where in the section headers. Returns
+/// the number of symbols.
+pub fn init(multiboot_info: usize) -> Result<usize, &'static str> {
+    let tag = find_tag(multiboot_info, TAG_ELF_SECTIONS).ok_or("no ELF sections tag")?;
+    let tag = unsafe { &*(tag as *const ElfSectionsTag) };
+    let headers = tag as *const ElfSectionsTag as usize + core::mem::size_of::<ElfSectionsTag>();
+    let section = |index: u32| -> Option<&'static SectionHeader> {
+        (index < tag.count).then(|| unsafe {
+            &*((headers + index as usize * tag.entry_size as usize) as *const SectionHeader)
+        })
+    };
+
+    let symtab = (0..tag.count)
+        .filter_map(section)
+        .find(|header| header.kind == SHT_SYMTAB)
+        .ok_or("no .symtab section")?;
+    let strtab = section(symtab.link).ok_or("bad .symtab string table link")?;
+    if symtab.addr == 0 || strtab.addr == 0 {
+        return Err("symbol sections not loaded");
+    }
+
+    for header in [symtab, strtab] {
+        paging::identity_map_range(header.addr as usize, header.size as usize, 0);
+    }
+    SYMBOLS.store(symtab.addr as usize, Ordering::Relaxed);
+    STRINGS.store(strtab.addr as usize, Ordering::Relaxed);
+    STRINGS_LEN.store(strtab.size as usize, Ordering::Relaxed);
+    let count = symtab.size as usize / core::mem::size_of::<Symbol>();
+    SYMBOL_COUNT.store(count, Ordering::Release);
+    Ok(count)
+}
+
+/// The function containing `addr` and the offset into it.
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let count = SYMBOL_COUNT.load(Ordering::Acquire);
+    if count == 0 {
+        return None;
+    }
+    let symbols = unsafe {
+        core::slice::from_raw_parts(SYMBOLS.load(Ordering::Relaxed) as *const Symbol, count)
+    };
+    let addr = addr as u64;
+    let symbol = symbols
+        .iter()
+        .filter(|symbol| symbol.info & 0xF == STT_FUNC && symbol.value <= addr)
+        .filter(|symbol| symbol.size == 0 || addr < symbol.value + symbol.size)
+        .max_by_key(|symbol| symbol.value)?;
+    Some((name(symbol.name as usize)?, (addr - symbol.value) as usize))
+}
+
+fn name(offset: usize) -> Option<&'static str> {
+    let len = STRINGS_LEN.load(Ordering::Relaxed);
+    let strings =
+        unsafe { core::slice::from_raw_parts(STRINGS.load(Ordering::Relaxed) as *const u8, len) };
+    let bytes = strings.get(offset..)?;
+    let end = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..end]).ok()
+}
+
+fn find_tag(multiboot_info: usize, kind: u32) -> Option<usize> {
+    let total = unsafe { *(multiboot_info as *const u32) } as usize;
+    let mut tag = multiboot_info + 8;
+    while tag + 8 <= multiboot_info + total {
+        let (tag_kind, size) = unsafe { (*(tag as *const u32), *((tag + 4) as *const u32)) };
+        if tag_kind == TAG_END {
+            break;
+        }
+        if tag_kind == kind {
+            return Some(tag);
+        }
+        tag += (size as usize).next_multiple_of(8);
+    }
+    None
+}
+
+/// A legacy-mangled Rust name (`_ZN4core3fmt5write17h…E`) as a path,
+/// without the hash. Anything else is shown as is.
+pub struct Demangled<'a>(pub &'a str);
+
+impl fmt::Display for Demangled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(mut rest) = self.0.strip_prefix("_ZN").and_then(|s| s.strip_suffix('E')) else {
+            return f.write_str(self.0);
+        };
+        let mut first = true;
+        while !rest.is_empty() {
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let Some(len) = rest[..digits].parse::<usize>().ok() else {
+                return f.write_str(self.0);
+            };
+            let Some(segment) = rest.get(digits..digits + len) else {
+                return f.write_str(self.0);
+            };
+            rest = &rest[digits + len..];
+            let is_hash = rest.is_empty()
+                && segment.len() == 17
+                && segment.starts_with('h')
+                && segment[1..].bytes().all(|b| b.is_ascii_hexdigit());
+            if is_hash {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_segment(f, segment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Undoes the `$..$` escapes legacy mangling uses for punctuation.
+fn write_segment(f: &mut fmt::Formatter<'_>, segment: &str) -> fmt::Result {
+    let mut rest = if segment.starts_with("_$") {
+        &segment[1..]
+    } else {
+        segment
+    };
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = tail;
+        } else if let Some(escaped) = rest.strip_prefix('$') {
+            let Some(end) = escaped.find('$') else {
+                return f.write_str(rest);
+            };
+            let text = match &escaped[..end] {
+                "SP" => "@",
+                "BP" => "*",
+                "RF" => "&",
+                "LT" => "<",
+                "GT" => ">",
+                "LP" => "(",
+                "RP" => ")",
+                "C" => ",",
+                "u20" => " ",
+                "u27" => "'",
+                "u5b" => "[",
+                "u5d" => "]",
+                "u7b" => "{",
+                "u7d" => "}",
+                "u7e" => "~",
+                other => other,
+            };
+            f.write_str(text)?;
+            rest = &escaped[end + 1..];
+        } else {
+            let end = rest.find(['$', '.']).unwrap_or(rest.len()).max(1);
+            f.write_str(&rest[..end])?;
+            rest = &rest[end..];
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use spin::Mutex;
+
+use crate::acpi::Madt;
+use crate::arch::x86::paging;
+
+/// MADT routing and redirection-entry encoding, shared with and tested in
+/// the kernel crate.
+#[path = "../../../../kernel/src/ioapic.rs"]
+#[allow(dead_code)]
+pub mod common;
+
+use common::{IoApicDescriptor, IrqRouting, IOREGSEL, IOWIN, REG_VERSION};
+
+const ISA_IRQS: usize = 16;
+
+/// Redirection entry programmed for one ISA IRQ.
+#[derive(Clone, Copy)]
+struct Route {
+    base: usize,
+    pin: u8,
+    entry: u64,
+}
+
+static ROUTES: Mutex<[Option<Route>; ISA_IRQS]> = Mutex::new([None; ISA_IRQS]);
+
+/// Masks every pin of every IOAPIC in the MADT, then routes ISA IRQ `n` to
+/// `vector_base + n` on the local APIC `destination`, honouring source
+/// overrides. Entries start masked. Returns false without an IOAPIC.
+pub fn init(madt: &Madt, destination: u8, vector_base: u8) -> bool {
+    let Some(routing) = madt.routing() else {
+        return false;
+    };
+    for io_apic in routing.io_apics() {
+        let base = io_apic.address as usize;
+        paging::identity_map_range(
+            base,
+            0x20,
+            paging::PAGE_WRITABLE | paging::PAGE_CACHE_DISABLE | paging::PAGE_WRITE_THROUGH,
+        );
+        for pin in 0..pin_count(base) {
+            write_entry(base, pin, common::set_masked(0, true));
+        }
+    }
+
+    let mut routes = [None; ISA_IRQS];
+    for (irq, slot) in routes.iter_mut().enumerate() {
+        let route = routing.isa_route(irq as u8);
+        let Some((io_apic, pin)) = pin_for(routing, route.gsi) else {
+            continue;
+        };
+        let entry = common::redirection_entry(vector_base + irq as u8, destination, route, true);
+        let base = io_apic.address as usize;
+        write_entry(base, pin, entry);
+        *slot = Some(Route { base, pin, entry });
+    }
+
+    let routed = routes.iter().any(Option::is_some);
+    *ROUTES.lock() = routes;
+    routed
+}
+
+pub fn mask(irq: u8) {
+    set_masked(irq, true);
+}
+
+pub fn unmask(irq: u8) {
+    set_masked(irq, false);
+}
+
+fn set_masked(irq: u8, masked: bool) {
+    let mut routes = ROUTES.lock();
+    let Some(route) = routes.get_mut(irq as usize).and_then(Option::as_mut) else {
+        return;
+    };
+    route.entry = common::set_masked(route.entry, masked);
+    write(
+        route.base,
+        common::redirection_register(route.pin),
+        route.entry as u32,
+    );
+}
+
+/// The IOAPIC serving `gsi` and the pin within it, if it has that many.
+fn pin_for(routing: &IrqRouting, gsi: u32) -> Option<(IoApicDescriptor, u8)> {
+    routing
+        .pin_for(gsi)
+        .filter(|(io_apic, pin)| *pin < pin_count(io_apic.address as usize))
+}
+
+fn pin_count(base: usize) -> u8 {
+    common::pin_count(read(base, REG_VERSION))
+}
+
+/// Writes the high half (destination) first so the entry never points at
+/// a stale CPU while unmasked.
+fn write_entry(base: usize, pin: u8, entry: u64) {
+    let register = common::redirection_register(pin);
+    write(base, register + 1, (entry >> 32) as u32);
+    write(base, register, entry as u32);
+}
+
+fn read(base: usize, register: u32) -> u32 {
+    unsafe {
+        core::ptr::write_volatile((base + IOREGSEL) as *mut u32, register);
+        core::ptr::read_volatile((base + IOWIN) as *const u32)
+    }
+}
+
+fn write(base: usize, register: u32, value: u32) {
+    unsafe {
+        core::ptr::write_volatile((base + IOREGSEL) as *mut u32, register);
+        core::ptr::write_volatile((base + IOWIN) as *mut u32, value);
+    }
+}
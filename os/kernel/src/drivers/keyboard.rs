@@ -1,6 +1,17 @@
 use x86::io::inb;
 
-pub fn handle_interrupt() {
-    let scancode = unsafe { inb(0x60) };
+use crate::interrupts::irq::{self, IrqReturn};
+use crate::process::ExecMode;
+
+const IRQ: u8 = 1;
+const DATA_PORT: u16 = 0x60;
+
+pub fn init() {
+    irq::register(IRQ, handle_interrupt, 0).expect("keyboard IRQ line is free");
+}
+
+fn handle_interrupt(_ctx: usize, _mode: ExecMode) -> IrqReturn {
+    let scancode = unsafe { inb(DATA_PORT) };
     println!("kbd scancode: 0x{:02x}", scancode);
+    IrqReturn::Handled
 }
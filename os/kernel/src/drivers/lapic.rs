@@ -0,0 +1,167 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::x86::paging;
+use crate::timer::common::{lapic_divide_config, lapic_lvt_timer, LapicTimerMode};
+
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const REG_ID: usize = 0x020;
+const REG_TPR: usize = 0x080;
+const REG_EOI: usize = 0x0B0;
+const REG_SVR: usize = 0x0F0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3E0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+
+const ICR_FIXED: u32 = 0b000 << 8;
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// Divider applied to the LAPIC timer input clock in every mode.
+pub const TIMER_DIVISOR: u32 = 16;
+const TIMER_DIVIDE: u32 = match lapic_divide_config(TIMER_DIVISOR) {
+    Some(config) => config,
+    None => panic!("unsupported LAPIC timer divisor"),
+};
+
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Maps the local APIC register window. Must run once on the BSP before
+/// any other function in this module.
+pub fn init(base: usize) {
+    paging::identity_map_range(
+        base,
+        0x1000,
+        paging::PAGE_WRITABLE | paging::PAGE_CACHE_DISABLE | paging::PAGE_WRITE_THROUGH,
+    );
+    BASE.store(base, Ordering::SeqCst);
+}
+
+/// Software-enables the calling CPU's local APIC.
+pub fn enable() {
+    write(REG_TPR, 0);
+    write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+pub fn id() -> u32 {
+    read(REG_ID) >> 24
+}
+
+pub fn end_of_interrupt() {
+    write(REG_EOI, 0);
+}
+
+pub fn send_init(apic_id: u32) {
+    send(apic_id, ICR_INIT | ICR_LEVEL_ASSERT);
+}
+
+pub fn send_startup(apic_id: u32, vector_page: u8) {
+    send(apic_id, ICR_STARTUP | ICR_LEVEL_ASSERT | vector_page as u32);
+}
+
+pub fn send_fixed(apic_id: u32, vector: u8) {
+    send(apic_id, ICR_FIXED | ICR_LEVEL_ASSERT | vector as u32);
+}
+
+pub fn broadcast_others(vector: u8) {
+    wait_for_delivery();
+    write(REG_ICR_HIGH, 0);
+    write(
+        REG_ICR_LOW,
+        ICR_ALL_EXCLUDING_SELF | ICR_FIXED | ICR_LEVEL_ASSERT | vector as u32,
+    );
+    wait_for_delivery();
+}
+
+/// Fires `vector` every `initial_count` divided timer clocks.
+pub fn timer_periodic(vector: u8, initial_count: u32) {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE);
+    write(
+        REG_LVT_TIMER,
+        lapic_lvt_timer(vector, LapicTimerMode::Periodic, false),
+    );
+    write(REG_TIMER_INITIAL, initial_count);
+}
+
+/// Fires `vector` once after `count` divided timer clocks.
+pub fn timer_oneshot(vector: u8, count: u32) {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE);
+    write(
+        REG_LVT_TIMER,
+        lapic_lvt_timer(vector, LapicTimerMode::OneShot, false),
+    );
+    write(REG_TIMER_INITIAL, count);
+}
+
+/// Switches the timer to TSC-deadline mode. Nothing fires until
+/// `set_tsc_deadline` is called.
+pub fn timer_tsc_deadline(vector: u8) {
+    write(
+        REG_LVT_TIMER,
+        lapic_lvt_timer(vector, LapicTimerMode::TscDeadline, false),
+    );
+    // The SDM requires the LVT write to be serialized against the
+    // `IA32_TSC_DEADLINE` WRMSR that follows; without it the deadline can
+    // be written while the timer is still in its old mode and get dropped.
+    unsafe { core::arch::asm!("mfence", options(nostack, preserves_flags)) };
+}
+
+/// Arms the TSC-deadline timer to fire once the TSC reaches `deadline`.
+pub fn set_tsc_deadline(deadline: u64) {
+    unsafe { x86::msr::wrmsr(IA32_TSC_DEADLINE, deadline) };
+}
+
+/// Starts a masked one-shot count down from `u32::MAX` for calibration.
+pub fn timer_start_counting() {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE);
+    write(
+        REG_LVT_TIMER,
+        lapic_lvt_timer(0, LapicTimerMode::OneShot, true),
+    );
+    write(REG_TIMER_INITIAL, u32::MAX);
+}
+
+pub fn timer_current_count() -> u32 {
+    read(REG_TIMER_CURRENT)
+}
+
+pub fn timer_stop() {
+    write(
+        REG_LVT_TIMER,
+        lapic_lvt_timer(0, LapicTimerMode::OneShot, true),
+    );
+    write(REG_TIMER_INITIAL, 0);
+}
+
+fn send(apic_id: u32, command: u32) {
+    wait_for_delivery();
+    write(REG_ICR_HIGH, apic_id << 24);
+    write(REG_ICR_LOW, command);
+    wait_for_delivery();
+}
+
+fn wait_for_delivery() {
+    while read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+fn read(reg: usize) -> u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { core::ptr::read_volatile((base + reg) as *const u32) }
+}
+
+fn write(reg: usize, value: u32) {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { core::ptr::write_volatile((base + reg) as *mut u32, value) }
+}
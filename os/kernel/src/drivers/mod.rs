@@ -1,2 +1,5 @@
+pub mod ioapic;
 pub mod keyboard;
+pub mod lapic;
 pub mod pic;
+pub mod rtc;
@@ -7,11 +7,16 @@ const PIC1_DATA: u16 = PIC1 + 1;
 const PIC2_COMMAND: u16 = PIC2;
 const PIC2_DATA: u16 = PIC2 + 1;
 
+/// OCW3 selecting the in-service register for the next command-port read.
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// Cascade input on the primary PIC that the secondary is wired to.
+const CASCADE_IRQ: u8 = 2;
+
+/// Remaps the PICs to vectors 32..48 with every line masked except the
+/// cascade; `interrupts::irq::register` unmasks lines as drivers hook them.
 pub fn init() {
     unsafe {
-        let a1 = inb(PIC1_DATA);
-        let a2 = inb(PIC2_DATA);
-
         outb(PIC1_COMMAND, 0x11);
         outb(PIC2_COMMAND, 0x11);
 
@@ -24,8 +29,44 @@ pub fn init() {
         outb(PIC1_DATA, 0x01);
         outb(PIC2_DATA, 0x01);
 
-        outb(PIC1_DATA, a1 & !0x03);
-        outb(PIC2_DATA, a2);
+        outb(PIC1_DATA, !(1 << CASCADE_IRQ));
+        outb(PIC2_DATA, 0xFF);
+    }
+}
+
+/// Stops `irq` from reaching the CPU, e.g. IRQ0 once the LAPIC timer ticks.
+pub fn mask(irq: u8) {
+    let (port, bit) = mask_bit(irq);
+    unsafe { outb(port, inb(port) | 1 << bit) };
+}
+
+pub fn unmask(irq: u8) {
+    let (port, bit) = mask_bit(irq);
+    unsafe { outb(port, inb(port) & !(1 << bit)) };
+}
+
+fn mask_bit(irq: u8) -> (u16, u8) {
+    if irq < 8 {
+        (PIC1_DATA, irq)
+    } else {
+        (PIC2_DATA, irq - 8)
+    }
+}
+
+/// Masks every line, cascade included, once the IOAPIC takes over.
+pub fn disable() {
+    unsafe {
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+    }
+}
+
+/// Combined in-service register, primary PIC in the low byte.
+pub fn in_service() -> u16 {
+    unsafe {
+        outb(PIC1_COMMAND, OCW3_READ_ISR);
+        outb(PIC2_COMMAND, OCW3_READ_ISR);
+        (inb(PIC2_COMMAND) as u16) << 8 | inb(PIC1_COMMAND) as u16
     }
 }
 
@@ -0,0 +1,50 @@
+use x86::io::{inb, outb};
+
+/// Register decoding, date validation and the stable-read loop, shared
+/// with and tested in the kernel crate.
+#[path = "../../../../kernel/src/rtc.rs"]
+#[allow(dead_code)]
+mod common;
+
+pub use common::DateTime;
+use common::{RtcRaw, STATUS_A_UPDATE_IN_PROGRESS};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Keeps NMIs masked while a CMOS register is selected.
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Reads the current date and time. `century_register` is the CMOS index
+/// the FADT names for the century, if any; without it years are 20xx.
+pub fn read(century_register: Option<u8>) -> Result<DateTime, &'static str> {
+    let raw = common::read_stable(
+        || cmos_read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0,
+        || RtcRaw {
+            seconds: cmos_read(REG_SECONDS),
+            minutes: cmos_read(REG_MINUTES),
+            hours: cmos_read(REG_HOURS),
+            day: cmos_read(REG_DAY),
+            month: cmos_read(REG_MONTH),
+            year: cmos_read(REG_YEAR),
+            century: century_register.map(cmos_read),
+            status_b: cmos_read(REG_STATUS_B),
+        },
+    )?;
+    common::decode(raw)
+}
+
+fn cmos_read(register: u8) -> u8 {
+    unsafe {
+        outb(CMOS_ADDRESS, NMI_DISABLE | register);
+        inb(CMOS_DATA)
+    }
+}
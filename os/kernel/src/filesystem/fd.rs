@@ -0,0 +1,221 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::Mutex;
+use syscall::{Errno, O_ACCMODE, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+
+use super::inode::NodeType;
+use super::{ramfs, vfs};
+
+/// Descriptor slots, `dup` and seek arithmetic, shared with and tested in
+/// the kernel crate.
+#[path = "../../../../kernel/src/fd.rs"]
+#[allow(dead_code)]
+mod table;
+
+/// Descriptors every process starts with, all on the console.
+const STDIO: usize = 3;
+
+#[derive(Clone, Copy)]
+enum Backing {
+    /// Writes go to the kernel console; reads see end of file, since the
+    /// keyboard keeps no input buffer yet.
+    Console,
+    File(usize),
+}
+
+/// An open file description. `dup` shares it, offset included.
+struct OpenFile {
+    backing: Backing,
+    offset: usize,
+    readable: bool,
+    writable: bool,
+    append: bool,
+}
+
+type Handle = Arc<Mutex<OpenFile>>;
+type FdTable = table::FdTable<Handle>;
+
+fn with_stdio() -> FdTable {
+    let console = Arc::new(Mutex::new(OpenFile {
+        backing: Backing::Console,
+        offset: 0,
+        readable: true,
+        writable: true,
+        append: false,
+    }));
+    let mut table = FdTable::new();
+    for _ in 0..STDIO {
+        let _ = table.insert(console.clone());
+    }
+    table
+}
+
+/// Descriptor tables by pid, created on a process's first file call.
+static TABLES: Mutex<BTreeMap<u32, FdTable>> = Mutex::new(BTreeMap::new());
+
+fn with_table<R>(pid: u32, f: impl FnOnce(&mut FdTable) -> R) -> R {
+    let mut tables = TABLES.lock();
+    f(tables.entry(pid).or_insert_with(with_stdio))
+}
+
+fn file(pid: u32, fd: u32) -> Result<Handle, Errno> {
+    with_table(pid, |table| {
+        table.get(fd as usize).cloned().ok_or(Errno::EBADF)
+    })
+}
+
+fn with_fs<R>(f: impl FnOnce(&mut ramfs::RamFs) -> R) -> Result<R, Errno> {
+    ramfs::with_fs(f).ok_or(Errno::EIO)
+}
+
+/// Opens `path` for `pid` with `open(2)` flags and returns the new
+/// descriptor.
+pub fn open(pid: u32, path: &str, flags: u32) -> Result<usize, Errno> {
+    let (readable, writable) = match flags & O_ACCMODE {
+        O_RDONLY => (true, false),
+        O_WRONLY => (false, true),
+        O_RDWR => (true, true),
+        _ => return Err(Errno::EINVAL),
+    };
+    let inode = with_fs(|fs| {
+        let inode = match vfs::resolve(fs, path) {
+            Err(Errno::ENOENT) if flags & O_CREAT != 0 => vfs::create(fs, path)?,
+            found => found?,
+        };
+        let is_dir = fs.node(inode).map(|node| node.node_type) == Some(NodeType::Directory);
+        if is_dir && writable {
+            return Err(Errno::EISDIR);
+        }
+        if !is_dir && writable && flags & O_TRUNC != 0 {
+            fs.truncate(inode);
+        }
+        Ok(inode)
+    })??;
+    let file = Arc::new(Mutex::new(OpenFile {
+        backing: Backing::File(inode),
+        offset: 0,
+        readable,
+        writable,
+        append: flags & O_APPEND != 0,
+    }));
+    with_table(pid, |table| table.insert(file).map_err(|_| Errno::EMFILE))
+}
+
+/// Reads into `out` at the descriptor's offset and advances it.
+pub fn read(pid: u32, fd: u32, out: &mut [u8]) -> Result<usize, Errno> {
+    let file = file(pid, fd)?;
+    let mut file = file.lock();
+    if !file.readable {
+        return Err(Errno::EBADF);
+    }
+    match file.backing {
+        Backing::Console => Ok(0),
+        Backing::File(inode) => {
+            let offset = file.offset;
+            let count = with_fs(|fs| {
+                if fs.node(inode).map(|node| node.node_type) == Some(NodeType::Directory) {
+                    return Err(Errno::EISDIR);
+                }
+                Ok(fs.read_at(inode, offset, out))
+            })??;
+            file.offset += count;
+            Ok(count)
+        }
+    }
+}
+
+/// Writes `data` at the descriptor's offset, or the end of the file for
+/// `O_APPEND`, and returns how much was taken.
+pub fn write(pid: u32, fd: u32, data: &[u8]) -> Result<usize, Errno> {
+    let file = file(pid, fd)?;
+    let mut file = file.lock();
+    if !file.writable {
+        return Err(Errno::EBADF);
+    }
+    match file.backing {
+        Backing::Console => write_console(data),
+        Backing::File(inode) => {
+            let append = file.append;
+            let (offset, count) = with_fs(|fs| {
+                let offset = if append {
+                    fs.node(inode).map_or(0, |node| node.data_len)
+                } else {
+                    file.offset
+                };
+                (offset, fs.write_at(inode, offset, data))
+            })?;
+            if count == 0 && !data.is_empty() {
+                return Err(Errno::ENOSPC);
+            }
+            file.offset = offset + count;
+            Ok(count)
+        }
+    }
+}
+
+/// Prints the UTF-8 in `data`. A character cut off at the end is left for
+/// the next write.
+fn write_console(data: &[u8]) -> Result<usize, Errno> {
+    let text = match core::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) if err.valid_up_to() > 0 && err.error_len().is_none() => {
+            core::str::from_utf8(&data[..err.valid_up_to()]).map_err(|_| Errno::EINVAL)?
+        }
+        Err(_) => return Err(Errno::EINVAL),
+    };
+    print!("{}", text);
+    Ok(text.len())
+}
+
+pub fn close(pid: u32, fd: u32) -> Result<(), Errno> {
+    with_table(pid, |table| {
+        table.close(fd as usize).map(drop).map_err(|_| Errno::EBADF)
+    })
+}
+
+/// A second descriptor, the lowest free one, sharing `fd`'s open file.
+pub fn dup(pid: u32, fd: u32) -> Result<usize, Errno> {
+    with_table(pid, |table| {
+        if table.get(fd as usize).is_none() {
+            return Err(Errno::EBADF);
+        }
+        table.dup(fd as usize).map_err(|_| Errno::EMFILE)
+    })
+}
+
+/// Points `new` at `old`'s open file, closing what `new` held. Duplicating
+/// a descriptor onto itself only checks that it is open.
+pub fn dup2(pid: u32, old: u32, new: u32) -> Result<usize, Errno> {
+    with_table(pid, |table| {
+        table
+            .dup2(old as usize, new as usize)
+            .map_err(|_| Errno::EBADF)
+    })
+}
+
+/// Moves the descriptor's offset and returns it. Seeking past the end is
+/// allowed; the gap reads as zeroes once written beyond.
+pub fn lseek(pid: u32, fd: u32, delta: i64, whence: u32) -> Result<usize, Errno> {
+    let file = file(pid, fd)?;
+    let mut file = file.lock();
+    let Backing::File(inode) = file.backing else {
+        return Err(Errno::ESPIPE);
+    };
+    let len = with_fs(|fs| fs.node(inode).map_or(0, |node| node.data_len))?;
+    let offset = table::seek(file.offset, len, delta, whence).map_err(|_| Errno::EINVAL)?;
+    file.offset = offset;
+    Ok(offset)
+}
+
+/// Gives `child` the descriptors `parent` has, sharing each open file.
+pub fn fork(parent: u32, child: u32) {
+    let mut tables = TABLES.lock();
+    if let Some(table) = tables.get(&parent).cloned() {
+        tables.insert(child, table);
+    }
+}
+
+/// Closes every descriptor `pid` holds; called when it exits.
+pub fn release(pid: u32) {
+    TABLES.lock().remove(&pid);
+}
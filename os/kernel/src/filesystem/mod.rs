@@ -1,3 +1,4 @@
+pub mod fd;
 pub mod inode;
 pub mod ramfs;
 pub mod vfs;
@@ -76,6 +76,55 @@ impl RamFs {
         0
     }
 
+    /// Copies file bytes from `offset` on into `out`; 0 at or past the end.
+    pub fn read_at(&self, inode: usize, offset: usize, out: &mut [u8]) -> usize {
+        if let Some(file) = self.files.iter().find(|f| f.inode == inode) {
+            if offset >= file.len {
+                return 0;
+            }
+            let count = core::cmp::min(out.len(), file.len - offset);
+            out[..count].copy_from_slice(&file.bytes[offset..offset + count]);
+            return count;
+        }
+        0
+    }
+
+    /// Writes `data` at `offset`, growing the file and zero-filling any gap,
+    /// and returns how many bytes fit.
+    pub fn write_at(&mut self, inode: usize, offset: usize, data: &[u8]) -> usize {
+        if let Some(file) = self.files.iter_mut().find(|f| f.inode == inode) {
+            if offset > file.bytes.len() {
+                return 0;
+            }
+            let count = core::cmp::min(data.len(), file.bytes.len() - offset);
+            if offset > file.len {
+                file.bytes[file.len..offset].fill(0);
+            }
+            file.bytes[offset..offset + count].copy_from_slice(&data[..count]);
+            file.len = core::cmp::max(file.len, offset + count);
+            if let Some(node) = self.inodes.get_mut(inode) {
+                node.data_len = file.len;
+            }
+            return count;
+        }
+        0
+    }
+
+    pub fn truncate(&mut self, inode: usize) {
+        self.write_file(inode, &[]);
+    }
+
+    pub fn node(&self, inode: usize) -> Option<&Inode> {
+        self.inodes.get(inode)
+    }
+
+    pub fn lookup(&self, parent: usize, name: &str) -> Option<usize> {
+        self.inodes
+            .iter()
+            .find(|i| i.parent == Some(parent) && i.name() == name)
+            .map(|i| i.id)
+    }
+
     pub fn children(&self, parent: usize) -> Vec<&Inode> {
         self.inodes
             .iter()
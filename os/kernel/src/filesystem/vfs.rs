@@ -1,11 +1,95 @@
-use super::ramfs;
+use alloc::vec;
+use alloc::vec::Vec;
+use syscall::Errno;
+
+use super::inode::NodeType;
+use super::ramfs::{self, RamFs};
+
+const ROOT: usize = 0;
+const MAX_NAME: usize = 32;
 
 pub fn init() {
     ramfs::initialize();
 
     let _ = ramfs::with_fs(|fs| {
+        fs.mkdir(0, "bin");
         let etc = fs.mkdir(0, "etc");
         let cfg = fs.create_file(etc, "aurora.conf");
         let _ = fs.write_file(cfg, b"kernel.log=info\nnet=enabled\n");
     });
 }
+
+/// The inode `path` names. Paths are absolute; `.` and `..` are followed
+/// and repeated slashes ignored.
+pub fn resolve(fs: &RamFs, path: &str) -> Result<usize, Errno> {
+    let rest = path.strip_prefix('/').ok_or(Errno::ENOENT)?;
+    rest.split('/')
+        .try_fold(ROOT, |dir, name| step(fs, dir, name))
+}
+
+/// Creates an empty file at `path`, whose directory must exist.
+pub fn create(fs: &mut RamFs, path: &str) -> Result<usize, Errno> {
+    let (dir, name) = path.rsplit_once('/').ok_or(Errno::ENOENT)?;
+    let dir = if dir.is_empty() {
+        ROOT
+    } else {
+        resolve(fs, dir)?
+    };
+    if fs.node(dir).map(|node| node.node_type) != Some(NodeType::Directory) {
+        return Err(Errno::ENOTDIR);
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Errno::EISDIR);
+    }
+    if name.len() > MAX_NAME {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    if fs.lookup(dir, name).is_some() {
+        return Err(Errno::EEXIST);
+    }
+    Ok(fs.create_file(dir, name))
+}
+
+/// The whole contents of the file at `path`.
+pub fn read(path: &str) -> Result<Vec<u8>, Errno> {
+    ramfs::with_fs(|fs| {
+        let inode = resolve(fs, path)?;
+        let node = fs.node(inode).ok_or(Errno::ENOENT)?;
+        if node.node_type == NodeType::Directory {
+            return Err(Errno::EISDIR);
+        }
+        let mut data = vec![0; node.data_len];
+        let len = fs.read_at(inode, 0, &mut data);
+        data.truncate(len);
+        Ok(data)
+    })
+    .ok_or(Errno::EIO)?
+}
+
+/// Creates `path`, or empties it if it exists, and fills it with `data`.
+pub fn write(path: &str, data: &[u8]) -> Result<usize, Errno> {
+    ramfs::with_fs(|fs| {
+        let inode = match resolve(fs, path) {
+            Err(Errno::ENOENT) => create(fs, path)?,
+            found => found?,
+        };
+        if fs.node(inode).map(|node| node.node_type) == Some(NodeType::Directory) {
+            return Err(Errno::EISDIR);
+        }
+        Ok(fs.write_file(inode, data))
+    })
+    .ok_or(Errno::EIO)?
+}
+
+fn step(fs: &RamFs, dir: usize, name: &str) -> Result<usize, Errno> {
+    let node = fs.node(dir).ok_or(Errno::ENOENT)?;
+    if node.node_type != NodeType::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+    match name {
+        "" | "." => Ok(dir),
+        ".." => Ok(node.parent.unwrap_or(ROOT)),
+        _ if name.len() > MAX_NAME => Err(Errno::ENAMETOOLONG),
+        _ => fs.lookup(dir, name).ok_or(Errno::ENOENT),
+    }
+}
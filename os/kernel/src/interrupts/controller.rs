@@ -0,0 +1,116 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::irq;
+use crate::acpi::Madt;
+use crate::drivers::{ioapic, lapic, pic};
+use crate::logging::LogLevel;
+
+/// Masks, unmasks and acknowledges ISA interrupt lines. `interrupts::irq`
+/// goes through whichever controller is active.
+pub trait InterruptController: Sync {
+    fn name(&self) -> &'static str;
+    fn mask(&self, line: u8);
+    fn unmask(&self, line: u8);
+    /// True when the interrupt on `line` was not really raised. Whatever
+    /// acknowledgement a spurious interrupt needs has been sent already,
+    /// and no handler or EOI must follow.
+    fn is_spurious(&self, line: u8) -> bool;
+    fn end_of_interrupt(&self, line: u8);
+}
+
+/// The 8259 pair: the boot controller, and the fallback without an IOAPIC.
+struct Pic;
+
+impl InterruptController for Pic {
+    fn name(&self) -> &'static str {
+        "8259 pic"
+    }
+
+    fn mask(&self, line: u8) {
+        pic::mask(line);
+    }
+
+    fn unmask(&self, line: u8) {
+        pic::unmask(line);
+    }
+
+    /// A glitch on IRQ 7 or 15 shows up with its in-service bit clear. The
+    /// primary PIC did see a real cascade interrupt for 15 and still needs
+    /// its EOI.
+    fn is_spurious(&self, line: u8) -> bool {
+        if line != 7 && line != 15 || pic::in_service() & (1 << line) != 0 {
+            return false;
+        }
+        if line == 15 {
+            pic::end_of_interrupt(0);
+        }
+        true
+    }
+
+    fn end_of_interrupt(&self, line: u8) {
+        pic::end_of_interrupt(line);
+    }
+}
+
+/// IOAPIC redirection entries, acknowledged at the local APIC.
+struct Apic;
+
+impl InterruptController for Apic {
+    fn name(&self) -> &'static str {
+        "ioapic"
+    }
+
+    fn mask(&self, line: u8) {
+        ioapic::mask(line);
+    }
+
+    fn unmask(&self, line: u8) {
+        ioapic::unmask(line);
+    }
+
+    /// Spurious LAPIC interrupts arrive on their own vector instead.
+    fn is_spurious(&self, _line: u8) -> bool {
+        false
+    }
+
+    fn end_of_interrupt(&self, _line: u8) {
+        lapic::end_of_interrupt();
+    }
+}
+
+const PIC: u8 = 0;
+const APIC: u8 = 1;
+
+static ACTIVE: AtomicU8 = AtomicU8::new(PIC);
+
+pub fn active() -> &'static dyn InterruptController {
+    match ACTIVE.load(Ordering::Acquire) {
+        APIC => &Apic,
+        _ => &Pic,
+    }
+}
+
+/// Moves the ISA lines from the 8259 pair to the IOAPICs listed in the
+/// MADT, delivering to the BSP. Lines keep their masked state. Runs on the
+/// BSP once its LAPIC is enabled; stays on the PIC without an IOAPIC.
+pub fn init_apic(madt: &Madt, bsp_apic_id: u32) {
+    if !ioapic::init(madt, bsp_apic_id as u8, irq::IRQ_BASE) {
+        klog!(
+            LogLevel::Info,
+            "irq: no usable IOAPIC, staying on the {}",
+            Pic.name()
+        );
+        return;
+    }
+    irq::switch_controller(|| {
+        pic::disable();
+        ACTIVE.store(APIC, Ordering::Release);
+    });
+    klog!(
+        LogLevel::Info,
+        "irq: using the {} ({} found)",
+        Apic.name(),
+        madt.routing()
+            .map_or(0, |routing| routing.io_apics().count())
+    );
+}
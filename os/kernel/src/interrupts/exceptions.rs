@@ -0,0 +1,345 @@
+use core::arch::asm;
+use core::fmt;
+
+use crate::arch::x86::paging;
+use crate::backtrace;
+use crate::logging::{self, LogLevel};
+use crate::memory;
+use crate::process::{ExecMode, UserRegisters};
+use crate::scheduler;
+use crate::smp::{percpu, MAX_CPUS};
+use crate::userspace;
+
+/// Vector table, fault policy and error-code decoding, shared with and
+/// tested in the kernel crate.
+#[path = "../../../../kernel/src/exception.rs"]
+#[allow(dead_code)]
+mod exception;
+
+use exception::{FaultAction, EXCEPTIONS};
+
+core::arch::global_asm!(
+    include_str!("exceptions.S"),
+    percpu_blocks = sym percpu::CPUS,
+    percpu_blocks_size = const core::mem::size_of::<[percpu::PerCpu; MAX_CPUS]>(),
+    options(att_syntax)
+);
+
+const NMI_VECTOR: u64 = 2;
+
+extern "C" {
+    /// Entry stub addresses, indexed by vector.
+    static exception_stubs: [usize; 32];
+}
+
+/// Longest x86 instruction.
+const INSTRUCTION_BYTES: usize = 15;
+/// Page-fault error code bits for a write to a present page.
+const PAGE_FAULT_PRESENT_WRITE: u64 = 0b11;
+
+/// Registers saved by `exception_common`, lowest address first, followed
+/// by what the CPU pushed.
+#[derive(Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    pub fn is_from_user(&self) -> bool {
+        self.cs & 3 == 3
+    }
+
+    pub fn user_registers(&self) -> UserRegisters {
+        UserRegisters {
+            rax: self.rax,
+            rbx: self.rbx,
+            rcx: self.rcx,
+            rdx: self.rdx,
+            rsi: self.rsi,
+            rdi: self.rdi,
+            rbp: self.rbp,
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rip: self.rip,
+            rsp: self.rsp,
+            rflags: self.rflags,
+        }
+    }
+
+    /// Makes the return to ring 3 land in `registers`.
+    pub fn set_user_registers(&mut self, registers: &UserRegisters) {
+        self.rax = registers.rax;
+        self.rbx = registers.rbx;
+        self.rcx = registers.rcx;
+        self.rdx = registers.rdx;
+        self.rsi = registers.rsi;
+        self.rdi = registers.rdi;
+        self.rbp = registers.rbp;
+        self.r8 = registers.r8;
+        self.r9 = registers.r9;
+        self.r10 = registers.r10;
+        self.r11 = registers.r11;
+        self.r12 = registers.r12;
+        self.r13 = registers.r13;
+        self.r14 = registers.r14;
+        self.r15 = registers.r15;
+        self.rip = registers.rip;
+        self.rsp = registers.rsp;
+        self.rflags = registers.rflags;
+    }
+}
+
+/// Addresses of the 32 entry stubs, for the IDT.
+pub fn stubs() -> [usize; 32] {
+    unsafe { exception_stubs }
+}
+
+/// Entered from every stub. Time spent here on behalf of a process in
+/// ring 3 is charged to it as kernel time.
+#[no_mangle]
+extern "C" fn exception_dispatch(frame: &mut TrapFrame) {
+    if frame.vector == NMI_VECTOR {
+        nmi(frame);
+        return;
+    }
+    let from_user = frame.is_from_user();
+    if from_user {
+        scheduler::enter_mode(ExecMode::Kernel);
+    }
+    handle(frame, from_user);
+    if from_user {
+        scheduler::enter_mode(ExecMode::User);
+    }
+}
+
+/// An NMI can arrive while this CPU holds any lock, the log's and the
+/// scheduler's included, so it only writes straight to the serial port.
+fn nmi(frame: &TrapFrame) {
+    logging::serial_fmt(format_args!(
+        "[NMI] cpu{} at {:#018x}, resuming\n",
+        percpu::current().index(),
+        frame.rip
+    ));
+}
+
+fn handle(frame: &mut TrapFrame, from_user: bool) {
+    let exception = &EXCEPTIONS[frame.vector as usize & 31];
+    let (mnemonic, name) = (exception.mnemonic, exception.name);
+    // A write to a page shared since fork: copy it and retry.
+    if frame.vector == 14
+        && frame.error_code & PAGE_FAULT_PRESENT_WRITE == PAGE_FAULT_PRESENT_WRITE
+        && memory::address_space::copy_on_write(fault_address())
+    {
+        return;
+    }
+    // A copy to or from user memory hit a bad page: the copy fails instead.
+    if !from_user && matches!(frame.vector, 13 | 14) {
+        if let Some(fixup) = memory::user::fixup(frame.rip as usize) {
+            frame.rip = fixup as u64;
+            return;
+        }
+    }
+    let action = exception::policy(frame.vector as u8, from_user);
+
+    if action == FaultAction::Resume {
+        klog!(
+            LogLevel::Warn,
+            "{} {} at {:#018x}, resuming",
+            mnemonic,
+            name,
+            frame.rip
+        );
+        return;
+    }
+    dump(frame);
+
+    match action {
+        FaultAction::Deliver(signal) => {
+            let pid = percpu::current().current_pid();
+            klog!(
+                LogLevel::Error,
+                "pid {} gets {} ({}) after {} at {:#018x}",
+                pid,
+                signal.name(),
+                signal as u32,
+                mnemonic,
+                frame.rip
+            );
+            scheduler::signals(pid, |signals| signals.force(signal as u32));
+            let mut registers = frame.user_registers();
+            userspace::signal::deliver_on_return(pid, &mut registers);
+            frame.set_user_registers(&registers);
+        }
+        _ => panic!(
+            "{} {} in kernel at {:#018x}, error code {}",
+            mnemonic,
+            name,
+            frame.rip,
+            exception::describe_error_code(frame.vector as u8, frame.error_code as u32)
+        ),
+    }
+}
+
+/// CR2: the address the last page fault on this CPU touched.
+fn fault_address() -> usize {
+    let cr2: usize;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+    }
+    cr2
+}
+
+fn dump(frame: &TrapFrame) {
+    let exception = &EXCEPTIONS[frame.vector as usize & 31];
+    let cpu = percpu::current();
+    klog!(
+        LogLevel::Error,
+        "exception {} ({} {}) on cpu{} pid {} from {}",
+        frame.vector,
+        exception.mnemonic,
+        exception.name,
+        cpu.index(),
+        cpu.current_pid(),
+        if frame.is_from_user() {
+            "user"
+        } else {
+            "kernel"
+        }
+    );
+    if exception.error_code {
+        klog!(
+            LogLevel::Error,
+            "  error code {}",
+            exception::describe_error_code(frame.vector as u8, frame.error_code as u32)
+        );
+    }
+    klog!(
+        LogLevel::Error,
+        "  rax={:016x} rbx={:016x} rcx={:016x} rdx={:016x}",
+        frame.rax,
+        frame.rbx,
+        frame.rcx,
+        frame.rdx
+    );
+    klog!(
+        LogLevel::Error,
+        "  rsi={:016x} rdi={:016x} rbp={:016x} rsp={:016x}",
+        frame.rsi,
+        frame.rdi,
+        frame.rbp,
+        frame.rsp
+    );
+    klog!(
+        LogLevel::Error,
+        "  r8 ={:016x} r9 ={:016x} r10={:016x} r11={:016x}",
+        frame.r8,
+        frame.r9,
+        frame.r10,
+        frame.r11
+    );
+    klog!(
+        LogLevel::Error,
+        "  r12={:016x} r13={:016x} r14={:016x} r15={:016x}",
+        frame.r12,
+        frame.r13,
+        frame.r14,
+        frame.r15
+    );
+    klog!(
+        LogLevel::Error,
+        "  rip={:016x} rflags={:08x} cs={:04x} ss={:04x}",
+        frame.rip,
+        frame.rflags,
+        frame.cs & 0xFFFF,
+        frame.ss & 0xFFFF
+    );
+    let (cr0, cr2, cr3, cr4): (usize, usize, usize, usize);
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+    klog!(
+        LogLevel::Error,
+        "  cr0={:08x} cr2={:016x} cr3={:016x} cr4={:08x}",
+        cr0,
+        cr2,
+        cr3,
+        cr4
+    );
+    klog!(
+        LogLevel::Error,
+        "  code: {}",
+        InstructionBytes {
+            rip: frame.rip,
+            from_user: frame.is_from_user(),
+        }
+    );
+    // User RBP chains are not the kernel's to follow.
+    if !frame.is_from_user() {
+        backtrace::print_from(frame.rip as usize, frame.rbp as usize);
+    }
+}
+
+/// The bytes at the faulting RIP, or a note when they are not mapped. A
+/// user RIP is read like any other user memory, through `copy_from_user`.
+struct InstructionBytes {
+    rip: u64,
+    from_user: bool,
+}
+
+impl fmt::Display for InstructionBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.rip as usize;
+        let mut bytes = [0u8; INSTRUCTION_BYTES];
+        if self.from_user {
+            if memory::user::copy_from_user(&mut bytes, start).is_err() {
+                return f.write_str("<unmapped>");
+            }
+        } else {
+            let end = start.saturating_add(INSTRUCTION_BYTES - 1);
+            if !paging::is_mapped(start) || !paging::is_mapped(end) {
+                return f.write_str("<unmapped>");
+            }
+            for (offset, byte) in bytes.iter_mut().enumerate() {
+                *byte = unsafe { core::ptr::read_volatile((start + offset) as *const u8) };
+            }
+        }
+        for (offset, byte) in bytes.iter().enumerate() {
+            if offset != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
@@ -1,37 +1,101 @@
 use core::arch::asm;
 
-use crate::drivers::{keyboard, pic};
-use crate::{scheduler, timer};
+use crate::drivers::lapic;
+use crate::process::ExecMode;
+use crate::smp::percpu;
+use crate::timer::TickSource;
+use crate::{scheduler, smp, timer, userspace};
 
-#[no_mangle]
-pub extern "x86-interrupt" fn timer_interrupt() {
-    timer::tick();
-    let _ = scheduler::tick();
-    pic::end_of_interrupt(0);
+/// CPU-pushed state on entry to a handler. Long mode always pushes the
+/// stack pointer and segment, privilege change or not.
+#[derive(Debug)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
 }
 
-#[no_mangle]
-pub extern "x86-interrupt" fn keyboard_interrupt() {
-    keyboard::handle_interrupt();
-    pic::end_of_interrupt(1);
+impl InterruptStackFrame {
+    /// Privilege level the CPU was running at when the interrupt arrived.
+    pub fn mode(&self) -> ExecMode {
+        if self.cs & 3 == 3 {
+            ExecMode::User
+        } else {
+            ExecMode::Kernel
+        }
+    }
+
+    /// Switches to the kernel GS base if the interrupt came from ring 3,
+    /// and starts charging the process kernel time. Take it before
+    /// anything reaches per-CPU data and keep it alive for the whole
+    /// handler.
+    pub fn kernel_gs(&self) -> KernelGs {
+        let from_user = self.mode() == ExecMode::User;
+        if from_user {
+            unsafe { asm!("swapgs", options(nomem, nostack, preserves_flags)) };
+            scheduler::enter_mode(ExecMode::Kernel);
+        }
+        KernelGs(from_user)
+    }
 }
 
-#[no_mangle]
-pub extern "x86-interrupt" fn page_fault_handler() {
-    let fault_addr: u32;
-    unsafe {
-        asm!("mov {0:e}, cr2", out(reg) fault_addr, options(nostack, preserves_flags));
+/// Gives ring 3 its GS base back when dropped, right before the handler
+/// returns, and charges the process user time again.
+pub struct KernelGs(bool);
+
+impl Drop for KernelGs {
+    fn drop(&mut self) {
+        if self.0 {
+            scheduler::enter_mode(ExecMode::User);
+            unsafe { asm!("swapgs", options(nomem, nostack, preserves_flags)) };
+        }
     }
-    println!("Page fault at: {:#x}", fault_addr);
-    loop {
-        x86::halt();
+}
+
+/// Last step of an interrupt that may have made another process more
+/// deserving of the CPU: if it arrived in ring 3, switches away when a
+/// reschedule is due, and acts on fatal or stopping signals before the
+/// process goes back. Call after EOI, with the `KernelGs` still held.
+pub fn preempt(mode: ExecMode) {
+    if mode == ExecMode::User {
+        scheduler::preempt();
+        userspace::signal::deliver_on_interrupt(percpu::current().current_pid());
     }
 }
 
+/// The per-CPU tick: the BSP's PIT broadcast, or this CPU's LAPIC timer,
+/// which also runs expired timers.
 #[no_mangle]
-pub extern "x86-interrupt" fn double_fault_handler() {
-    println!("Double fault");
-    loop {
-        x86::halt();
+pub extern "x86-interrupt" fn local_timer_interrupt(frame: InterruptStackFrame) {
+    let _gs = frame.kernel_gs();
+    if timer::source() == TickSource::Pit {
+        smp::local_tick();
+    } else {
+        timer::events::handle_local_timer();
     }
+    lapic::end_of_interrupt();
+    preempt(frame.mode());
+}
+
+#[no_mangle]
+pub extern "x86-interrupt" fn reschedule_interrupt(frame: InterruptStackFrame) {
+    let _gs = frame.kernel_gs();
+    scheduler::handle_reschedule();
+    lapic::end_of_interrupt();
+    preempt(frame.mode());
+}
+
+#[no_mangle]
+pub extern "x86-interrupt" fn tlb_shootdown_interrupt(frame: InterruptStackFrame) {
+    let _gs = frame.kernel_gs();
+    smp::handle_tlb_shootdown();
+    lapic::end_of_interrupt();
+}
+
+#[no_mangle]
+pub extern "x86-interrupt" fn spurious_interrupt(_frame: InterruptStackFrame) {
+    // Spurious LAPIC interrupts must not be acknowledged.
 }
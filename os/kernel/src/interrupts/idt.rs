@@ -1,15 +1,28 @@
 use core::arch::asm;
 
-use super::handlers;
+use super::{exceptions, handlers, irq};
+use crate::arch::x86::gdt::KERNEL_CODE_SELECTOR;
+use crate::arch::x86::tss;
+use crate::drivers::lapic;
+use crate::smp;
+use crate::syscalls::entry as syscall_entry;
 
-#[repr(C, packed)]
+/// Present, ring 0, 64-bit interrupt gate.
+const INTERRUPT_GATE: u8 = 0x8E;
+/// Same, but ring 3 may raise it with INT.
+const USER_INTERRUPT_GATE: u8 = 0xEE;
+
+#[repr(C)]
 #[derive(Clone, Copy)]
 struct IdtEntry {
     offset_low: u16,
     selector: u16,
-    zero: u8,
+    /// Interrupt stack table slot in bits 0-2; 0 keeps the current stack.
+    ist: u8,
     flags: u8,
-    offset_high: u16,
+    offset_mid: u16,
+    offset_high: u32,
+    reserved: u32,
 }
 
 impl IdtEntry {
@@ -17,41 +30,81 @@ impl IdtEntry {
         Self {
             offset_low: 0,
             selector: 0,
-            zero: 0,
+            ist: 0,
             flags: 0,
+            offset_mid: 0,
             offset_high: 0,
+            reserved: 0,
         }
     }
 
     fn new(handler: usize, flags: u8) -> Self {
         Self {
             offset_low: (handler & 0xFFFF) as u16,
-            selector: 0x08,
-            zero: 0,
+            selector: KERNEL_CODE_SELECTOR,
+            ist: 0,
             flags,
-            offset_high: ((handler >> 16) & 0xFFFF) as u16,
+            offset_mid: ((handler >> 16) & 0xFFFF) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
         }
     }
+
+    /// Switches to the given IST stack on entry, whatever the CPU was doing.
+    fn with_ist(mut self, ist: u8) -> Self {
+        self.ist = ist & 0b111;
+        self
+    }
 }
 
 #[repr(C, packed)]
 struct Idtr {
     limit: u16,
-    base: u32,
+    base: u64,
 }
 
 static mut IDT: [IdtEntry; 256] = [IdtEntry::missing(); 256];
 
 pub fn init() {
     unsafe {
-        IDT[32] = IdtEntry::new(handlers::timer_interrupt as usize, 0x8E);
-        IDT[33] = IdtEntry::new(handlers::keyboard_interrupt as usize, 0x8E);
-        IDT[14] = IdtEntry::new(handlers::page_fault_handler as usize, 0x8E);
-        IDT[8] = IdtEntry::new(handlers::double_fault_handler as usize, 0x8E);
+        for (vector, stub) in exceptions::stubs().into_iter().enumerate() {
+            IDT[vector] = IdtEntry::new(stub, INTERRUPT_GATE);
+        }
+        IDT[2] = IDT[2].with_ist(tss::NMI_IST);
+        IDT[8] = IDT[8].with_ist(tss::DOUBLE_FAULT_IST);
+        IDT[18] = IDT[18].with_ist(tss::MACHINE_CHECK_IST);
+        for (line, stub) in irq::stubs().into_iter().enumerate() {
+            IDT[irq::IRQ_BASE as usize + line] = IdtEntry::new(stub, INTERRUPT_GATE);
+        }
+        IDT[smp::TICK_VECTOR as usize] = IdtEntry::new(
+            handlers::local_timer_interrupt as *const () as usize,
+            INTERRUPT_GATE,
+        );
+        IDT[smp::RESCHEDULE_VECTOR as usize] = IdtEntry::new(
+            handlers::reschedule_interrupt as *const () as usize,
+            INTERRUPT_GATE,
+        );
+        IDT[smp::TLB_SHOOTDOWN_VECTOR as usize] = IdtEntry::new(
+            handlers::tlb_shootdown_interrupt as *const () as usize,
+            INTERRUPT_GATE,
+        );
+        IDT[lapic::SPURIOUS_VECTOR as usize] = IdtEntry::new(
+            handlers::spurious_interrupt as *const () as usize,
+            INTERRUPT_GATE,
+        );
+        IDT[syscall_entry::SYSCALL_VECTOR as usize] =
+            IdtEntry::new(syscall_entry::int80_stub(), USER_INTERRUPT_GATE);
+    }
 
+    load();
+}
+
+/// Loads the shared IDT on the calling CPU.
+pub fn load() {
+    unsafe {
         let idtr = Idtr {
             limit: (core::mem::size_of::<[IdtEntry; 256]>() - 1) as u16,
-            base: &IDT as *const _ as u32,
+            base: core::ptr::addr_of!(IDT) as u64,
         };
 
         asm!("lidt [{0}]", in(reg) &idtr, options(readonly, nostack, preserves_flags));
@@ -0,0 +1,250 @@
+use spin::Mutex;
+
+use super::controller;
+use super::handlers::{self, InterruptStackFrame};
+use crate::arch::x86::without_interrupts;
+use crate::process::ExecMode;
+
+/// Vector of IRQ line 0, for the 8259 pair and the IOAPIC alike.
+pub const IRQ_BASE: u8 = 32;
+pub const IRQ_LINES: usize = 16;
+/// Handlers one line can carry, fixed so drivers can register before the
+/// heap is up.
+const MAX_SHARED_HANDLERS: usize = 4;
+
+/// Whether a handler recognised the interrupt as its device's. Handlers on
+/// a shared line return `NotMine` for the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    NotMine,
+    /// Handled, but the device is serviced elsewhere (a userspace driver):
+    /// the line stays masked until someone calls `unmask`.
+    Deferred,
+}
+
+/// Runs in interrupt context with the `ctx` it was registered with and the
+/// mode the CPU was interrupted in. Must not register or unregister.
+pub type Handler = fn(ctx: usize, mode: ExecMode) -> IrqReturn;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandlerId {
+    line: u8,
+    serial: u32,
+}
+
+/// Per-line counters, printed by `dump`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct IrqStats {
+    count: u64,
+    /// Interrupts no handler claimed.
+    unhandled: u64,
+    /// Interrupts the controller reported that were never raised.
+    spurious: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Action {
+    serial: u32,
+    handler: Handler,
+    ctx: usize,
+}
+
+struct Line {
+    actions: [Option<Action>; MAX_SHARED_HANDLERS],
+    stats: IrqStats,
+    /// Kept here too, so a controller switch can restore it.
+    masked: bool,
+}
+
+struct IrqTable {
+    lines: [Line; IRQ_LINES],
+    next_serial: u32,
+}
+
+static TABLE: Mutex<IrqTable> = Mutex::new(IrqTable {
+    lines: [const {
+        Line {
+            actions: [None; MAX_SHARED_HANDLERS],
+            stats: IrqStats {
+                count: 0,
+                unhandled: 0,
+                spurious: 0,
+            },
+            masked: true,
+        }
+    }; IRQ_LINES],
+    next_serial: 0,
+});
+
+macro_rules! irq_stubs {
+    ($($line:literal)*) => {
+        [$({
+            extern "x86-interrupt" fn stub(frame: InterruptStackFrame) {
+                let _gs = frame.kernel_gs();
+                dispatch($line, frame.mode());
+                handlers::preempt(frame.mode());
+            }
+            stub as *const () as usize
+        },)*]
+    };
+}
+
+/// IDT entry points for lines 0..16, installed at `IRQ_BASE + line`.
+pub fn stubs() -> [usize; IRQ_LINES] {
+    irq_stubs!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)
+}
+
+/// Hooks `handler` onto `line`, unmasking it for the first handler. Other
+/// handlers already on the line keep running: lines are shared.
+pub fn register(line: u8, handler: Handler, ctx: usize) -> Result<HandlerId, &'static str> {
+    without_interrupts(|| {
+        let mut table = TABLE.lock();
+        let serial = table.next_serial;
+        let entry = table
+            .lines
+            .get_mut(line as usize)
+            .ok_or("IRQ line out of range")?;
+        let first = entry.actions.iter().all(Option::is_none);
+        let slot = entry
+            .actions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or("IRQ line has no free handler slot")?;
+        *slot = Some(Action {
+            serial,
+            handler,
+            ctx,
+        });
+        if first {
+            entry.masked = false;
+            controller::active().unmask(line);
+        }
+        table.next_serial += 1;
+        Ok(HandlerId { line, serial })
+    })
+}
+
+/// Removes a handler, masking the line once nothing is left on it.
+pub fn unregister(id: HandlerId) -> bool {
+    without_interrupts(|| {
+        let mut table = TABLE.lock();
+        let entry = &mut table.lines[id.line as usize];
+        let Some(slot) = entry
+            .actions
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|action| action.serial == id.serial))
+        else {
+            return false;
+        };
+        *slot = None;
+        if entry.actions.iter().all(Option::is_none) {
+            entry.masked = true;
+            controller::active().mask(id.line);
+        }
+        true
+    })
+}
+
+pub fn mask(line: u8) {
+    set_masked(line, true);
+}
+
+pub fn unmask(line: u8) {
+    set_masked(line, false);
+}
+
+fn set_masked(line: u8, masked: bool) {
+    without_interrupts(|| {
+        let mut table = TABLE.lock();
+        let Some(entry) = table.lines.get_mut(line as usize) else {
+            return;
+        };
+        entry.masked = masked;
+        let controller = controller::active();
+        if masked {
+            controller.mask(line);
+        } else {
+            controller.unmask(line);
+        }
+    });
+}
+
+/// Runs `switch`, which changes the active controller, with the line table
+/// locked, then programs every line's masked state into the new one.
+pub(super) fn switch_controller(switch: impl FnOnce()) {
+    without_interrupts(|| {
+        let table = TABLE.lock();
+        switch();
+        let controller = controller::active();
+        for (line, entry) in table.lines.iter().enumerate() {
+            if entry.masked {
+                controller.mask(line as u8);
+            } else {
+                controller.unmask(line as u8);
+            }
+        }
+    });
+}
+
+pub fn handlers(line: u8) -> usize {
+    without_interrupts(|| {
+        TABLE
+            .lock()
+            .lines
+            .get(line as usize)
+            .map_or(0, |line| line.actions.iter().flatten().count())
+    })
+}
+
+/// Runs every handler on `line`, masks it if any deferred, then
+/// acknowledges the interrupt.
+fn dispatch(line: u8, mode: ExecMode) {
+    let controller = controller::active();
+    {
+        let mut table = TABLE.lock();
+        let entry = &mut table.lines[line as usize];
+        if controller.is_spurious(line) {
+            entry.stats.spurious += 1;
+            return;
+        }
+        entry.stats.count += 1;
+        let mut outcome = IrqReturn::NotMine;
+        for action in entry.actions.iter().flatten() {
+            match (action.handler)(action.ctx, mode) {
+                IrqReturn::Deferred => outcome = IrqReturn::Deferred,
+                IrqReturn::Handled if outcome == IrqReturn::NotMine => outcome = IrqReturn::Handled,
+                _ => {}
+            }
+        }
+        match outcome {
+            IrqReturn::NotMine => entry.stats.unhandled += 1,
+            IrqReturn::Deferred => {
+                entry.masked = true;
+                controller.mask(line);
+            }
+            IrqReturn::Handled => {}
+        }
+    }
+    controller.end_of_interrupt(line);
+}
+
+/// Prints the counters of every line that has fired or has a handler.
+pub fn dump() {
+    println!("irq: controller {}", controller::active().name());
+    let table = without_interrupts(|| {
+        let table = TABLE.lock();
+        core::array::from_fn::<_, IRQ_LINES, _>(|line| {
+            let line = &table.lines[line];
+            (line.stats, line.actions.iter().flatten().count())
+        })
+    });
+    for (line, (stats, handlers)) in table.into_iter().enumerate() {
+        if stats.count != 0 || stats.spurious != 0 || handlers != 0 {
+            println!(
+                "irq {:2}: count={} unhandled={} spurious={} handlers={}",
+                line, stats.count, stats.unhandled, stats.spurious, handlers
+            );
+        }
+    }
+}
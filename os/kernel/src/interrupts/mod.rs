@@ -1,2 +1,6 @@
+pub mod controller;
+pub mod exceptions;
 pub mod handlers;
 pub mod idt;
+pub mod irq;
+pub mod user;
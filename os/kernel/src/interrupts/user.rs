@@ -0,0 +1,142 @@
+use spin::Mutex;
+
+use super::irq::{self, HandlerId, IrqReturn, IRQ_LINES};
+use crate::arch::x86::without_interrupts;
+use crate::process::ExecMode;
+use crate::scheduler;
+use crate::security::Capability;
+
+/// An IRQ line handed to a userspace driver server.
+#[derive(Clone, Copy)]
+struct Binding {
+    pid: u32,
+    /// Notification bits the server receives when the line fires.
+    bits: u64,
+    /// Forwarded and not yet acknowledged; the line is masked meanwhile.
+    in_flight: bool,
+    /// `None` only while `bind` is registering the forwarder.
+    handler: Option<HandlerId>,
+}
+
+static BINDINGS: Mutex<[Option<Binding>; IRQ_LINES]> = Mutex::new([None; IRQ_LINES]);
+
+/// Routes `line` to `pid`: each interrupt masks the line and signals
+/// `bits` on the process's notification word until `ack`. Needs the
+/// `DriverIo` capability. A line has at most one server and no in-kernel
+/// handlers: the forwarder defers every interrupt, so it cannot tell the
+/// server's device from a kernel driver's.
+pub fn bind(line: u8, pid: u32, bits: u64) -> Result<(), &'static str> {
+    if !scheduler::can(pid, Capability::DriverIo) {
+        return Err("binding an IRQ needs the DriverIo capability");
+    }
+    if bits == 0 {
+        return Err("IRQ notification bits must be non-zero");
+    }
+    if irq::handlers(line) != 0 {
+        return Err("IRQ line is used by the kernel");
+    }
+    without_interrupts(|| {
+        let mut bindings = BINDINGS.lock();
+        let slot = bindings
+            .get_mut(line as usize)
+            .ok_or("IRQ line out of range")?;
+        if slot.is_some() {
+            return Err("IRQ line already bound");
+        }
+        *slot = Some(Binding {
+            pid,
+            bits,
+            in_flight: false,
+            handler: None,
+        });
+        Ok(())
+    })?;
+
+    // Registered with the binding table unlocked: the forwarder takes it
+    // from inside the IRQ dispatcher, which holds the line table.
+    let registered = irq::register(line, forward, line as usize);
+    without_interrupts(|| {
+        let mut bindings = BINDINGS.lock();
+        match registered {
+            Ok(id) => {
+                if let Some(binding) = bindings[line as usize].as_mut() {
+                    binding.handler = Some(id);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                bindings[line as usize] = None;
+                Err(err)
+            }
+        }
+    })
+}
+
+/// The server finished with the forwarded interrupt: unmask the line.
+pub fn ack(line: u8, pid: u32) -> Result<(), &'static str> {
+    without_interrupts(|| {
+        let mut bindings = BINDINGS.lock();
+        let binding = bindings
+            .get_mut(line as usize)
+            .and_then(Option::as_mut)
+            .filter(|binding| binding.pid == pid)
+            .ok_or("IRQ line is not bound to the caller")?;
+        if !binding.in_flight {
+            return Err("no interrupt to acknowledge");
+        }
+        binding.in_flight = false;
+        Ok(())
+    })?;
+    irq::unmask(line);
+    Ok(())
+}
+
+pub fn unbind(line: u8, pid: u32) -> Result<(), &'static str> {
+    let binding = without_interrupts(|| {
+        BINDINGS
+            .lock()
+            .get_mut(line as usize)
+            .filter(|slot| slot.is_some_and(|binding| binding.pid == pid))
+            .and_then(Option::take)
+            .ok_or("IRQ line is not bound to the caller")
+    })?;
+    drop_binding(binding);
+    Ok(())
+}
+
+/// Unbinds every line `pid` holds; called when it exits.
+pub fn release(pid: u32) {
+    let released = without_interrupts(|| {
+        let mut bindings = BINDINGS.lock();
+        core::array::from_fn::<_, IRQ_LINES, _>(|line| {
+            let slot = &mut bindings[line];
+            slot.filter(|binding| binding.pid == pid)
+                .and_then(|_| slot.take())
+        })
+    });
+    for binding in released.into_iter().flatten() {
+        drop_binding(binding);
+    }
+}
+
+/// Removes the forwarder. A line the server left masked stays masked
+/// until the next `irq::register` on it.
+fn drop_binding(binding: Binding) {
+    if let Some(id) = binding.handler {
+        irq::unregister(id);
+    }
+}
+
+fn forward(line: usize, _mode: ExecMode) -> IrqReturn {
+    let target = BINDINGS.lock()[line].as_mut().map(|binding| {
+        binding.in_flight = true;
+        (binding.pid, binding.bits)
+    });
+    match target {
+        Some((pid, bits)) => {
+            scheduler::notify(pid, bits);
+            IrqReturn::Deferred
+        }
+        None => IrqReturn::NotMine,
+    }
+}
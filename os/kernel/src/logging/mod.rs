@@ -2,7 +2,7 @@ use core::fmt;
 
 use x86::io::outb;
 
-use crate::vga;
+use crate::{timer, vga};
 
 #[derive(Clone, Copy, Debug)]
 pub enum LogLevel {
@@ -27,7 +27,9 @@ pub fn init() {
     serial_write_str("[INFO] serial logger initialized\n");
 }
 
+/// Writes one line prefixed with seconds since boot and the level.
 pub fn log(level: LogLevel, args: fmt::Arguments<'_>) {
+    let now_ns = timer::clocksource::now_ns();
     let mut line_buf = [0u8; 512];
     let mut cursor = 0usize;
 
@@ -36,7 +38,13 @@ pub fn log(level: LogLevel, args: fmt::Arguments<'_>) {
             buffer: &mut line_buf,
             cursor: &mut cursor,
         },
-        format_args!("[{}] {}", level.as_str(), args),
+        format_args!(
+            "[{:5}.{:06}] [{}] {}",
+            now_ns / 1_000_000_000,
+            now_ns % 1_000_000_000 / 1000,
+            level.as_str(),
+            args
+        ),
     );
 
     let msg = core::str::from_utf8(&line_buf[..cursor]).unwrap_or("[log utf8 err]");
@@ -46,6 +54,12 @@ pub fn log(level: LogLevel, args: fmt::Arguments<'_>) {
     vga::_print(format_args!("{}\n", msg));
 }
 
+/// Formats straight to the serial port, without a level prefix or VGA echo.
+/// Meant for machine-readable dumps.
+pub fn serial_fmt(args: fmt::Arguments<'_>) {
+    let _ = fmt::write(&mut SerialWriter, args);
+}
+
 pub fn serial_write_str(s: &str) {
     for b in s.bytes() {
         serial_write_byte(b);
@@ -58,6 +72,15 @@ pub fn serial_write_byte(byte: u8) {
     }
 }
 
+struct SerialWriter;
+
+impl fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial_write_str(s);
+        Ok(())
+    }
+}
+
 struct BufferWriter<'a> {
     buffer: &'a mut [u8],
     cursor: &'a mut usize,
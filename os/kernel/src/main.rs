@@ -4,30 +4,45 @@
 
 extern crate alloc;
 
+#[macro_use]
+mod vga;
+#[macro_use]
+mod logging;
+
+mod acpi;
 mod arch;
+mod backtrace;
 mod drivers;
 mod filesystem;
 mod graphics;
 mod interrupts;
 mod ipc;
-mod logging;
 mod memory;
 mod networking;
 mod process;
 mod scheduler;
 mod security;
+mod smp;
 mod syscalls;
 mod timer;
-mod vga;
+mod userspace;
 
+use alloc::boxed::Box;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
 use logging::LogLevel;
+use multiboot2::{BootInformation, BootInformationHeader};
 use x86::irq;
 
-core::arch::global_asm!(include_str!("start.S"));
+core::arch::global_asm!(include_str!("start.S"), options(att_syntax));
+
+/// How often the main loop logs the scheduler state.
+const STATUS_PERIOD_NS: u64 = 1_000_000_000;
+/// Set by the status timer, cleared by the main loop once it has logged.
+static STATUS_DUE: AtomicBool = AtomicBool::new(false);
 
 #[no_mangle]
-pub extern "C" fn kernel_main(multiboot_magic: u32, multiboot_info: u32) -> ! {
+pub extern "C" fn kernel_main(multiboot_magic: u32, multiboot_info: usize) -> ! {
     vga::init();
     logging::init();
     klog!(LogLevel::Info, "RustOS Kernel Booted");
@@ -40,38 +55,67 @@ pub extern "C" fn kernel_main(multiboot_magic: u32, multiboot_info: u32) -> ! {
         );
         loop {}
     }
+    let boot_info =
+        unsafe { BootInformation::load(multiboot_info as *const BootInformationHeader) }
+            .expect("valid multiboot2 info");
 
+    backtrace::init(multiboot_info);
     arch::x86::gdt::init();
+    arch::x86::cpu::init();
+    smp::init_bsp();
     interrupts::idt::init();
     drivers::pic::init();
     timer::init(100);
-    memory::init(multiboot_info as usize);
+    drivers::keyboard::init();
+    memory::init(&boot_info);
+    acpi::init(&boot_info);
+    timer::init_wall_clock();
     scheduler::init();
     filesystem::init();
     networking::init();
     ipc::init();
-    syscalls::init();
+    let cmdline = boot_info
+        .command_line_tag()
+        .and_then(|tag| tag.cmdline().ok())
+        .unwrap_or("");
+    syscalls::init(cmdline);
 
     unsafe { irq::enable() };
 
     klog!(LogLevel::Info, "interrupts enabled");
 
-    let mut last_tick = 0;
+    smp::init();
+    timer::clocksource::init();
+    arch::x86::usermode::self_test();
+    userspace::init();
+
+    // The timer only raises a flag: the dumps take locks that interrupt
+    // context must not.
+    timer::hrtimer::arm_periodic(
+        STATUS_PERIOD_NS,
+        Box::new(|| STATUS_DUE.store(true, Ordering::Relaxed)),
+    )
+    .expect("status period is non-zero");
+    let mut dumps = 0u64;
     loop {
-        let ticks = timer::uptime_ticks();
-        if ticks != last_tick && ticks % 100 == 0 {
-            klog!(LogLevel::Trace, "uptime ticks: {}", ticks);
+        if STATUS_DUE.swap(false, Ordering::Relaxed) {
+            klog!(LogLevel::Trace, "uptime ticks: {}", timer::uptime_ticks());
             scheduler::dump();
-            last_tick = ticks;
+            dumps += 1;
+            if dumps.is_multiple_of(10) {
+                scheduler::trace::dump();
+                interrupts::irq::dump();
+            }
         }
-        x86::halt();
+        timer::events::idle();
     }
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     klog!(LogLevel::Error, "KERNEL PANIC: {}", info);
+    backtrace::print_current();
     loop {
-        x86::halt();
+        unsafe { x86::halt() };
     }
 }
@@ -0,0 +1,220 @@
+use crate::arch::x86::paging::{
+    self, ADDRESS_MASK, ENTRIES, PAGE_PRESENT, PAGE_SIZE, PAGE_USER, PAGE_WRITABLE,
+};
+
+use super::frames;
+use crate::smp;
+
+/// Lowest user address. PML4 slot 0 holds the kernel's identity map, which
+/// every address space shares, so user mappings start at slot 1.
+pub const USER_BASE: usize = 0x0000_0080_0000_0000;
+/// End of the lower canonical half.
+pub const USER_END: usize = 0x0000_8000_0000_0000;
+
+/// Software bit in a page entry: the page is shared read-only since a
+/// `fork`, and the first write gives the writer its own copy.
+const PAGE_COPY_ON_WRITE: u64 = 1 << 9;
+/// Intermediate tables allow everything; leaf entries decide.
+const TABLE_FLAGS: u64 = PAGE_PRESENT | PAGE_WRITABLE | PAGE_USER;
+const PML4_USER_SLOTS: core::ops::Range<usize> = 1..ENTRIES / 2;
+
+type Table = [u64; ENTRIES];
+
+fn table(frame: usize) -> &'static mut Table {
+    unsafe { &mut *(frame as *mut Table) }
+}
+
+/// A process's page tables: the kernel's identity map plus user pages in
+/// `USER_BASE..USER_END`, each backed by its own frame or one shared
+/// copy-on-write with other address spaces. Dropping it frees them all,
+/// after a TLB shootdown, so it must not be dropped under a lock.
+pub struct AddressSpace {
+    root: usize,
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, &'static str> {
+        let root = frames::alloc().ok_or("out of page frames")?;
+        table(root)[0] = table(paging::root_table_address())[0];
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    pub fn is_active(&self) -> bool {
+        paging::current_root() == self.root
+    }
+
+    pub fn activate(&self) {
+        unsafe { paging::load_root(self.root) };
+    }
+
+    /// Maps a zeroed page at `addr` with `flags`, which gain `PAGE_PRESENT`
+    /// and `PAGE_USER`, and returns its frame.
+    pub fn map(&mut self, addr: usize, flags: u64) -> Result<usize, &'static str> {
+        if !addr.is_multiple_of(PAGE_SIZE) || !(USER_BASE..USER_END).contains(&addr) {
+            return Err("user page outside the user half");
+        }
+        let entry = self.entry(addr)?;
+        if *entry & PAGE_PRESENT != 0 {
+            return Err("user page already mapped");
+        }
+        let frame = frames::alloc().ok_or("out of page frames")?;
+        *entry = frame as u64 | flags | PAGE_PRESENT | PAGE_USER;
+        Ok(frame)
+    }
+
+    /// Physical address behind user address `addr`.
+    pub fn translate(&self, addr: usize) -> Option<usize> {
+        let (entry, _) = paging::leaf_entry(self.root, addr)?;
+        let frame = unsafe { *entry } & ADDRESS_MASK;
+        Some(frame as usize + addr % PAGE_SIZE)
+    }
+
+    /// Copies `data` to user address `addr` through the frames behind it,
+    /// so this space need not be active. Every page must be mapped.
+    pub fn write(&self, addr: usize, data: &[u8]) -> Result<(), &'static str> {
+        let mut done = 0;
+        while done < data.len() {
+            let at = addr + done;
+            let len = (PAGE_SIZE - at % PAGE_SIZE).min(data.len() - done);
+            let frame = self.translate(at).ok_or("user page not mapped")?;
+            unsafe { core::ptr::copy_nonoverlapping(data[done..].as_ptr(), frame as *mut u8, len) };
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// A copy of the user half that shares every page copy-on-write. Pages
+    /// this space could write become read-only here too until written; the
+    /// caller shoots the old entries down on other CPUs once it holds no
+    /// locks, with `smp::tlb_shootdown`.
+    pub fn fork(&self) -> Result<Self, &'static str> {
+        let mut child = Self::new()?;
+        let mut result = Ok(());
+        self.for_each_page(|addr, entry| {
+            if result.is_err() {
+                return;
+            }
+            let mut value = *entry;
+            if value & (PAGE_WRITABLE | PAGE_COPY_ON_WRITE) != 0 {
+                value = value & !PAGE_WRITABLE | PAGE_COPY_ON_WRITE;
+                *entry = value;
+            }
+            match child.entry(addr) {
+                Ok(slot) => {
+                    frames::share((value & ADDRESS_MASK) as usize);
+                    *slot = value;
+                }
+                Err(err) => result = Err(err),
+            }
+        });
+        if self.is_active() {
+            self.activate();
+        }
+        result.map(|()| child)
+    }
+
+    /// The leaf entry for `addr`, allocating the tables above it.
+    fn entry(&mut self, addr: usize) -> Result<&'static mut u64, &'static str> {
+        let mut frame = self.root;
+        for shift in [39, 30, 21] {
+            let entry = &mut table(frame)[(addr >> shift) % ENTRIES];
+            if *entry & PAGE_PRESENT == 0 {
+                let next = frames::alloc().ok_or("out of page frames")?;
+                *entry = next as u64 | TABLE_FLAGS;
+            }
+            frame = (*entry & ADDRESS_MASK) as usize;
+        }
+        Ok(&mut table(frame)[(addr >> 12) % ENTRIES])
+    }
+
+    /// Calls `f` with every present user page and its leaf entry.
+    fn for_each_page(&self, mut f: impl FnMut(usize, &mut u64)) {
+        fn walk(frame: usize, level: u32, base: usize, f: &mut impl FnMut(usize, &mut u64)) {
+            let shift = 12 + 9 * level;
+            for (index, entry) in table(frame).iter_mut().enumerate() {
+                if *entry & PAGE_PRESENT == 0 {
+                    continue;
+                }
+                let addr = base + (index << shift);
+                if level == 0 {
+                    f(addr, entry);
+                } else {
+                    walk((*entry & ADDRESS_MASK) as usize, level - 1, addr, f);
+                }
+            }
+        }
+        for slot in PML4_USER_SLOTS {
+            let entry = table(self.root)[slot];
+            if entry & PAGE_PRESENT != 0 {
+                walk((entry & ADDRESS_MASK) as usize, 2, slot << 39, &mut f);
+            }
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            unsafe { paging::load_root(paging::root_table_address()) };
+        }
+        // No CPU may keep a translation into the frames freed below.
+        smp::tlb_shootdown(smp::FLUSH_ALL);
+        self.for_each_page(|_, entry| frames::release((*entry & ADDRESS_MASK) as usize));
+
+        fn free_tables(frame: usize, level: u32) {
+            for entry in table(frame).iter() {
+                if entry & PAGE_PRESENT != 0 {
+                    let next = (entry & ADDRESS_MASK) as usize;
+                    if level > 0 {
+                        free_tables(next, level - 1);
+                    }
+                    frames::release(next);
+                }
+            }
+        }
+        for slot in PML4_USER_SLOTS {
+            let entry = table(self.root)[slot];
+            if entry & PAGE_PRESENT != 0 {
+                let pdpt = (entry & ADDRESS_MASK) as usize;
+                free_tables(pdpt, 1);
+                frames::release(pdpt);
+            }
+        }
+        frames::release(self.root);
+    }
+}
+
+/// Resolves a write to the copy-on-write page at `addr` in the current
+/// address space: the last sharer takes the frame over, others get a
+/// copy. Returns false if `addr` is not such a page or no frame is left.
+pub fn copy_on_write(addr: usize) -> bool {
+    if !(USER_BASE..USER_END).contains(&addr) {
+        return false;
+    }
+    let Some((entry, _)) = paging::leaf_entry(paging::current_root(), addr) else {
+        return false;
+    };
+    let value = unsafe { *entry };
+    if value & PAGE_COPY_ON_WRITE == 0 {
+        return false;
+    }
+    let frame = (value & ADDRESS_MASK) as usize;
+    let flags = value & !ADDRESS_MASK & !PAGE_COPY_ON_WRITE | PAGE_WRITABLE;
+    let new = if frames::ref_count(frame) == 1 {
+        frame
+    } else {
+        let Some(copy) = frames::alloc() else {
+            return false;
+        };
+        unsafe { core::ptr::copy_nonoverlapping(frame as *const u8, copy as *mut u8, PAGE_SIZE) };
+        frames::release(frame);
+        copy
+    };
+    unsafe { *entry = new as u64 | flags };
+    paging::invalidate(addr);
+    true
+}
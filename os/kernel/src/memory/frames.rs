@@ -0,0 +1,137 @@
+use multiboot2::{BootInformation, MemoryAreaType};
+use spin::Mutex;
+
+use crate::arch::x86::paging::PAGE_SIZE;
+use crate::logging::LogLevel;
+
+/// Most frames the pool manages: 32 MiB, plenty for page tables and the
+/// few user images there are.
+const MAX_FRAMES: usize = 8192;
+/// The boot tables identity-map the first GiB, so frames below it can be
+/// filled and read through their physical address.
+const IDENTITY_END: usize = 1 << 30;
+
+extern "C" {
+    /// End of the kernel image, from the linker script.
+    static kernel_end: u8;
+}
+
+/// One contiguous run of free RAM, handed out a frame at a time. Each
+/// frame counts its users: address spaces sharing a page after `fork` all
+/// hold a reference, and the frame is free again once none do.
+struct Pool {
+    base: usize,
+    frames: usize,
+    refs: [u16; MAX_FRAMES],
+    /// Where the search for a free frame starts.
+    next: usize,
+    free: usize,
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    base: 0,
+    frames: 0,
+    refs: [0; MAX_FRAMES],
+    next: 0,
+    free: 0,
+});
+
+/// Takes the largest available region below `IDENTITY_END` that does not
+/// overlap the kernel, the multiboot information or the sections GRUB
+/// loaded with it.
+pub fn init(boot_info: &BootInformation) {
+    let mut reserved_end = core::ptr::addr_of!(kernel_end) as usize;
+    reserved_end = reserved_end.max(boot_info.end_address());
+    if let Some(sections) = boot_info.elf_sections() {
+        for section in sections {
+            reserved_end = reserved_end.max(section.end_address() as usize);
+        }
+    }
+    for module in boot_info.module_tags() {
+        reserved_end = reserved_end.max(module.end_address() as usize);
+    }
+
+    let mut best = (0, 0);
+    if let Some(map) = boot_info.memory_map_tag() {
+        for area in map.memory_areas() {
+            if area.typ() != MemoryAreaType::Available {
+                continue;
+            }
+            let start = (area.start_address() as usize).max(reserved_end);
+            let start = start.next_multiple_of(PAGE_SIZE);
+            let end = (area.end_address() as usize).min(IDENTITY_END) / PAGE_SIZE * PAGE_SIZE;
+            if end > start && end - start > best.1 - best.0 {
+                best = (start, end);
+            }
+        }
+    }
+
+    let mut pool = POOL.lock();
+    pool.base = best.0;
+    pool.frames = ((best.1 - best.0) / PAGE_SIZE).min(MAX_FRAMES);
+    pool.free = pool.frames;
+    klog!(
+        LogLevel::Info,
+        "memory: {} page frames at {:#x}",
+        pool.frames,
+        pool.base
+    );
+}
+
+/// A zeroed frame with one reference, or `None` when the pool is empty.
+pub fn alloc() -> Option<usize> {
+    let frame = {
+        let mut pool = POOL.lock();
+        let frames = pool.frames;
+        let index = (0..frames)
+            .map(|offset| (pool.next + offset) % frames)
+            .find(|&index| pool.refs[index] == 0)?;
+        pool.refs[index] = 1;
+        pool.next = (index + 1) % frames;
+        pool.free -= 1;
+        pool.base + index * PAGE_SIZE
+    };
+    unsafe { core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE) };
+    Some(frame)
+}
+
+/// Adds a reference to `frame`.
+pub fn share(frame: usize) {
+    let mut pool = POOL.lock();
+    if let Some(index) = pool.index(frame) {
+        pool.refs[index] += 1;
+    }
+}
+
+/// Drops a reference to `frame`, freeing it with the last one.
+pub fn release(frame: usize) {
+    let mut pool = POOL.lock();
+    if let Some(index) = pool.index(frame) {
+        match pool.refs[index] {
+            0 => klog!(LogLevel::Error, "memory: frame {:#x} freed twice", frame),
+            1 => {
+                pool.refs[index] = 0;
+                pool.free += 1;
+            }
+            _ => pool.refs[index] -= 1,
+        }
+    }
+}
+
+pub fn ref_count(frame: usize) -> u16 {
+    let pool = POOL.lock();
+    pool.index(frame).map_or(0, |index| pool.refs[index])
+}
+
+/// (free, total) frames.
+pub fn stats() -> (usize, usize) {
+    let pool = POOL.lock();
+    (pool.free, pool.frames)
+}
+
+impl Pool {
+    fn index(&self, frame: usize) -> Option<usize> {
+        let index = frame.checked_sub(self.base)? / PAGE_SIZE;
+        (index < self.frames).then_some(index)
+    }
+}
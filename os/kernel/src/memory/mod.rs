@@ -1,25 +1,26 @@
+pub mod address_space;
+pub mod frames;
+pub mod user;
+
 use linked_list_allocator::LockedHeap;
 use multiboot2::{BootInformation, MemoryAreaType};
 
-use crate::arch::x86::paging;
-
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
-const HEAP_SIZE: usize = 1024 * 64;
+/// Also holds the kernel stacks of user processes.
+const HEAP_SIZE: usize = 1024 * 1024;
 #[repr(align(16))]
 struct Heap([u8; HEAP_SIZE]);
 static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
 
-pub fn init(multiboot_info_addr: usize) {
-    paging::init_identity_4mb();
+pub fn init(boot_info: &BootInformation) {
     unsafe {
-        ALLOCATOR.lock().init(HEAP.0.as_ptr() as usize, HEAP_SIZE);
+        ALLOCATOR
+            .lock()
+            .init(core::ptr::addr_of_mut!(HEAP.0).cast(), HEAP_SIZE);
     }
 
-    let boot_info = unsafe { BootInformation::load(multiboot_info_addr as *const ()) }
-        .expect("valid multiboot2 info");
-
     println!("Memory map:");
     if let Some(map) = boot_info.memory_map_tag() {
         for area in map.memory_areas() {
@@ -32,4 +33,6 @@ pub fn init(multiboot_info_addr: usize) {
             }
         }
     }
+
+    frames::init(boot_info);
 }
@@ -0,0 +1,206 @@
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+use syscall::{Errno, SigAction, SyscallFilter, Timespec};
+
+use super::address_space::{self, USER_END};
+use crate::arch::x86::{cpu, paging};
+
+core::arch::global_asm!(include_str!("user_copy.S"), options(att_syntax));
+
+extern "C" {
+    fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn user_strncpy(dst: *mut u8, src: *const u8, max: usize) -> isize;
+    static user_fixups: [Fixup; 0];
+    static user_fixups_end: u8;
+}
+
+#[repr(C)]
+struct Fixup {
+    access: usize,
+    fixup: usize,
+}
+
+/// Where to resume after a kernel-mode fault at `rip`, if `rip` is one of
+/// the user-access instructions in `user_copy.S`.
+pub fn fixup(rip: usize) -> Option<usize> {
+    let start = core::ptr::addr_of!(user_fixups) as *const Fixup;
+    let end = core::ptr::addr_of!(user_fixups_end) as usize;
+    let count = (end - start as usize) / size_of::<Fixup>();
+    let fixups = unsafe { core::slice::from_raw_parts(start, count) };
+    fixups
+        .iter()
+        .find(|entry| entry.access == rip)
+        .map(|entry| entry.fixup)
+}
+
+/// Fails with `EFAULT` unless all of `[addr, addr + len)` is user memory
+/// the caller may read, or write if `write` is set. Copy-on-write pages
+/// about to be written are copied first. A page unmapped after the check
+/// still faults during the copy, which the fixups catch.
+fn check(addr: usize, len: usize, write: bool) -> Result<(), Errno> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = addr.checked_add(len).ok_or(Errno::EFAULT)?;
+    if end > USER_END {
+        return Err(Errno::EFAULT);
+    }
+    let first = addr / paging::PAGE_SIZE;
+    let last = (end - 1) / paging::PAGE_SIZE;
+    let accessible = |page: usize| {
+        let addr = page * paging::PAGE_SIZE;
+        if write {
+            address_space::copy_on_write(addr);
+        }
+        paging::user_accessible(addr, write)
+    };
+    if (first..=last).all(accessible) {
+        Ok(())
+    } else {
+        Err(Errno::EFAULT)
+    }
+}
+
+/// Fills `dst` from user address `src`.
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), Errno> {
+    check(src, dst.len(), false)?;
+    let left =
+        cpu::user_access(|| unsafe { user_copy(dst.as_mut_ptr(), src as *const u8, dst.len()) });
+    if left == 0 {
+        Ok(())
+    } else {
+        Err(Errno::EFAULT)
+    }
+}
+
+/// Copies `src` to user address `dst`.
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), Errno> {
+    check(dst, src.len(), true)?;
+    let left = cpu::user_access(|| unsafe { user_copy(dst as *mut u8, src.as_ptr(), src.len()) });
+    if left == 0 {
+        Ok(())
+    } else {
+        Err(Errno::EFAULT)
+    }
+}
+
+/// Copies the NUL-terminated string at user address `src` into `dst`,
+/// NUL included, and returns its length. Fails with `ENAMETOOLONG` if the
+/// string and its NUL do not fit.
+pub fn strncpy_from_user(dst: &mut [u8], src: usize) -> Result<usize, Errno> {
+    if dst.is_empty() {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    // The string may end anywhere, so only the first page is checked up
+    // front; later pages are checked as the copy reaches them.
+    let mut copied = 0;
+    while copied < dst.len() {
+        let addr = src.checked_add(copied).ok_or(Errno::EFAULT)?;
+        let page_left = paging::PAGE_SIZE - addr % paging::PAGE_SIZE;
+        let chunk = page_left.min(dst.len() - copied);
+        check(addr, chunk, false)?;
+        let len = cpu::user_access(|| unsafe {
+            user_strncpy(dst[copied..].as_mut_ptr(), addr as *const u8, chunk)
+        });
+        if len < 0 {
+            return Err(Errno::EFAULT);
+        }
+        if (len as usize) < chunk {
+            return Ok(copied + len as usize);
+        }
+        copied += chunk;
+    }
+    Err(Errno::ENAMETOOLONG)
+}
+
+/// Types any byte pattern is a valid value of, so they can be copied in
+/// from user memory.
+///
+/// # Safety
+///
+/// Implementors must have no padding, no invalid bit patterns and no
+/// pointers the kernel would follow.
+pub unsafe trait UserData: Copy {}
+
+unsafe impl UserData for u8 {}
+unsafe impl UserData for u16 {}
+unsafe impl UserData for u32 {}
+unsafe impl UserData for u64 {}
+unsafe impl UserData for usize {}
+unsafe impl UserData for i32 {}
+unsafe impl UserData for i64 {}
+unsafe impl UserData for Timespec {}
+unsafe impl UserData for SyscallFilter {}
+unsafe impl UserData for SigAction {}
+
+/// A `T` in user memory. Holding one proves nothing: every access checks
+/// the address again.
+#[derive(Clone, Copy)]
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T: UserData> UserPtr<T> {
+    pub fn new(ptr: *mut T) -> Self {
+        Self {
+            addr: ptr as usize,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn read(&self) -> Result<T, Errno> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        copy_from_user(bytes, self.addr)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    pub fn write(&self, value: T) -> Result<(), Errno> {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        copy_to_user(self.addr, bytes)
+    }
+}
+
+/// A byte buffer in user memory, such as the one `write` is given.
+#[derive(Clone, Copy)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(ptr: *const u8, len: usize) -> Self {
+        Self {
+            addr: ptr as usize,
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Fills `dst` from the slice, starting `offset` bytes in.
+    pub fn read(&self, offset: usize, dst: &mut [u8]) -> Result<(), Errno> {
+        self.range(offset, dst.len())?;
+        copy_from_user(dst, self.addr + offset)
+    }
+
+    /// Copies `src` into the slice, starting `offset` bytes in.
+    pub fn write(&self, offset: usize, src: &[u8]) -> Result<(), Errno> {
+        self.range(offset, src.len())?;
+        copy_to_user(self.addr + offset, src)
+    }
+
+    fn range(&self, offset: usize, len: usize) -> Result<(), Errno> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err(Errno::EFAULT),
+        }
+    }
+}
@@ -1,20 +1,156 @@
 use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use syscall::{SignalState, SIGCONT, SIGKILL, SIG_IGN};
+
+use crate::security::SecurityContext;
+
+/// Priority of per-CPU idle processes. They never age, so they only run
+/// when nothing else on their CPU is ready.
+pub const IDLE_PRIORITY: u8 = 0;
+
+/// Adopts the children of processes that exit before them.
+pub const INIT_PID: u32 = 1;
+
+/// The kernel as a parent. Processes it starts itself have no parent
+/// process; it reaps them as `KERNEL_PARENT`.
+pub const KERNEL_PARENT: u32 = 0;
+
+/// Scheduling decisions a ready process sits through per one-step boost of
+/// its effective priority. Keeps low-priority work from starving.
+pub const AGING_TICKS: u32 = 4;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ProcessState {
     Ready,
     Running,
     Sleeping,
+    /// Stopped by a signal until `SIGCONT` or `SIGKILL` arrives.
+    Stopped,
     Zombie,
 }
 
+/// Privilege level a process is executing at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecMode {
+    User,
+    #[default]
+    Kernel,
+}
+
+/// TSC-based CPU usage of one process.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuAccounting {
+    pub user_cycles: u64,
+    pub kernel_cycles: u64,
+    /// Cycles spent runnable but waiting for a CPU.
+    pub wait_cycles: u64,
+    /// Times the process gave up the CPU by blocking.
+    pub voluntary_switches: u64,
+    /// Times the process was preempted.
+    pub involuntary_switches: u64,
+    /// TSC at which the current running or waiting interval started.
+    since: u64,
+    /// Where the process runs, so the interval is charged to the right
+    /// bucket when it ends.
+    mode: ExecMode,
+}
+
+impl CpuAccounting {
+    fn charge_run(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.since);
+        match self.mode {
+            ExecMode::User => self.user_cycles = self.user_cycles.saturating_add(elapsed),
+            ExecMode::Kernel => self.kernel_cycles = self.kernel_cycles.saturating_add(elapsed),
+        }
+        self.since = now;
+    }
+
+    fn charge_wait(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.since);
+        self.wait_cycles = self.wait_cycles.saturating_add(elapsed);
+        self.since = now;
+    }
+
+    /// Adds the totals of `other`, e.g. a child being reaped.
+    fn absorb(&mut self, other: &CpuAccounting) {
+        self.user_cycles = self.user_cycles.saturating_add(other.user_cycles);
+        self.kernel_cycles = self.kernel_cycles.saturating_add(other.kernel_cycles);
+        self.wait_cycles = self.wait_cycles.saturating_add(other.wait_cycles);
+        self.voluntary_switches += other.voluntary_switches;
+        self.involuntary_switches += other.involuntary_switches;
+    }
+}
+
+/// Ring 3 register state, captured when the process enters the kernel.
+/// `usermode.S` loads it field by field, so the layout is fixed.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct UserRegisters {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+}
+
+/// What switching to a ring 3 process takes.
+#[derive(Clone, Copy, Debug)]
+pub struct KernelContext {
+    /// Top of its kernel stack, where it enters the kernel from ring 3.
+    pub stack_top: usize,
+    /// Its kernel stack pointer, saved while it is switched out.
+    pub rsp: usize,
+    /// Physical address of its PML4.
+    pub root: usize,
+}
+
 #[derive(Clone, Debug)]
 pub struct Process {
     pub pid: u32,
     pub parent: Option<u32>,
+    /// Children not yet reaped, exited ones included.
+    pub children: Vec<u32>,
     pub priority: u8,
     pub state: ProcessState,
+    /// CPU whose run queue holds this process.
+    pub cpu: usize,
+    /// Pinned processes (per-CPU idle tasks, ring 3 processes on the BSP)
+    /// are never migrated.
+    pub pinned: bool,
+    /// Timer ticks that landed while this process was running.
     pub ticks_used: u64,
+    /// Scheduling decisions on its CPU this process has lost while ready.
+    pub waiting: u32,
+    pub accounting: CpuAccounting,
+    /// Usage of reaped children and, through them, their descendants.
+    pub children_accounting: CpuAccounting,
+    pub security: SecurityContext,
+    /// Notification bits signalled but not yet collected, e.g. by IRQs
+    /// bound to this process.
+    pub notifications: u64,
+    /// Its own kernel stack and address space, for ring 3 processes.
+    /// Kernel-only processes have none and run in their CPU's own context.
+    pub kernel: Option<KernelContext>,
+    /// Registers from its last system call.
+    pub user_registers: UserRegisters,
+    pub signals: SignalState,
+    /// Signal that stopped it, until its parent's `wait` reports the stop.
+    pub stop_signal: Option<u8>,
+    /// Wait status, once it has exited, for its parent to collect.
+    pub exit_status: Option<i32>,
     pub name: [u8; 24],
     pub name_len: usize,
 }
@@ -31,9 +167,22 @@ impl Process {
         Self {
             pid,
             parent,
+            children: Vec::new(),
             priority,
             state: ProcessState::Ready,
+            cpu: 0,
+            pinned: false,
             ticks_used: 0,
+            waiting: 0,
+            accounting: CpuAccounting::default(),
+            children_accounting: CpuAccounting::default(),
+            security: SecurityContext::root(),
+            notifications: 0,
+            kernel: None,
+            user_registers: UserRegisters::default(),
+            signals: SignalState::default(),
+            stop_signal: None,
+            exit_status: None,
             name: name_buf,
             name_len,
         }
@@ -42,11 +191,48 @@ impl Process {
     pub fn name(&self) -> &str {
         core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("<invalid>")
     }
+
+    /// Base priority plus one for every `AGING_TICKS` decisions spent waiting.
+    pub fn effective_priority(&self) -> u32 {
+        if self.priority == IDLE_PRIORITY {
+            return 0;
+        }
+        self.priority as u32 + self.waiting / AGING_TICKS
+    }
+}
+
+/// What `ProcessTable::reap` found among a parent's children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reap {
+    /// This child had exited; it is gone from the table now.
+    Exited {
+        pid: u32,
+        status: i32,
+    },
+    /// This child stopped since the last report.
+    Stopped {
+        pid: u32,
+        signal: u8,
+    },
+    /// No child has exited yet, but this one still runs.
+    Alive(u32),
+    /// Every child left is stopped and has been reported.
+    AllStopped,
+    NoChildren,
+}
+
+/// A process moved between per-CPU run queues by `ProcessTable::balance`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Migration {
+    pub pid: u32,
+    pub from: usize,
+    pub to: usize,
 }
 
 #[derive(Default)]
 pub struct ProcessTable {
     next_pid: u32,
+    cpu_count: usize,
     procs: VecDeque<Process>,
 }
 
@@ -54,41 +240,348 @@ impl ProcessTable {
     pub fn new() -> Self {
         Self {
             next_pid: 1,
+            cpu_count: 1,
             procs: VecDeque::new(),
         }
     }
 
+    /// Grows the set of run queues; new processes spread across all of them.
+    pub fn set_cpu_count(&mut self, cpu_count: usize) {
+        self.cpu_count = cpu_count.max(self.cpu_count);
+    }
+
     pub fn spawn(&mut self, parent: Option<u32>, name: &str, priority: u8) -> u32 {
+        let cpu = self.least_loaded_cpu();
+        self.spawn_on(cpu, parent, name, priority, false)
+    }
+
+    pub fn spawn_on(
+        &mut self,
+        cpu: usize,
+        parent: Option<u32>,
+        name: &str,
+        priority: u8,
+        pinned: bool,
+    ) -> u32 {
         let pid = self.next_pid;
         self.next_pid += 1;
-        self.procs
-            .push_back(Process::new(pid, parent, name, priority));
+        let mut proc_ = Process::new(pid, parent, name, priority);
+        if let Some(parent) = parent.and_then(|parent| self.get_mut(parent)) {
+            proc_.security = parent.security;
+            parent.children.push(pid);
+        }
+        proc_.cpu = cpu;
+        proc_.pinned = pinned;
+        self.procs.push_back(proc_);
         pid
     }
 
-    pub fn schedule_next(&mut self) -> Option<u32> {
-        let mut best_idx = None;
-        let mut best_prio = 0u8;
-        for (idx, p) in self.procs.iter().enumerate() {
-            if p.state == ProcessState::Ready && (best_idx.is_none() || p.priority > best_prio) {
-                best_idx = Some(idx);
-                best_prio = p.priority;
+    /// Copies `parent` into a new ready process: same name, priority,
+    /// security context, signal actions and user registers, with fresh
+    /// accounting.
+    pub fn fork(&mut self, parent: u32) -> Option<u32> {
+        let cpu = self.least_loaded_cpu();
+        let mut child = self.get(parent)?.clone();
+        child.pid = self.next_pid;
+        self.next_pid += 1;
+        child.parent = Some(parent);
+        child.children = Vec::new();
+        child.state = ProcessState::Ready;
+        child.cpu = cpu;
+        child.pinned = false;
+        child.ticks_used = 0;
+        child.waiting = 0;
+        child.accounting = CpuAccounting::default();
+        child.children_accounting = CpuAccounting::default();
+        child.notifications = 0;
+        child.signals = child.signals.fork();
+        child.stop_signal = None;
+        child.kernel = None;
+        child.exit_status = None;
+        let pid = child.pid;
+        self.procs.push_back(child);
+        self.get_mut(parent)?.children.push(pid);
+        Some(pid)
+    }
+
+    /// Number of migratable, runnable processes queued on `cpu`.
+    pub fn load(&self, cpu: usize) -> usize {
+        self.procs
+            .iter()
+            .filter(|p| p.cpu == cpu && !p.pinned && p.state != ProcessState::Zombie)
+            .count()
+    }
+
+    /// Moves one process from the busiest to the idlest run queue when
+    /// their loads differ by at least two.
+    pub fn balance(&mut self) -> Option<Migration> {
+        let (mut busiest, mut idlest) = (0, 0);
+        for cpu in 1..self.cpu_count {
+            if self.load(cpu) > self.load(busiest) {
+                busiest = cpu;
+            }
+            if self.load(cpu) < self.load(idlest) {
+                idlest = cpu;
+            }
+        }
+
+        if self.load(busiest) < self.load(idlest) + 2 {
+            return None;
+        }
+
+        let proc_ = self
+            .procs
+            .iter_mut()
+            .filter(|p| p.cpu == busiest && !p.pinned && p.state == ProcessState::Ready)
+            .min_by_key(|p| p.priority)?;
+        proc_.cpu = idlest;
+
+        Some(Migration {
+            pid: proc_.pid,
+            from: busiest,
+            to: idlest,
+        })
+    }
+
+    /// Ends the running time slice on `cpu` at TSC `now` and picks the ready
+    /// process with the highest effective priority for it, preferring the
+    /// one that has waited longest on ties.
+    pub fn schedule_next(&mut self, cpu: usize, now: u64) -> Option<u32> {
+        let prev = self.running_index(cpu);
+        if let Some(prev) = prev.and_then(|idx| self.procs.get_mut(idx)) {
+            prev.accounting.charge_run(now);
+            prev.state = ProcessState::Ready;
+        }
+
+        let mut best: Option<(usize, (u32, u32))> = None;
+        for (idx, p) in self.procs.iter_mut().enumerate() {
+            if p.cpu != cpu || p.state != ProcessState::Ready {
+                continue;
+            }
+            // Newly spawned: start waiting from the first decision it sees.
+            if p.accounting.since == 0 {
+                p.accounting.since = now;
+            }
+            let key = (p.effective_priority(), p.waiting);
+            if best.is_none_or(|(_, best_key)| key > best_key) {
+                best = Some((idx, key));
             }
         }
 
-        let idx = best_idx?;
-        if let Some(proc_) = self.procs.get_mut(idx) {
-            proc_.state = ProcessState::Running;
+        let (idx, _) = best?;
+        for (other, p) in self.procs.iter_mut().enumerate() {
+            if other != idx && p.cpu == cpu && p.state == ProcessState::Ready {
+                p.waiting = p.waiting.saturating_add(1);
+            }
+        }
+        if prev != Some(idx) {
+            if let Some(prev) = prev.and_then(|idx| self.procs.get_mut(idx)) {
+                prev.accounting.involuntary_switches += 1;
+            }
+            self.procs[idx].accounting.charge_wait(now);
+        }
+
+        let next = &mut self.procs[idx];
+        next.state = ProcessState::Running;
+        next.waiting = 0;
+        Some(next.pid)
+    }
+
+    /// Charges a timer tick to whatever is running on `cpu`.
+    pub fn account_tick(&mut self, cpu: usize) {
+        if let Some(idx) = self.running_index(cpu) {
+            let proc_ = &mut self.procs[idx];
             proc_.ticks_used = proc_.ticks_used.saturating_add(1);
-            let pid = proc_.pid;
+        }
+    }
+
+    /// Charges `pid`'s time since it was scheduled or last changed mode to
+    /// the mode it was in, at TSC `now`, and notes that it runs in `mode`
+    /// from here on. Called as it crosses between ring 3 and the kernel.
+    pub fn enter_mode(&mut self, pid: u32, now: u64, mode: ExecMode) {
+        if let Some(proc_) = self.get_mut(pid) {
+            if proc_.state == ProcessState::Running {
+                proc_.accounting.charge_run(now);
+            }
+            proc_.accounting.mode = mode;
+        }
+    }
+
+    /// Puts `pid` to sleep. Returns true when it was running, in which case
+    /// its CPU has to reschedule.
+    pub fn block(&mut self, pid: u32, now: u64) -> bool {
+        let Some(proc_) = self.get_mut(pid) else {
+            return false;
+        };
+
+        let was_running = proc_.state == ProcessState::Running;
+        if was_running {
+            proc_.accounting.charge_run(now);
+            proc_.accounting.voluntary_switches += 1;
+        }
+        if proc_.state != ProcessState::Zombie {
+            proc_.state = ProcessState::Sleeping;
+        }
+        was_running
+    }
+
+    /// Makes a sleeping process runnable again; returns its CPU.
+    pub fn wake(&mut self, pid: u32, now: u64) -> Option<usize> {
+        let proc_ = self.get_mut(pid)?;
+        if proc_.state != ProcessState::Sleeping {
+            return None;
+        }
+        proc_.state = ProcessState::Ready;
+        proc_.waiting = 0;
+        proc_.accounting.since = now;
+        Some(proc_.cpu)
+    }
+
+    /// Stops `pid` on `signal`. Returns true when it was running, in which
+    /// case its CPU has to reschedule.
+    pub fn stop(&mut self, pid: u32, now: u64, signal: u8) -> bool {
+        let Some(proc_) = self.get_mut(pid) else {
+            return false;
+        };
+
+        let was_running = proc_.state == ProcessState::Running;
+        if was_running {
+            proc_.accounting.charge_run(now);
+        }
+        if proc_.state != ProcessState::Zombie {
+            proc_.state = ProcessState::Stopped;
+            proc_.stop_signal = Some(signal);
+        }
+        was_running
+    }
+
+    /// Makes `signal` pending for `pid`; false if there is no such
+    /// process. `SIGCONT` and `SIGKILL` resume a stopped process. Init
+    /// only takes signals it has a handler for.
+    pub fn signal(&mut self, pid: u32, signal: u32, now: u64) -> bool {
+        let Some(proc_) = self.get_mut(pid) else {
+            return false;
+        };
+
+        if pid == INIT_PID && proc_.signals.action(signal).handler <= SIG_IGN {
+            return true;
+        }
+        proc_.signals.send(signal);
+        if proc_.state == ProcessState::Stopped && matches!(signal, SIGCONT | SIGKILL) {
             proc_.state = ProcessState::Ready;
-            Some(pid)
-        } else {
-            None
+            proc_.stop_signal = None;
+            proc_.waiting = 0;
+            proc_.accounting.since = now;
+        }
+        true
+    }
+
+    /// Turns `pid` into a zombie holding wait status `status` and its
+    /// usage until its parent reaps it, and hands its children to
+    /// `INIT_PID`. Returns true when it was running, in which case its CPU
+    /// has to reschedule.
+    pub fn exit(&mut self, pid: u32, now: u64, status: i32) -> bool {
+        let Some(proc_) = self.get_mut(pid) else {
+            return false;
+        };
+
+        let was_running = proc_.state == ProcessState::Running;
+        if was_running {
+            proc_.accounting.charge_run(now);
+        }
+        if proc_.state == ProcessState::Zombie {
+            return was_running;
+        }
+        proc_.exit_status = Some(status);
+        proc_.state = ProcessState::Zombie;
+        let orphans = core::mem::take(&mut proc_.children);
+
+        // Should init itself go, its orphans fall to the kernel.
+        let adopter = (pid != INIT_PID && self.get(INIT_PID).is_some()).then_some(INIT_PID);
+        for orphan in &orphans {
+            if let Some(orphan) = self.get_mut(*orphan) {
+                orphan.parent = adopter;
+            }
         }
+        if let Some(init) = adopter.and_then(|pid| self.get_mut(pid)) {
+            init.children.extend(orphans);
+        }
+        was_running
+    }
+
+    /// Whether `pid` has an exited child it has not reaped.
+    pub fn has_zombie_child(&self, pid: u32) -> bool {
+        self.get(pid).is_some_and(|proc_| {
+            proc_.children.iter().any(|child| {
+                self.get(*child)
+                    .is_some_and(|child| child.state == ProcessState::Zombie)
+            })
+        })
+    }
+
+    /// Removes an exited child of `parent`, `pid` or any if `pid` is 0,
+    /// adds its usage to the parent's `children_accounting` and returns
+    /// its status. Failing that, reports a child's new stop. `KERNEL_PARENT`
+    /// reaps processes without a parent.
+    pub fn reap(&mut self, parent: u32, pid: u32) -> Reap {
+        let matches =
+            |p: &Process| p.parent.unwrap_or(KERNEL_PARENT) == parent && (pid == 0 || p.pid == pid);
+        let zombie = self
+            .procs
+            .iter()
+            .position(|p| matches(p) && p.state == ProcessState::Zombie);
+        if let Some(child) = zombie.and_then(|idx| self.procs.remove(idx)) {
+            if let Some(parent) = self.get_mut(parent) {
+                parent.children.retain(|pid| *pid != child.pid);
+                parent.children_accounting.absorb(&child.accounting);
+                parent
+                    .children_accounting
+                    .absorb(&child.children_accounting);
+            }
+            return Reap::Exited {
+                pid: child.pid,
+                status: child.exit_status.unwrap_or(0),
+            };
+        }
+        if let Some(child) = self
+            .procs
+            .iter_mut()
+            .find(|p| matches(p) && p.stop_signal.is_some())
+        {
+            return Reap::Stopped {
+                pid: child.pid,
+                signal: child.stop_signal.take().unwrap_or(0),
+            };
+        }
+        let mut children = self.procs.iter().filter(|p| matches(p));
+        match children.clone().find(|p| p.state != ProcessState::Stopped) {
+            Some(child) => Reap::Alive(child.pid),
+            None if children.next().is_some() => Reap::AllStopped,
+            None => Reap::NoChildren,
+        }
+    }
+
+    pub fn get(&self, pid: u32) -> Option<&Process> {
+        self.procs.iter().find(|p| p.pid == pid)
+    }
+
+    pub fn get_mut(&mut self, pid: u32) -> Option<&mut Process> {
+        self.procs.iter_mut().find(|p| p.pid == pid)
     }
 
     pub fn list(&self) -> impl Iterator<Item = &Process> {
         self.procs.iter()
     }
+
+    fn running_index(&self, cpu: usize) -> Option<usize> {
+        self.procs
+            .iter()
+            .position(|p| p.cpu == cpu && p.state == ProcessState::Running)
+    }
+
+    fn least_loaded_cpu(&self) -> usize {
+        (0..self.cpu_count)
+            .min_by_key(|cpu| self.load(*cpu))
+            .unwrap_or(0)
+    }
 }
@@ -1,34 +1,568 @@
+pub mod trace;
+
+use alloc::format;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use spin::Mutex;
 
-use crate::process::ProcessTable;
+use syscall::{FilterAction, SignalState, SyscallFilter, MAX_ARGS, NOTIFY_CHILD, SIGCHLD};
+
+use crate::arch::x86::{context, paging, tss, without_interrupts};
+use crate::process::{
+    ExecMode, KernelContext, Process, ProcessState, ProcessTable, Reap, UserRegisters,
+    IDLE_PRIORITY, INIT_PID, KERNEL_PARENT,
+};
+use crate::security::Capability;
+use crate::smp::percpu::{self, NO_PID};
+use crate::smp::{self, MAX_CPUS};
+use crate::timer;
+use crate::{filesystem, interrupts, syscalls};
+use trace::EventKind;
+
+/// How often (in BSP ticks) run queues are rebalanced across CPUs.
+const BALANCE_INTERVAL_TICKS: u64 = 50;
+
+/// The run queue ring 3 processes are pinned to. They share the BSP so
+/// that switching between them never has to chase a stack onto another
+/// CPU.
+const USER_CPU: usize = 0;
 
 static TABLE: Mutex<Option<ProcessTable>> = Mutex::new(None);
 
+/// Stack pointer of each CPU's own context, the one it booted on, while a
+/// ring 3 process has the CPU. Kernel-only processes all run there.
+static HOME: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+/// Whose kernel stack each CPU is on; `NO_PID` for its own context.
+static ON_CPU: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(NO_PID) }; MAX_CPUS];
+/// The process a CPU just switched away from and the stack pointer it
+/// left at, for `finish_switch` to file once the switch is over.
+static SWITCHED_FROM: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(NO_PID) }; MAX_CPUS];
+static SWITCHED_RSP: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+/// Set by the tick and by wakeups; acted on at the next `preempt` or
+/// `schedule`.
+static NEED_RESCHED: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
 pub fn init() {
     let mut table = ProcessTable::new();
-    let _ = table.spawn(None, "init", 10);
-    let _ = table.spawn(Some(1), "kworker/0", 5);
-    let _ = table.spawn(Some(1), "netd", 6);
+    let init = table.spawn(None, "init", 10);
+    let idle = table.spawn_on(0, None, "idle/0", IDLE_PRIORITY, true);
+    let _ = table.spawn(Some(init), "kworker/0", 5);
+    let _ = table.spawn(Some(init), "netd", 6);
 
+    percpu::current().set_idle_pid(idle);
     *TABLE.lock() = Some(table);
 }
 
-pub fn tick() -> Option<u32> {
+/// Creates the run queue and pinned idle task for a CPU about to come online.
+pub fn add_cpu(cpu: usize) -> u32 {
     let mut guard = TABLE.lock();
-    guard.as_mut().and_then(ProcessTable::schedule_next)
+    let table = guard.get_or_insert_with(ProcessTable::new);
+    table.set_cpu_count(cpu + 1);
+    table.spawn_on(cpu, None, &format!("idle/{cpu}"), IDLE_PRIORITY, true)
+}
+
+/// Gives `proc_` its own kernel context and pins it to `USER_CPU`.
+fn make_user(proc_: &mut Process, context: KernelContext) {
+    // Running elsewhere as a kernel-only process: that CPU lets go of it
+    // at its next reschedule, and `USER_CPU` picks it up from its queue.
+    if proc_.cpu != USER_CPU && proc_.state == ProcessState::Running {
+        proc_.state = ProcessState::Ready;
+    }
+    proc_.kernel = Some(context);
+    proc_.cpu = USER_CPU;
+    proc_.pinned = true;
+}
+
+/// Adds a process that runs in ring 3 from `registers`, switched to with
+/// `context`.
+pub fn spawn_user(
+    name: &str,
+    priority: u8,
+    context: KernelContext,
+    registers: UserRegisters,
+) -> u32 {
+    without_interrupts(|| {
+        let mut guard = TABLE.lock();
+        let table = guard.get_or_insert_with(ProcessTable::new);
+        let pid = table.spawn_on(USER_CPU, None, name, priority, true);
+        if let Some(proc_) = table.get_mut(pid) {
+            make_user(proc_, context);
+            proc_.user_registers = registers;
+        }
+        pid
+    })
+}
+
+/// Turns `pid` into a ring 3 process that starts with `registers`,
+/// switched to with `context`.
+pub fn attach_user(pid: u32, context: KernelContext, registers: UserRegisters) -> bool {
+    without_interrupts(|| {
+        let mut guard = TABLE.lock();
+        let Some(proc_) = guard.as_mut().and_then(|table| table.get_mut(pid)) else {
+            return false;
+        };
+        make_user(proc_, context);
+        proc_.user_registers = registers;
+        true
+    })
+}
+
+/// Points the context of `pid` at the address space rooted at `root`, as
+/// `exec` replaces it.
+pub fn set_root(pid: u32, root: usize) {
+    without_interrupts(|| {
+        let mut guard = TABLE.lock();
+        let proc_ = guard.as_mut().and_then(|table| table.get_mut(pid));
+        if let Some(context) = proc_.and_then(|proc_| proc_.kernel.as_mut()) {
+            context.root = root;
+        }
+    })
+}
+
+/// Records the ring 3 registers `pid` entered the kernel with, or, after
+/// `exec`, the ones it starts the new image with.
+pub fn save_user_registers(pid: u32, registers: UserRegisters) {
+    without_interrupts(|| {
+        if let Some(proc_) = TABLE.lock().as_mut().and_then(|table| table.get_mut(pid)) {
+            proc_.user_registers = registers;
+        }
+    })
+}
+
+pub fn user_registers(pid: u32) -> Option<UserRegisters> {
+    without_interrupts(|| {
+        TABLE
+            .lock()
+            .as_ref()
+            .and_then(|table| table.get(pid))
+            .map(|proc_| proc_.user_registers)
+    })
+}
+
+/// Adds a copy of `parent` that resumes ring 3 with `registers`, switched
+/// to with `context`.
+pub fn fork(parent: u32, context: KernelContext, registers: UserRegisters) -> Option<u32> {
+    without_interrupts(|| {
+        let mut guard = TABLE.lock();
+        let table = guard.as_mut()?;
+        let pid = table.fork(parent)?;
+        let proc_ = table.get_mut(pid)?;
+        make_user(proc_, context);
+        proc_.user_registers = registers;
+        Some(pid)
+    })
+}
+
+/// Collects an exited child of `parent`: `pid`, or any child if 0. The
+/// kernel collects processes it started as `KERNEL_PARENT`.
+pub fn reap(parent: u32, pid: u32) -> Reap {
+    without_interrupts(|| {
+        TABLE
+            .lock()
+            .as_mut()
+            .map_or(Reap::NoChildren, |table| table.reap(parent, pid))
+    })
+}
+
+/// `reap`, putting `parent` to sleep in the same step when the children
+/// it waits for are all alive and it has no signal to act on, so an exit
+/// in between cannot be missed. The caller then calls `schedule`.
+pub fn reap_or_block(parent: u32, pid: u32) -> Reap {
+    let (reap, blocked) = without_interrupts(|| {
+        let mut guard = TABLE.lock();
+        let Some(table) = guard.as_mut() else {
+            return (Reap::NoChildren, false);
+        };
+        let reap = table.reap(parent, pid);
+        let signalled = table
+            .get(parent)
+            .is_some_and(|proc_| proc_.signals.peek().is_some());
+        let blocked =
+            matches!(reap, Reap::Alive(_)) && !signalled && table.block(parent, timer::tsc());
+        (reap, blocked)
+    });
+    if blocked {
+        trace::record(EventKind::Block, percpu::current().index(), parent, 0);
+    }
+    reap
+}
+
+/// Waits in the calling CPU's own context for `pid`, a process the kernel
+/// started, to exit, and returns its wait status. `None` if there is no
+/// such process.
+pub fn join(pid: u32) -> Option<i32> {
+    loop {
+        match reap(KERNEL_PARENT, pid) {
+            Reap::Exited { status, .. } => return Some(status),
+            Reap::NoChildren => return None,
+            Reap::Alive(_) | Reap::Stopped { .. } | Reap::AllStopped => timer::events::idle(),
+        }
+    }
+}
+
+/// Timer tick on the calling CPU: charges it to whatever runs and asks
+/// for a reschedule at the next chance.
+pub fn tick() {
+    let cpu = percpu::current();
+    if let Some(table) = TABLE.lock().as_mut() {
+        table.account_tick(cpu.index());
+    }
+    if cpu.index() == 0 && cpu.ticks().is_multiple_of(BALANCE_INTERVAL_TICKS) {
+        let migration = TABLE.lock().as_mut().and_then(ProcessTable::balance);
+        if let Some(migration) = migration {
+            trace::record(
+                EventKind::Migrate,
+                migration.from,
+                migration.pid,
+                migration.to as u32,
+            );
+            smp::send_reschedule(migration.to);
+        }
+    }
+    NEED_RESCHED[cpu.index()].store(true, Ordering::Relaxed);
+}
+
+/// Gives the CPU to the next process on its run queue, if that is not the
+/// caller. Kernel code that made the caller block, stop or exit calls this
+/// to get off the CPU; it returns once the caller is picked again.
+pub fn schedule() {
+    reschedule();
+}
+
+/// Reschedules if a tick or wakeup asked for it. Called from interrupts
+/// that arrived in ring 3, right before they return there.
+pub fn preempt() {
+    if NEED_RESCHED[percpu::current().index()].load(Ordering::Relaxed) {
+        reschedule();
+    }
+}
+
+/// Picks the next process on the calling CPU's run queue and switches to
+/// its kernel stack and address space, or to the CPU's own context for a
+/// kernel-only one.
+fn reschedule() {
+    without_interrupts(|| {
+        let cpu = percpu::current();
+        let index = cpu.index();
+        NEED_RESCHED[index].store(false, Ordering::Relaxed);
+        let now = timer::tsc();
+        let (next, context) = {
+            let mut guard = TABLE.lock();
+            let next = guard
+                .as_mut()
+                .and_then(|table| table.schedule_next(index, now))
+                .unwrap_or(cpu.idle_pid());
+            let context = guard
+                .as_ref()
+                .and_then(|table| table.get(next))
+                .and_then(|proc_| proc_.kernel);
+            (next, context)
+        };
+        let prev = cpu.current_pid();
+        if next != prev {
+            trace::record(EventKind::Switch, index, next, prev);
+        }
+        cpu.set_current_pid(next);
+
+        let from = ON_CPU[index].load(Ordering::Relaxed);
+        let to = if context.is_some() { next } else { NO_PID };
+        if from == to {
+            return;
+        }
+        let (root, target) = match context {
+            Some(context) => {
+                tss::set_kernel_stack(index, context.stack_top);
+                (context.root, context.rsp)
+            }
+            None => (
+                paging::root_table_address(),
+                HOME[index].load(Ordering::Relaxed),
+            ),
+        };
+        if paging::current_root() != root {
+            unsafe { paging::load_root(root) };
+        }
+        ON_CPU[index].store(to, Ordering::Relaxed);
+        SWITCHED_FROM[index].store(from, Ordering::Relaxed);
+        let save = if from == NO_PID {
+            HOME[index].as_ptr()
+        } else {
+            SWITCHED_RSP[index].as_ptr()
+        };
+        unsafe { context::switch(save, target) };
+        finish_switch();
+    })
+}
+
+/// Files the stack pointer of the process this CPU just switched away
+/// from, now that nothing runs on that stack. The first thing to run
+/// after every switch, including on a new process's stack.
+pub fn finish_switch() {
+    let index = percpu::current().index();
+    let from = SWITCHED_FROM[index].swap(NO_PID, Ordering::Relaxed);
+    if from == NO_PID {
+        return;
+    }
+    let rsp = SWITCHED_RSP[index].load(Ordering::Relaxed);
+    let mut guard = TABLE.lock();
+    let proc_ = guard.as_mut().and_then(|table| table.get_mut(from));
+    if let Some(context) = proc_.and_then(|proc_| proc_.kernel.as_mut()) {
+        context.rsp = rsp;
+    }
+}
+
+/// Notes that the process on this CPU crosses into `mode`, charging its
+/// time so far to the mode it leaves. Every entry from ring 3 and every
+/// return there calls this, so user and kernel time are measured rather
+/// than sampled.
+pub fn enter_mode(mode: ExecMode) {
+    let pid = percpu::current().current_pid();
+    without_interrupts(|| {
+        if let Some(table) = TABLE.lock().as_mut() {
+            table.enter_mode(pid, timer::tsc(), mode);
+        }
+    })
+}
+
+/// Puts `pid` to sleep. If it is the caller, it then calls `schedule`.
+pub fn block(pid: u32) {
+    without_interrupts(|| {
+        if let Some(table) = TABLE.lock().as_mut() {
+            table.block(pid, timer::tsc());
+        }
+    });
+    trace::record(EventKind::Block, percpu::current().index(), pid, 0);
+}
+
+pub fn wake(pid: u32) {
+    let target = without_interrupts(|| {
+        TABLE
+            .lock()
+            .as_mut()
+            .and_then(|table| table.wake(pid, timer::tsc()))
+    });
+    if let Some(target) = target {
+        let cpu = percpu::current().index();
+        trace::record(EventKind::Wake, cpu, pid, target as u32);
+        if target == cpu {
+            NEED_RESCHED[cpu].store(true, Ordering::Relaxed);
+        } else {
+            smp::send_reschedule(target);
+        }
+    }
+}
+
+/// A reschedule IPI: another CPU woke or moved a process onto this one.
+pub fn handle_reschedule() {
+    NEED_RESCHED[percpu::current().index()].store(true, Ordering::Relaxed);
+}
+
+/// Ends `pid`, leaving a zombie with wait status `status`. Its parent gets
+/// `NOTIFY_CHILD` and `SIGCHLD`, and so does init when it adopts children
+/// that have already exited. If `pid` is the caller, it then calls
+/// `schedule` and never comes back.
+pub fn exit(pid: u32, status: i32) {
+    let cpu = percpu::current();
+    let (parent, orphans_exited) = without_interrupts(|| {
+        let mut guard = TABLE.lock();
+        match guard.as_mut() {
+            Some(table) => {
+                let had_children = table.get(pid).is_some_and(|p| !p.children.is_empty());
+                table.exit(pid, timer::tsc(), status);
+                let parent = table.get(pid).and_then(|p| p.parent);
+                let orphans_exited = had_children && table.has_zombie_child(INIT_PID);
+                (parent, orphans_exited)
+            }
+            None => (None, false),
+        }
+    });
+    trace::record(EventKind::Exit, cpu.index(), pid, 0);
+    interrupts::user::release(pid);
+    filesystem::fd::release(pid);
+    syscalls::trace::release(pid);
+    for parent in parent.into_iter().chain(orphans_exited.then_some(INIT_PID)) {
+        notify(parent, NOTIFY_CHILD);
+        signal(parent, SIGCHLD);
+    }
+}
+
+/// Stops `pid` on `signal` until it is continued. Its parent hears of it
+/// as of an exit. If `pid` is the caller, it then calls `schedule`.
+pub fn stop(pid: u32, signal: u8) {
+    let cpu = percpu::current();
+    let parent = without_interrupts(|| {
+        TABLE.lock().as_mut().and_then(|table| {
+            table.stop(pid, timer::tsc(), signal);
+            table.get(pid).and_then(|p| p.parent)
+        })
+    });
+    trace::record(EventKind::Block, cpu.index(), pid, 0);
+    if let Some(parent) = parent {
+        notify(parent, NOTIFY_CHILD);
+        self::signal(parent, SIGCHLD);
+    }
+}
+
+/// Makes `signal` pending for `pid`; false if there is no such process.
+/// A process asleep in the kernel wakes if it now has a signal to act on,
+/// so a blocking call returns early.
+pub fn signal(pid: u32, signal: u32) -> bool {
+    let (exists, interrupted) = without_interrupts(|| {
+        let mut guard = TABLE.lock();
+        let Some(table) = guard.as_mut() else {
+            return (false, false);
+        };
+        let exists = table.signal(pid, signal, timer::tsc());
+        let interrupted = table.get(pid).is_some_and(|proc_| {
+            proc_.state == ProcessState::Sleeping && proc_.signals.peek().is_some()
+        });
+        (exists, interrupted)
+    });
+    if interrupted {
+        wake(pid);
+    }
+    exists
+}
+
+/// Runs `f` on the signal state of `pid`.
+pub fn signals<R>(pid: u32, f: impl FnOnce(&mut SignalState) -> R) -> Option<R> {
+    without_interrupts(|| {
+        TABLE
+            .lock()
+            .as_mut()
+            .and_then(|table| table.get_mut(pid))
+            .map(|proc_| f(&mut proc_.signals))
+    })
+}
+
+/// Whether `sender` may signal `pid`; `None` if either does not exist.
+/// Kernel tasks, which have no user image to deliver to, never may be.
+pub fn may_signal(sender: u32, pid: u32) -> Option<bool> {
+    without_interrupts(|| {
+        let guard = TABLE.lock();
+        let table = guard.as_ref()?;
+        let target = table.get(pid)?;
+        let sender = table.get(sender)?;
+        Some(target.kernel.is_some() && sender.security.may_signal(&target.security))
+    })
+}
+
+pub fn can(pid: u32, cap: Capability) -> bool {
+    TABLE
+        .lock()
+        .as_ref()
+        .and_then(|table| table.get(pid))
+        .is_some_and(|proc_| proc_.security.can(cap))
+}
+
+/// What `pid`'s syscall filters make of call `number` with `args`: `None`
+/// lets it run.
+pub fn check_syscall(pid: u32, number: usize, args: &[usize; MAX_ARGS]) -> Option<FilterAction> {
+    TABLE
+        .lock()
+        .as_ref()
+        .and_then(|table| table.get(pid))
+        .and_then(|proc_| proc_.security.check_syscall(number, args))
+}
+
+pub fn add_syscall_filter(pid: u32, filter: SyscallFilter) -> Result<(), &'static str> {
+    TABLE
+        .lock()
+        .as_mut()
+        .and_then(|table| table.get_mut(pid))
+        .ok_or("no such process")?
+        .security
+        .add_filter(filter)
+}
+
+/// Signals notification `bits` to `pid`, waking it if it sleeps waiting
+/// for them. Safe from interrupt handlers.
+pub fn notify(pid: u32, bits: u64) {
+    let sleeping = TABLE
+        .lock()
+        .as_mut()
+        .and_then(|table| table.get_mut(pid))
+        .is_some_and(|proc_| {
+            proc_.notifications |= bits;
+            proc_.state == ProcessState::Sleeping
+        });
+    if sleeping {
+        wake(pid);
+    }
+}
+
+/// Collects and clears `pid`'s pending notification bits, sleeping until
+/// some arrive. Taking none and going to sleep are one step, so a
+/// notification arriving in between cannot be missed. Returns 0 if a
+/// signal cut the wait short.
+pub fn wait_notification(pid: u32) -> u64 {
+    loop {
+        let (bits, blocked) = without_interrupts(|| {
+            let mut guard = TABLE.lock();
+            let Some(table) = guard.as_mut() else {
+                return (0, false);
+            };
+            let Some(proc_) = table.get_mut(pid) else {
+                return (0, false);
+            };
+            let bits = core::mem::take(&mut proc_.notifications);
+            let signalled = proc_.signals.peek().is_some();
+            let blocked = bits == 0 && !signalled && table.block(pid, timer::tsc());
+            (bits, blocked)
+        });
+        if !blocked {
+            return bits;
+        }
+        trace::record(EventKind::Block, percpu::current().index(), pid, 0);
+        schedule();
+    }
 }
 
 pub fn dump() {
+    for cpu in percpu::online() {
+        println!(
+            "cpu{} apic={} ticks={} current={} idle={}",
+            cpu.index(),
+            cpu.apic_id(),
+            cpu.ticks(),
+            cpu.current_pid(),
+            cpu.idle_pid()
+        );
+    }
+
     let guard = TABLE.lock();
     if let Some(table) = guard.as_ref() {
-        for proc_ in table.list() {
-            println!(
-                "proc pid={} name={} prio={} ticks={}",
-                proc_.pid,
-                proc_.name(),
-                proc_.priority,
-                proc_.ticks_used
-            );
+        let roots = table
+            .list()
+            .filter(|p| p.parent.is_none_or(|parent| table.get(parent).is_none()));
+        for root in roots {
+            dump_tree(table, root, 0);
         }
     }
 }
+
+/// Prints `proc_` indented by `depth`, then its children below it.
+fn dump_tree(table: &ProcessTable, proc_: &Process, depth: usize) {
+    let acct = &proc_.accounting;
+    let children = &proc_.children_accounting;
+    println!(
+        "{:indent$}proc pid={} name={} state={:?} prio={} cpu={} ticks={} user={} sys={} wait={} vcsw={} ivcsw={} cuser={} csys={}",
+        "",
+        proc_.pid,
+        proc_.name(),
+        proc_.state,
+        proc_.priority,
+        proc_.cpu,
+        proc_.ticks_used,
+        acct.user_cycles,
+        acct.kernel_cycles,
+        acct.wait_cycles,
+        acct.voluntary_switches,
+        acct.involuntary_switches,
+        children.user_cycles,
+        children.kernel_cycles,
+        indent = depth * 2
+    );
+    for child in proc_.children.iter().filter_map(|pid| table.get(*pid)) {
+        dump_tree(table, child, depth + 1);
+    }
+}
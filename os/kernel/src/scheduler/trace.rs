@@ -0,0 +1,148 @@
+//! Fixed-size ring of scheduler events, dumped over serial for
+//! `scripts/decode-sched-trace.py`.
+//!
+//! Recording is lock-free so it can run from interrupt handlers on any CPU.
+//! When the ring wraps, the oldest events are overwritten and counted as
+//! dropped at the next dump.
+
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::logging::serial_fmt;
+use crate::timer;
+
+const TRACE_SLOTS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventKind {
+    /// `pid` started running; `arg` is the pid it replaced.
+    Switch = 0,
+    /// `pid` became runnable on CPU `arg`.
+    Wake = 1,
+    /// `pid` went to sleep.
+    Block = 2,
+    /// `pid` moved from the recording CPU to CPU `arg`.
+    Migrate = 3,
+    /// `pid` exited or was killed.
+    Exit = 4,
+}
+
+impl EventKind {
+    fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Switch),
+            1 => Some(Self::Wake),
+            2 => Some(Self::Block),
+            3 => Some(Self::Migrate),
+            4 => Some(Self::Exit),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Switch => "switch",
+            Self::Wake => "wake",
+            Self::Block => "block",
+            Self::Migrate => "migrate",
+            Self::Exit => "exit",
+        }
+    }
+}
+
+/// One ring entry, guarded like a seqlock: `seq` is zero while the slot is
+/// being filled and written last, and a reader keeps what it read only if
+/// `seq` held the expected value both before and after.
+struct Slot {
+    seq: AtomicU64,
+    tsc: AtomicU64,
+    /// kind in bits 40..48, cpu in bits 32..40, pid in bits 0..32.
+    meta: AtomicU64,
+    arg: AtomicU32,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            tsc: AtomicU64::new(0),
+            meta: AtomicU64::new(0),
+            arg: AtomicU32::new(0),
+        }
+    }
+}
+
+static RING: [Slot; TRACE_SLOTS] = [const { Slot::new() }; TRACE_SLOTS];
+
+/// Sequence number of the most recently claimed slot; sequence numbers
+/// start at 1.
+static HEAD: AtomicU64 = AtomicU64::new(0);
+/// Last sequence number emitted by `dump`.
+static DUMPED: AtomicU64 = AtomicU64::new(0);
+
+pub fn record(kind: EventKind, cpu: usize, pid: u32, arg: u32) {
+    let tsc = timer::tsc();
+    let seq = HEAD.fetch_add(1, Ordering::AcqRel) + 1;
+    let slot = &RING[seq as usize % TRACE_SLOTS];
+
+    slot.seq.store(0, Ordering::Relaxed);
+    // Keeps the fields below from becoming visible before the zero.
+    fence(Ordering::Release);
+    slot.tsc.store(tsc, Ordering::Relaxed);
+    slot.meta.store(
+        (kind as u64) << 40 | (cpu as u64 & 0xFF) << 32 | pid as u64,
+        Ordering::Relaxed,
+    );
+    slot.arg.store(arg, Ordering::Relaxed);
+    slot.seq.store(seq, Ordering::Release);
+}
+
+/// Writes every event recorded since the previous dump to the serial port:
+///
+/// ```text
+/// SCHEDTRACE v1 tsc_hz=<hz>
+/// T <seq> <tsc> <cpu> <kind> <pid> <arg>
+/// SCHEDTRACE end dropped=<n>
+/// ```
+pub fn dump() {
+    let head = HEAD.load(Ordering::Acquire);
+    let dumped = DUMPED.swap(head, Ordering::AcqRel);
+    if head <= dumped {
+        return;
+    }
+
+    let oldest = (dumped + 1).max((head + 1).saturating_sub(TRACE_SLOTS as u64));
+    let mut dropped = oldest - (dumped + 1);
+    serial_fmt(format_args!("SCHEDTRACE v1 tsc_hz={}\n", timer::tsc_hz()));
+
+    for seq in oldest..=head {
+        let slot = &RING[seq as usize % TRACE_SLOTS];
+        let before = slot.seq.load(Ordering::Acquire);
+        let tsc = slot.tsc.load(Ordering::Relaxed);
+        let meta = slot.meta.load(Ordering::Relaxed);
+        let arg = slot.arg.load(Ordering::Relaxed);
+        // Keeps the loads above from moving past the second look at `seq`.
+        fence(Ordering::Acquire);
+        let after = slot.seq.load(Ordering::Relaxed);
+        // Overwritten by a newer event, or being written as we read.
+        if before != seq || after != seq {
+            dropped += 1;
+            continue;
+        }
+        let Some(kind) = EventKind::from_raw((meta >> 40) as u8) else {
+            continue;
+        };
+
+        serial_fmt(format_args!(
+            "T {} {} {} {} {} {}\n",
+            seq,
+            tsc,
+            (meta >> 32) as u8,
+            kind.as_str(),
+            meta as u32,
+            arg
+        ));
+    }
+
+    serial_fmt(format_args!("SCHEDTRACE end dropped={}\n", dropped));
+}
@@ -1,8 +1,17 @@
+use syscall::{FilterAction, SyscallFilter, MAX_ARGS};
+
+/// Syscall filters one process can stack.
+pub const MAX_FILTERS: usize = 4;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Capability {
     MountFs,
     NetAdmin,
     DriverIo,
+    /// Trace the system calls of other processes.
+    Trace,
+    /// Send signals to processes of other users.
+    Kill,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -10,6 +19,9 @@ pub struct SecurityContext {
     pub uid: u32,
     pub gid: u32,
     pub caps: [Option<Capability>; 8],
+    /// Every call must pass all of these. Forked children inherit them and
+    /// none is ever removed, so a sandbox only narrows.
+    pub filters: [Option<SyscallFilter>; MAX_FILTERS],
 }
 
 impl SecurityContext {
@@ -21,16 +33,42 @@ impl SecurityContext {
                 Some(Capability::MountFs),
                 Some(Capability::NetAdmin),
                 Some(Capability::DriverIo),
-                None,
-                None,
+                Some(Capability::Trace),
+                Some(Capability::Kill),
                 None,
                 None,
                 None,
             ],
+            filters: [None; MAX_FILTERS],
         }
     }
 
     pub fn can(&self, cap: Capability) -> bool {
         self.caps.iter().flatten().any(|c| *c == cap)
     }
+
+    /// Whether a process in this context may signal one in `target`.
+    pub fn may_signal(&self, target: &SecurityContext) -> bool {
+        self.uid == target.uid || self.can(Capability::Kill)
+    }
+
+    pub fn add_filter(&mut self, filter: SyscallFilter) -> Result<(), &'static str> {
+        let slot = self
+            .filters
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or("too many syscall filters")?;
+        *slot = Some(filter);
+        Ok(())
+    }
+
+    /// `None` if every filter lets call `number` with `args` through,
+    /// otherwise the strictest action among those that reject it.
+    pub fn check_syscall(&self, number: usize, args: &[usize; MAX_ARGS]) -> Option<FilterAction> {
+        self.filters
+            .iter()
+            .flatten()
+            .filter_map(|filter| filter.check(number, args))
+            .max()
+    }
 }
@@ -0,0 +1,221 @@
+/// MADT CPU parsing and the shared vector numbers, shared with and tested
+/// in the kernel crate.
+#[path = "../../../../kernel/src/smp.rs"]
+#[allow(dead_code)]
+pub mod common;
+pub mod percpu;
+
+/// The shootdown handshake, shared with the host-tested kernel crate.
+#[path = "../../../../kernel/src/tlb.rs"]
+#[allow(dead_code)]
+mod tlb;
+
+pub use common::{MAX_CPUS, RESCHEDULE_VECTOR, TLB_SHOOTDOWN_VECTOR};
+pub use tlb::FLUSH_ALL;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86::irq;
+
+use crate::arch::x86::{cpu, gdt, paging, smp as ap_boot, without_interrupts};
+use crate::drivers::lapic;
+use crate::interrupts::{controller, idt};
+use crate::logging::LogLevel;
+use crate::{acpi, scheduler, syscalls, timer};
+
+/// Local timer vector: the LAPIC timer when it is the tick source, otherwise
+/// broadcast by the BSP on every PIT interrupt so APs see the same tick rate.
+pub const TICK_VECTOR: u8 = 0xF0;
+
+const AP_STACK_SIZE: usize = 16 * 1024;
+const AP_ONLINE_TIMEOUT_TICKS: u64 = 100;
+
+#[repr(align(16))]
+struct ApStack([u8; AP_STACK_SIZE]);
+
+static mut AP_STACKS: [ApStack; MAX_CPUS] = [const { ApStack([0; AP_STACK_SIZE]) }; MAX_CPUS];
+
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+static SHOOTDOWN: tlb::TlbShootdown = tlb::TlbShootdown::new();
+
+/// Sets up per-CPU data for the bootstrap processor. Runs before anything
+/// that calls `percpu::current()`, including the timer interrupt.
+pub fn init_bsp() {
+    percpu::install(0, 0);
+    percpu::current().set_online();
+    ONLINE_CPUS.store(1, Ordering::SeqCst);
+}
+
+/// Brings up every application processor listed in the MADT.
+///
+/// Needs interrupts enabled on the BSP: the INIT/SIPI delays are measured
+/// in timer ticks.
+pub fn init() {
+    let Some(madt) = acpi::madt() else {
+        klog!(LogLevel::Info, "smp: no MADT, running on the BSP only");
+        timer::select_source(false);
+        return;
+    };
+
+    lapic::init(madt.lapic_address() as usize);
+    lapic::enable();
+    let bsp_apic_id = lapic::id();
+    percpu::current().set_apic_id(bsp_apic_id);
+    controller::init_apic(&madt, bsp_apic_id);
+    timer::select_source(true);
+
+    ap_boot::install_trampoline();
+
+    let mut next_index = 1;
+    for cpu in madt.cpus() {
+        if cpu.apic_id == bsp_apic_id {
+            continue;
+        }
+        if next_index >= MAX_CPUS {
+            klog!(
+                LogLevel::Warn,
+                "smp: ignoring CPUs beyond MAX_CPUS={}",
+                MAX_CPUS
+            );
+            break;
+        }
+
+        if start_ap(next_index, cpu.apic_id) {
+            next_index += 1;
+        } else {
+            klog!(
+                LogLevel::Warn,
+                "smp: apic {} did not come online",
+                cpu.apic_id
+            );
+        }
+    }
+
+    klog!(LogLevel::Info, "smp: {} CPU(s) online", cpu_count());
+}
+
+pub fn cpu_count() -> usize {
+    ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+fn start_ap(index: usize, apic_id: u32) -> bool {
+    let idle_pid = scheduler::add_cpu(index);
+    if let Some(cpu) = percpu::get(index) {
+        cpu.set_idle_pid(idle_pid);
+    }
+
+    let stack_top = unsafe {
+        let stack = core::ptr::addr_of_mut!(AP_STACKS[index].0);
+        stack as usize + AP_STACK_SIZE
+    };
+    ap_boot::prepare(paging::root_table_address(), stack_top, index, ap_main);
+
+    lapic::send_init(apic_id);
+    timer::sleep_ticks(1);
+    for _ in 0..2 {
+        lapic::send_startup(apic_id, ap_boot::startup_vector());
+        timer::sleep_ticks(1);
+        if percpu::get(index).is_some_and(|cpu| cpu.is_online()) {
+            return true;
+        }
+    }
+
+    let deadline = timer::uptime_ticks() + AP_ONLINE_TIMEOUT_TICKS;
+    while timer::uptime_ticks() < deadline {
+        if percpu::get(index).is_some_and(|cpu| cpu.is_online()) {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+extern "C" fn ap_main(index: usize) -> ! {
+    gdt::load();
+    cpu::enable_protections();
+    idt::load();
+    percpu::install(index, lapic::id());
+    syscalls::entry::init_cpu();
+    lapic::enable();
+    timer::init_local();
+
+    let cpu = percpu::current();
+    cpu.set_current_pid(cpu.idle_pid());
+    cpu.set_online();
+    ONLINE_CPUS.fetch_add(1, Ordering::AcqRel);
+    klog!(
+        LogLevel::Info,
+        "cpu{} online (apic {})",
+        index,
+        cpu.apic_id()
+    );
+
+    unsafe { irq::enable() };
+    loop {
+        timer::events::idle();
+    }
+}
+
+/// Per-CPU tick: accounts the tick locally and runs the local scheduler.
+pub fn local_tick() {
+    percpu::current().tick();
+    scheduler::tick();
+}
+
+pub fn broadcast_tick() {
+    if cpu_count() > 1 && timer::source() == timer::TickSource::Pit {
+        lapic::broadcast_others(TICK_VECTOR);
+    }
+}
+
+/// Asks `cpu` to re-run its scheduler, e.g. after a task migrated to it.
+pub fn send_reschedule(cpu: usize) {
+    if cpu == percpu::current().index() {
+        return;
+    }
+    if let Some(target) = percpu::get(cpu).filter(|target| target.is_online()) {
+        lapic::send_fixed(target.apic_id(), RESCHEDULE_VECTOR);
+    }
+}
+
+/// Invalidates `addr`, or the whole TLB for `FLUSH_ALL`, on every online
+/// CPU and waits for all of them. Other CPUs must be able to take the IPI,
+/// so the caller may not hold a lock they could be spinning on with
+/// interrupts off.
+pub fn tlb_shootdown(addr: u64) {
+    without_interrupts(|| {
+        let me = percpu::current().index();
+        flush(addr);
+        let targets = (0..MAX_CPUS)
+            .filter(|&cpu| cpu != me && percpu::get(cpu).is_some_and(|cpu| cpu.is_online()))
+            .fold(0u32, |mask, cpu| mask | 1 << cpu);
+        // Whoever holds the shootdown may be waiting on this CPU.
+        while SHOOTDOWN.begin(addr, targets).is_err() {
+            if SHOOTDOWN.is_pending(me) {
+                handle_tlb_shootdown();
+            }
+            core::hint::spin_loop();
+        }
+        for cpu in (0..MAX_CPUS).filter(|cpu| targets & 1 << cpu != 0) {
+            if let Some(target) = percpu::get(cpu) {
+                lapic::send_fixed(target.apic_id(), TLB_SHOOTDOWN_VECTOR);
+            }
+        }
+        while !SHOOTDOWN.is_complete() {
+            core::hint::spin_loop();
+        }
+    });
+}
+
+pub fn handle_tlb_shootdown() {
+    SHOOTDOWN.acknowledge(percpu::current().index(), flush);
+}
+
+fn flush(addr: u64) {
+    if addr == FLUSH_ALL {
+        unsafe { paging::load_root(paging::current_root()) };
+    } else {
+        paging::invalidate(addr as usize);
+    }
+}
@@ -0,0 +1,138 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use x86::msr::{wrmsr, IA32_GS_BASE, IA32_KERNEL_GSBASE};
+
+use super::MAX_CPUS;
+use crate::arch::x86::gdt;
+
+/// Sentinel stored in `current_pid` while a CPU has nothing scheduled.
+pub const NO_PID: u32 = 0;
+
+/// Offsets the SYSCALL entry stub reads through GS.
+pub const KERNEL_STACK_OFFSET: usize = 8;
+pub const USER_STACK_OFFSET: usize = 16;
+
+/// Per-CPU data block reached through the GS base of each CPU.
+///
+/// The first field points back at the block itself so `current()` can
+/// recover a normal reference with a single `gs:[0]` load.
+#[repr(C)]
+pub struct PerCpu {
+    self_ptr: AtomicUsize,
+    /// Stack the SYSCALL entry switches to; mirrors RSP0 in the TSS.
+    kernel_stack: AtomicUsize,
+    /// User RSP parked by the SYSCALL entry while it switches stacks.
+    user_stack: AtomicUsize,
+    index: AtomicUsize,
+    apic_id: AtomicU32,
+    current_pid: AtomicU32,
+    idle_pid: AtomicU32,
+    ticks: AtomicU64,
+    online: AtomicBool,
+}
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            self_ptr: AtomicUsize::new(0),
+            kernel_stack: AtomicUsize::new(0),
+            user_stack: AtomicUsize::new(0),
+            index: AtomicUsize::new(0),
+            apic_id: AtomicU32::new(0),
+            current_pid: AtomicU32::new(NO_PID),
+            idle_pid: AtomicU32::new(NO_PID),
+            ticks: AtomicU64::new(0),
+            online: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_kernel_stack(&self, top: usize) {
+        self.kernel_stack.store(top, Ordering::Relaxed);
+    }
+
+    pub fn index(&self) -> usize {
+        self.index.load(Ordering::Relaxed)
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn set_apic_id(&self, apic_id: u32) {
+        self.apic_id.store(apic_id, Ordering::Relaxed);
+    }
+
+    pub fn current_pid(&self) -> u32 {
+        self.current_pid.load(Ordering::Relaxed)
+    }
+
+    pub fn set_current_pid(&self, pid: u32) {
+        self.current_pid.store(pid, Ordering::Relaxed);
+    }
+
+    pub fn idle_pid(&self) -> u32 {
+        self.idle_pid.load(Ordering::Relaxed)
+    }
+
+    pub fn set_idle_pid(&self, pid: u32) {
+        self.idle_pid.store(pid, Ordering::Relaxed);
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.ticks.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub fn set_online(&self) {
+        self.online.store(true, Ordering::Release);
+    }
+}
+
+const _: () = assert!(core::mem::offset_of!(PerCpu, kernel_stack) == KERNEL_STACK_OFFSET);
+const _: () = assert!(core::mem::offset_of!(PerCpu, user_stack) == USER_STACK_OFFSET);
+
+/// Also bounds the GS bases the paranoid exception entry accepts as the
+/// kernel's.
+pub(crate) static CPUS: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+
+/// Binds the per-CPU block for `index` to the calling CPU's GS base, and
+/// loads the CPU's TSS. The inactive base, which SWAPGS hands to ring 3,
+/// starts out zero.
+pub fn install(index: usize, apic_id: u32) {
+    let cpu = &CPUS[index];
+    cpu.self_ptr
+        .store(cpu as *const PerCpu as usize, Ordering::Relaxed);
+    cpu.index.store(index, Ordering::Relaxed);
+    cpu.set_apic_id(apic_id);
+
+    gdt::load_tss(index);
+    unsafe {
+        wrmsr(IA32_GS_BASE, cpu as *const PerCpu as u64);
+        wrmsr(IA32_KERNEL_GSBASE, 0);
+    }
+}
+
+/// Returns the calling CPU's block. Only valid after `install` ran on it.
+pub fn current() -> &'static PerCpu {
+    let ptr: usize;
+    unsafe {
+        asm!("mov {0}, gs:[0]", out(reg) ptr, options(nostack, preserves_flags, readonly));
+        &*(ptr as *const PerCpu)
+    }
+}
+
+pub fn get(index: usize) -> Option<&'static PerCpu> {
+    CPUS.get(index)
+}
+
+pub fn online() -> impl Iterator<Item = &'static PerCpu> {
+    CPUS.iter().filter(|cpu| cpu.is_online())
+}
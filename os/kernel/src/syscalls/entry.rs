@@ -0,0 +1,79 @@
+use x86::msr::{rdmsr, wrmsr, IA32_EFER, IA32_FMASK, IA32_LSTAR, IA32_STAR};
+
+use syscall::Syscall;
+
+use crate::arch::x86::gdt::{KERNEL_CODE_SELECTOR, USER_DATA_SELECTOR};
+use crate::interrupts::exceptions::TrapFrame;
+use crate::process::ExecMode;
+use crate::scheduler;
+use crate::userspace::signal;
+
+core::arch::global_asm!(include_str!("entry.S"), options(att_syntax));
+
+/// Vector of the `int 0x80` fallback; also the `vector` every system call
+/// frame carries.
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+const EFER_SCE: u64 = 1 << 0;
+/// TF, IF, DF and AC: the kernel runs system calls with interrupts off and
+/// a clean direction flag, whatever ring 3 left in RFLAGS.
+const SYSCALL_RFLAGS_MASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 18);
+
+extern "C" {
+    fn syscall_entry();
+    fn syscall_int80();
+}
+
+/// Enables SYSCALL on the calling CPU. SYSRET derives the user selectors
+/// from STAR: CS is the base plus 16, SS the base plus 8.
+pub fn init_cpu() {
+    let star = ((USER_DATA_SELECTOR - 8) as u64) << 48 | (KERNEL_CODE_SELECTOR as u64) << 32;
+    unsafe {
+        wrmsr(IA32_STAR, star);
+        wrmsr(IA32_LSTAR, syscall_entry as *const () as u64);
+        wrmsr(IA32_FMASK, SYSCALL_RFLAGS_MASK);
+        wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_SCE);
+    }
+}
+
+/// Address of the `int 0x80` stub, for the IDT.
+pub fn int80_stub() -> usize {
+    syscall_int80 as *const () as usize
+}
+
+/// Called from both entry stubs with the caller's registers. The number is
+/// in RAX and the arguments in RDI, RSI, RDX, R10, R8 and R9 (R10 because
+/// SYSCALL overwrites RCX); the result goes back in RAX. Signals pending
+/// for the caller are delivered on the way out.
+///
+/// Returns true when ring 3 must get RCX and R11 back as well, which only
+/// IRET can do: after `sigreturn` they hold the interrupted code's values.
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame: &mut TrapFrame) -> bool {
+    let pid = super::caller();
+    if frame.is_from_user() {
+        scheduler::enter_mode(ExecMode::Kernel);
+        scheduler::save_user_registers(pid, frame.user_registers());
+    }
+    let number = frame.rax as usize;
+    let args = [
+        frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9,
+    ];
+    frame.rax = super::dispatch(number, args.map(|arg| arg as usize)) as u64;
+    // A successful exec leaves nothing to return to but the new image, and
+    // a successful sigreturn nothing but the interrupted code.
+    let reload = frame.rax == 0
+        && (number == Syscall::Exec as usize || number == Syscall::Sigreturn as usize);
+    if reload {
+        if let Some(registers) = scheduler::user_registers(pid) {
+            frame.set_user_registers(&registers);
+        }
+    }
+    if frame.is_from_user() {
+        let mut registers = frame.user_registers();
+        signal::deliver_on_return(pid, &mut registers);
+        frame.set_user_registers(&registers);
+        scheduler::enter_mode(ExecMode::User);
+    }
+    reload && number == Syscall::Sigreturn as usize
+}
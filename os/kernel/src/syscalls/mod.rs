@@ -1,73 +1,356 @@
-use spin::Mutex;
-
-#[derive(Clone, Copy)]
-#[repr(u32)]
-pub enum SyscallNumber {
-    Read = 0,
-    Write = 1,
-    Open = 2,
-    Close = 3,
-    Fork = 4,
-    Exec = 5,
-    Wait = 6,
-    Exit = 7,
-    Sleep = 8,
-    Socket = 9,
+pub mod entry;
+pub mod trace;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use syscall::{
+    Call, Errno, FilterAction, SigAction, SysResult, Syscall, SyscallFilter, Timespec, WaitStatus,
+    MAX_ARGS, NOTIFY_CHILD, NSIG, SIGSEGV, SIGSYS,
+};
+
+use crate::filesystem::fd;
+use crate::interrupts;
+use crate::logging::LogLevel;
+use crate::memory::user::{strncpy_from_user, UserPtr, UserSlice};
+use crate::security::Capability;
+use crate::smp::percpu;
+use crate::userspace::{self, stack};
+use crate::{scheduler, timer};
+
+/// Most bytes `read` and `write` move through the kernel at once.
+const IO_CHUNK: usize = 256;
+/// Longest path `open` accepts, NUL included.
+const MAX_PATH: usize = 256;
+
+/// The calls this kernel implements; `syscall::dispatch` routes to them
+/// and answers everything else with `ENOSYS`.
+struct Handlers;
+
+/// `cmdline` is the kernel command line, for its `trace=` options.
+pub fn init(cmdline: &str) {
+    entry::init_cpu();
+    trace::init(cmdline);
+}
+
+pub fn dispatch(number: usize, args: [usize; MAX_ARGS]) -> isize {
+    let pid = caller();
+    if trace::traced(pid, number) {
+        trace::record(pid, number, args, || filtered(pid, number, args))
+    } else {
+        filtered(pid, number, args)
+    }
 }
 
-type Handler = fn(arg0: usize, arg1: usize, arg2: usize) -> isize;
+/// Runs the call unless one of `pid`'s syscall filters rejects it, which
+/// fails it with `EPERM` or kills `pid` with `SIGSYS`.
+fn filtered(pid: u32, number: usize, args: [usize; MAX_ARGS]) -> isize {
+    let Some(action) = scheduler::check_syscall(pid, number, &args) else {
+        return syscall::dispatch(&Handlers, number, args);
+    };
+    match Syscall::from_raw(number) {
+        Some(syscall) => klog!(
+            LogLevel::Warn,
+            "security: pid {} filtered {} ({:?})",
+            pid,
+            Call { syscall, args },
+            action
+        ),
+        None => klog!(
+            LogLevel::Warn,
+            "security: pid {} filtered syscall #{} ({:?})",
+            pid,
+            number,
+            action
+        ),
+    }
+    match action {
+        FilterAction::Errno => syscall::encode(Err(Errno::EPERM)),
+        FilterAction::Kill => {
+            let status = WaitStatus::Killed(SIGSYS as u8).encode();
+            userspace::terminate(pid, status)
+        }
+    }
+}
 
-fn unimplemented_handler(_: usize, _: usize, _: usize) -> isize {
-    -38
+/// Copies the NULL-terminated array of user strings at `array`, which may
+/// itself be NULL, into `buf` back to back with their NULs. Returns where
+/// each string starts in `buf` and how long it is, and how much of `buf`
+/// they fill. Fails with `E2BIG` when they do not fit.
+fn strings_from_user(
+    array: *const *const u8,
+    buf: &mut [u8],
+) -> Result<(Vec<(usize, usize)>, usize), Errno> {
+    let mut strings = Vec::new();
+    let mut used = 0;
+    if array.is_null() {
+        return Ok((strings, used));
+    }
+    loop {
+        if strings.len() == stack::MAX_STRINGS {
+            return Err(Errno::E2BIG);
+        }
+        let slot = array.wrapping_add(strings.len()).cast_mut();
+        let string = UserPtr::new(slot.cast::<usize>()).read()?;
+        if string == 0 {
+            return Ok((strings, used));
+        }
+        let len = match strncpy_from_user(&mut buf[used..], string) {
+            Err(Errno::ENAMETOOLONG) => return Err(Errno::E2BIG),
+            result => result?,
+        };
+        strings.push((used, len));
+        used += len + 1;
+    }
 }
 
-struct SyscallTable {
-    handlers: [Handler; 64],
+/// The process making the call: whatever this CPU has scheduled.
+pub(crate) fn caller() -> u32 {
+    percpu::current().current_pid()
 }
 
-impl SyscallTable {
-    const fn new() -> Self {
-        Self {
-            handlers: [unimplemented_handler; 64],
+impl syscall::Kernel for Handlers {
+    /// Reads through a kernel buffer `IO_CHUNK` bytes at a time, stopping
+    /// early at the end of the file.
+    fn read(&self, fd: u32, buf: *mut u8, len: usize) -> SysResult {
+        let pid = caller();
+        let buf = UserSlice::new(buf, len);
+        let mut chunk = [0u8; IO_CHUNK];
+        let mut done = 0;
+        while done < buf.len() {
+            let want = (buf.len() - done).min(IO_CHUNK);
+            let count = match fd::read(pid, fd, &mut chunk[..want]) {
+                Ok(count) => count,
+                Err(_) if done > 0 => break,
+                Err(errno) => return Err(errno),
+            };
+            buf.write(done, &chunk[..count])?;
+            done += count;
+            if count < want {
+                break;
+            }
         }
+        Ok(done)
     }
 
-    fn register(&mut self, num: SyscallNumber, handler: Handler) {
-        self.handlers[num as usize] = handler;
+    /// Writes `IO_CHUNK` bytes at a time and returns how many were taken;
+    /// a short write ends the call.
+    fn write(&self, fd: u32, buf: *const u8, len: usize) -> SysResult {
+        let pid = caller();
+        let buf = UserSlice::new(buf, len);
+        let mut chunk = [0u8; IO_CHUNK];
+        let mut done = 0;
+        while done < buf.len() {
+            let chunk = &mut chunk[..(buf.len() - done).min(IO_CHUNK)];
+            buf.read(done, chunk)?;
+            let count = match fd::write(pid, fd, chunk) {
+                Ok(count) => count,
+                Err(_) if done > 0 => break,
+                Err(errno) => return Err(errno),
+            };
+            done += count;
+            if count < chunk.len() {
+                break;
+            }
+        }
+        Ok(done)
     }
 
-    fn dispatch(&self, num: usize, a: usize, b: usize, c: usize) -> isize {
-        if let Some(handler) = self.handlers.get(num) {
-            handler(a, b, c)
-        } else {
-            -38
+    fn open(&self, path: *const u8, flags: u32) -> SysResult {
+        let mut name = [0u8; MAX_PATH];
+        let len = strncpy_from_user(&mut name, path as usize)?;
+        let path = core::str::from_utf8(&name[..len]).map_err(|_| Errno::EINVAL)?;
+        fd::open(caller(), path, flags)
+    }
+
+    fn fork(&self) -> SysResult {
+        userspace::fork(caller()).map(|pid| pid as usize)
+    }
+
+    /// On success `entry::syscall_dispatch` returns into the new image
+    /// rather than to the caller.
+    fn exec(&self, path: *const u8, argv: *const *const u8, envp: *const *const u8) -> SysResult {
+        let mut name = [0u8; MAX_PATH];
+        let len = strncpy_from_user(&mut name, path as usize)?;
+        let path = core::str::from_utf8(&name[..len]).map_err(|_| Errno::EINVAL)?;
+        let mut strings = [0u8; stack::ARG_MAX];
+        let (argv, used) = strings_from_user(argv, &mut strings)?;
+        let (envp, _) = strings_from_user(envp, &mut strings[used..])?;
+        let argv: Vec<&[u8]> = argv
+            .iter()
+            .map(|&(start, len)| &strings[start..][..len])
+            .collect();
+        let envp: Vec<&[u8]> = envp
+            .iter()
+            .map(|&(start, len)| &strings[used + start..][..len])
+            .collect();
+        userspace::exec(caller(), path, &argv, &envp)?;
+        Ok(0)
+    }
+
+    fn wait(&self, pid: u32, status: *mut i32) -> SysResult {
+        let (child, code) = userspace::wait(caller(), pid)?;
+        if !status.is_null() {
+            UserPtr::new(status).write(code)?;
         }
+        Ok(child as usize)
     }
-}
 
-static TABLE: Mutex<SyscallTable> = Mutex::new(SyscallTable::new());
+    fn exit(&self, code: i32) -> SysResult {
+        let status = WaitStatus::Exited(code as u8).encode();
+        userspace::terminate(caller(), status)
+    }
 
-pub fn init() {
-    let mut table = TABLE.lock();
-    table.register(SyscallNumber::Write, sys_write);
-    table.register(SyscallNumber::Sleep, sys_sleep);
-}
+    fn close(&self, fd: u32) -> SysResult {
+        fd::close(caller(), fd)?;
+        Ok(0)
+    }
 
-pub fn dispatch(number: usize, arg0: usize, arg1: usize, arg2: usize) -> isize {
-    TABLE.lock().dispatch(number, arg0, arg1, arg2)
-}
+    fn dup(&self, fd: u32) -> SysResult {
+        fd::dup(caller(), fd)
+    }
 
-fn sys_write(ptr: usize, len: usize, _fd: usize) -> isize {
-    let msg = unsafe { core::slice::from_raw_parts(ptr as *const u8, len) };
-    if let Ok(text) = core::str::from_utf8(msg) {
-        print!("{}", text);
-        len as isize
-    } else {
-        -22
+    fn dup2(&self, old: u32, new: u32) -> SysResult {
+        fd::dup2(caller(), old, new)
+    }
+
+    fn lseek(&self, fd: u32, offset: i64, whence: u32) -> SysResult {
+        fd::lseek(caller(), fd, offset, whence)
+    }
+
+    fn trace(&self, pid: u32, calls: u64) -> SysResult {
+        let caller = caller();
+        if pid != caller && !scheduler::can(caller, Capability::Trace) {
+            return Err(Errno::EPERM);
+        }
+        trace::set(pid, calls);
+        Ok(0)
+    }
+
+    fn filter(&self, filter: *const SyscallFilter) -> SysResult {
+        let filter = UserPtr::new(filter.cast_mut()).read()?;
+        filter.validate()?;
+        scheduler::add_syscall_filter(caller(), filter).map_err(|_| Errno::ENOMEM)?;
+        Ok(0)
+    }
+
+    fn kill(&self, pid: u32, signal: u32) -> SysResult {
+        if signal >= NSIG {
+            return Err(Errno::EINVAL);
+        }
+        if !scheduler::may_signal(caller(), pid).ok_or(Errno::ESRCH)? {
+            return Err(Errno::EPERM);
+        }
+        if signal != 0 {
+            scheduler::signal(pid, signal);
+        }
+        Ok(0)
+    }
+
+    fn sigaction(&self, signal: u32, action: *const SigAction, old: *mut SigAction) -> SysResult {
+        if !(1..NSIG).contains(&signal) {
+            return Err(Errno::EINVAL);
+        }
+        let pid = caller();
+        let current =
+            scheduler::signals(pid, |signals| signals.action(signal)).ok_or(Errno::ESRCH)?;
+        if !action.is_null() {
+            let action = UserPtr::new(action.cast_mut()).read()?;
+            scheduler::signals(pid, |signals| signals.set_action(signal, action))
+                .ok_or(Errno::ESRCH)??;
+        }
+        if !old.is_null() {
+            UserPtr::new(old).write(current)?;
+        }
+        Ok(0)
+    }
+
+    fn sigprocmask(&self, how: u32, set: *const u64, old: *mut u64) -> SysResult {
+        let pid = caller();
+        let current = scheduler::signals(pid, |signals| signals.blocked).ok_or(Errno::ESRCH)?;
+        if !set.is_null() {
+            let set = UserPtr::new(set.cast_mut()).read()?;
+            scheduler::signals(pid, |signals| signals.set_blocked(how, set))
+                .ok_or(Errno::ESRCH)??;
+        }
+        if !old.is_null() {
+            UserPtr::new(old).write(current)?;
+        }
+        Ok(0)
     }
-}
 
-fn sys_sleep(ticks: usize, _unused: usize, _unused2: usize) -> isize {
-    println!("sys_sleep({ticks}) called");
-    0
+    /// A frame that cannot be read back, or would return into the kernel,
+    /// gets the process `SIGSEGV` instead.
+    fn sigreturn(&self) -> SysResult {
+        let pid = caller();
+        let rsp = scheduler::user_registers(pid).ok_or(Errno::ESRCH)?.rsp;
+        match userspace::signal::restore(pid, rsp) {
+            Ok(registers) => {
+                scheduler::save_user_registers(pid, registers);
+                Ok(0)
+            }
+            Err(errno) => {
+                scheduler::signals(pid, |signals| signals.force(SIGSEGV));
+                Err(errno)
+            }
+        }
+    }
+
+    /// Blocks the calling process and wakes it from a timer `ticks` ticks
+    /// later, or sooner on a signal.
+    fn sleep(&self, ticks: u64) -> SysResult {
+        if ticks == 0 {
+            return Ok(0);
+        }
+        let pid = caller();
+        let delay_ns =
+            ticks.saturating_mul(timer::clocksource::NSEC_PER_SEC / timer::hz().max(1) as u64);
+        scheduler::block(pid);
+        let wakeup = timer::hrtimer::arm_after(delay_ns, Box::new(move || scheduler::wake(pid)));
+        scheduler::schedule();
+        // After a signal the timer is still pending, and would otherwise
+        // wake the process out of whatever it blocks on next.
+        timer::hrtimer::cancel(wakeup);
+        Ok(0)
+    }
+
+    fn clock_gettime(&self, clock: u32, ts: *mut Timespec) -> SysResult {
+        let clock = timer::ClockId::from_raw(clock as usize).ok_or(Errno::EINVAL)?;
+        UserPtr::new(ts).write(timer::clock_gettime(clock))?;
+        Ok(0)
+    }
+
+    fn irq_bind(&self, line: u8, bits: u64) -> SysResult {
+        let pid = caller();
+        if !scheduler::can(pid, Capability::DriverIo) {
+            return Err(Errno::EPERM);
+        }
+        if bits & NOTIFY_CHILD != 0 {
+            return Err(Errno::EINVAL);
+        }
+        interrupts::user::bind(line, pid, bits).map_err(|_| Errno::EINVAL)?;
+        Ok(0)
+    }
+
+    fn irq_ack(&self, line: u8) -> SysResult {
+        let pid = caller();
+        interrupts::user::ack(line, pid).map_err(|_| Errno::EINVAL)?;
+        Ok(0)
+    }
+
+    fn irq_unbind(&self, line: u8) -> SysResult {
+        let pid = caller();
+        interrupts::user::unbind(line, pid).map_err(|_| Errno::EINVAL)?;
+        Ok(0)
+    }
+
+    /// Collects the pending bits; with none pending this stores 0 and
+    /// blocks, and the caller retries once woken.
+    fn notify_wait(&self, bits: *mut u64) -> SysResult {
+        let pid = caller();
+        let out = UserPtr::new(bits);
+        // Fail before consuming the bits, not after.
+        out.write(0)?;
+        out.write(scheduler::wait_notification(pid))?;
+        Ok(0)
+    }
 }
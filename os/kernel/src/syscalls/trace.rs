@@ -0,0 +1,136 @@
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use syscall::{call_bit, Call, Errno, Syscall, MAX_ARGS};
+
+use crate::logging::LogLevel;
+use crate::timer::clocksource;
+
+/// Traced calls by pid, one bit per call number. Pid 0 matches every
+/// process.
+static MASKS: Mutex<BTreeMap<u32, u64>> = Mutex::new(BTreeMap::new());
+/// Set while `MASKS` is non-empty, so untraced calls skip the lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Every process.
+const ANY_PID: u32 = 0;
+
+/// Applies `trace=CALLS[@PID]` from the kernel command line. CALLS is
+/// `all` or a comma-separated list of call names; without a pid every
+/// process is traced.
+pub fn init(cmdline: &str) {
+    for spec in cmdline
+        .split_whitespace()
+        .filter_map(|option| option.strip_prefix("trace="))
+    {
+        match parse(spec) {
+            Ok((pid, calls)) => {
+                set(pid, calls);
+                klog!(
+                    LogLevel::Info,
+                    "trace: {} for pid {} ({:#x})",
+                    spec,
+                    pid,
+                    calls
+                );
+            }
+            Err(err) => klog!(LogLevel::Warn, "trace: ignoring {:?}: {}", spec, err),
+        }
+    }
+}
+
+fn parse(spec: &str) -> Result<(u32, u64), &'static str> {
+    let (calls, pid) = match spec.split_once('@') {
+        Some((calls, pid)) => (calls, pid.parse().map_err(|_| "bad pid")?),
+        None => (spec, ANY_PID),
+    };
+    if calls == "all" {
+        return Ok((pid, u64::MAX));
+    }
+    let mut mask = 0;
+    for name in calls.split(',') {
+        let call = Syscall::from_name(name).ok_or("unknown call")?;
+        mask |= call_bit(call as usize);
+    }
+    Ok((pid, mask))
+}
+
+/// Traces the calls in `calls` made by `pid`, or stops tracing it if
+/// `calls` is empty.
+pub fn set(pid: u32, calls: u64) {
+    let mut masks = MASKS.lock();
+    if calls == 0 {
+        masks.remove(&pid);
+    } else {
+        masks.insert(pid, calls);
+    }
+    ACTIVE.store(!masks.is_empty(), Ordering::Relaxed);
+}
+
+pub fn traced(pid: u32, number: usize) -> bool {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return false;
+    }
+    let masks = MASKS.lock();
+    let mask = [pid, ANY_PID]
+        .iter()
+        .filter_map(|pid| masks.get(pid))
+        .fold(0, |mask, calls| mask | calls);
+    mask & call_bit(number) != 0
+}
+
+/// Runs call `number` for `pid` through `run` and logs it with its
+/// decoded arguments, result and duration. Calls that do not return are
+/// logged before they run.
+pub fn record(
+    pid: u32,
+    number: usize,
+    args: [usize; MAX_ARGS],
+    run: impl FnOnce() -> isize,
+) -> isize {
+    let Some(syscall) = Syscall::from_raw(number) else {
+        let raw = run();
+        klog!(
+            LogLevel::Trace,
+            "syscall: pid {} #{} = {}",
+            pid,
+            number,
+            Errno::ENOSYS
+        );
+        return raw;
+    };
+    let call = Call { syscall, args };
+    if syscall == Syscall::Exit {
+        klog!(LogLevel::Trace, "syscall: pid {} {} = ?", pid, call);
+    }
+    let start = clocksource::now_ns();
+    let raw = run();
+    let took = clocksource::now_ns() - start;
+    match syscall::decode(raw) {
+        Ok(value) => klog!(
+            LogLevel::Trace,
+            "syscall: pid {} {} = {} <{} ns>",
+            pid,
+            call,
+            value,
+            took
+        ),
+        Err(errno) => klog!(
+            LogLevel::Trace,
+            "syscall: pid {} {} = {} <{} ns>",
+            pid,
+            call,
+            errno,
+            took
+        ),
+    }
+    raw
+}
+
+/// Drops the filter for `pid`; called when it exits.
+pub fn release(pid: u32) {
+    if pid != ANY_PID && ACTIVE.load(Ordering::Relaxed) {
+        set(pid, 0);
+    }
+}
@@ -0,0 +1,103 @@
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use super::hpet;
+use crate::arch::x86::cpu::{self, Feature};
+use crate::logging::LogLevel;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Counter behind `now_ns`. Until `init` runs it is the tick count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clocksource {
+    Pit = 0,
+    Hpet = 1,
+    Tsc = 2,
+}
+
+impl Clocksource {
+    pub fn name(self) -> &'static str {
+        match self {
+            Clocksource::Pit => "pit",
+            Clocksource::Hpet => "hpet",
+            Clocksource::Tsc => "tsc",
+        }
+    }
+}
+
+static KIND: AtomicU8 = AtomicU8::new(Clocksource::Pit as u8);
+/// `ns = OFFSET_NS + (counter - BASE) * MULT >> SHIFT`, so switching
+/// counters at `init` does not make the clock jump.
+static BASE: AtomicU64 = AtomicU64::new(0);
+static MULT: AtomicU64 = AtomicU64::new(0);
+static OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+/// Latest value handed out; keeps readings from different CPUs ordered.
+static LAST_NS: AtomicU64 = AtomicU64::new(0);
+
+const SHIFT: u32 = 32;
+
+/// Switches to the best counter: the TSC when CPUID reports it invariant,
+/// else the HPET, else the tick count. Runs once, after the boot timer
+/// calibration has measured the TSC.
+pub fn init() {
+    let invariant_tsc = cpu::has(Feature::InvariantTsc);
+    let tsc_hz = super::tsc_hz();
+
+    let (kind, hz) = if invariant_tsc && tsc_hz != 0 {
+        (Clocksource::Tsc, tsc_hz)
+    } else if hpet::available() {
+        (Clocksource::Hpet, hpet::frequency())
+    } else {
+        klog!(
+            LogLevel::Info,
+            "clocksource: pit ({} ns resolution)",
+            NSEC_PER_SEC / super::hz().max(1) as u64
+        );
+        return;
+    };
+
+    let offset = now_ns();
+    MULT.store(
+        ((NSEC_PER_SEC as u128) << SHIFT).div_ceil(hz as u128) as u64,
+        Ordering::Relaxed,
+    );
+    OFFSET_NS.store(offset, Ordering::Relaxed);
+    BASE.store(read_counter(kind), Ordering::Relaxed);
+    KIND.store(kind as u8, Ordering::Release);
+    klog!(
+        LogLevel::Info,
+        "clocksource: {} at {} Hz (invariant tsc: {})",
+        kind.name(),
+        hz,
+        invariant_tsc
+    );
+}
+
+pub fn current() -> Clocksource {
+    match KIND.load(Ordering::Acquire) {
+        1 => Clocksource::Hpet,
+        2 => Clocksource::Tsc,
+        _ => Clocksource::Pit,
+    }
+}
+
+/// Nanoseconds since boot. Never goes backwards.
+pub fn now_ns() -> u64 {
+    let ns = match current() {
+        Clocksource::Pit => super::uptime_ticks() * (NSEC_PER_SEC / super::hz().max(1) as u64),
+        kind => {
+            let elapsed = read_counter(kind).wrapping_sub(BASE.load(Ordering::Relaxed));
+            let scaled = (elapsed as u128 * MULT.load(Ordering::Relaxed) as u128) >> SHIFT;
+            OFFSET_NS.load(Ordering::Relaxed) + scaled as u64
+        }
+    };
+    let previous = LAST_NS.fetch_max(ns, Ordering::AcqRel);
+    previous.max(ns)
+}
+
+fn read_counter(kind: Clocksource) -> u64 {
+    match kind {
+        Clocksource::Pit => super::uptime_ticks(),
+        Clocksource::Hpet => hpet::counter(),
+        Clocksource::Tsc => super::tsc(),
+    }
+}
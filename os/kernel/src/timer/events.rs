@@ -0,0 +1,147 @@
+use core::arch::asm;
+
+use spin::Mutex;
+use x86::irq;
+
+use super::{clocksource, hrtimer, TickSource};
+use crate::scheduler;
+use crate::smp::{self, percpu, MAX_CPUS};
+
+/// A tick this close to its due time counts as due, so an interrupt that
+/// fires a hair early does not cost a whole extra period.
+const TICK_SLACK_NS: u64 = 50_000;
+/// Longest a tickless idle CPU sleeps with no timer pending.
+const MAX_IDLE_NS: u64 = 1_000_000_000;
+/// Shortest delay worth programming; anything closer fires right away.
+const MIN_EVENT_NS: u64 = 1_000;
+
+/// Next tick of one CPU once its local timer is programmed event by event.
+#[derive(Clone, Copy)]
+struct TickState {
+    next_tick_ns: u64,
+    stopped: bool,
+}
+
+static STATES: [Mutex<Option<TickState>>; MAX_CPUS] = [const { Mutex::new(None) }; MAX_CPUS];
+
+/// Event-driven mode needs the LAPIC for one-shot interrupts and a
+/// clocksource finer than the tick to measure deadlines against.
+fn high_resolution() -> bool {
+    super::source() != TickSource::Pit && clocksource::current() != clocksource::Clocksource::Pit
+}
+
+fn period_ns() -> u64 {
+    clocksource::NSEC_PER_SEC / super::hz().max(1) as u64
+}
+
+/// Local timer interrupt with the LAPIC as the tick source. Runs the tick
+/// if one is due (several after a tickless sleep), expired timers, and
+/// programs the next interrupt.
+pub fn handle_local_timer() {
+    let cpu = percpu::current().index();
+    if !high_resolution() {
+        super::rearm_local();
+        if cpu == 0 {
+            super::tick();
+        }
+        smp::local_tick();
+        hrtimer::run_expired(clocksource::now_ns());
+        return;
+    }
+
+    let now = clocksource::now_ns();
+    let due = due_ticks(cpu, now);
+    if due > 0 {
+        if cpu == 0 {
+            super::advance(due);
+        }
+        smp::local_tick();
+    }
+    hrtimer::run_expired(now);
+    program_next_event(cpu);
+}
+
+/// Runs whatever else is ready on this CPU, then halts until the next
+/// interrupt. A CPU with nothing but its idle task to run first stops its
+/// tick, so it sleeps until the nearest timer instead of waking every
+/// tick.
+pub fn idle() {
+    scheduler::schedule();
+    unsafe { irq::disable() };
+    let cpu = percpu::current();
+    let index = cpu.index();
+    let tickless = high_resolution() && cpu.current_pid() == cpu.idle_pid();
+    if tickless {
+        set_stopped(index, true);
+        program_next_event(index);
+    }
+
+    // `sti` only takes effect after `hlt` starts, so a wakeup interrupt
+    // cannot slip in between the two.
+    unsafe { asm!("sti", "hlt", options(nomem, nostack)) };
+
+    if tickless {
+        unsafe { irq::disable() };
+        set_stopped(index, false);
+        let due = due_ticks(index, clocksource::now_ns());
+        if index == 0 {
+            super::advance(due);
+        } else if is_stopped(0) {
+            // CPU 0 keeps `uptime_ticks`; wake it so it catches up.
+            smp::send_reschedule(0);
+        }
+        program_next_event(index);
+        unsafe { irq::enable() };
+    }
+}
+
+/// Ticks that fell due on `cpu` by `now`, counting any slept through, and
+/// moves its next tick past `now`. The first call just starts the count.
+fn due_ticks(cpu: usize, now: u64) -> u64 {
+    let period = period_ns();
+    let mut state = STATES[cpu].lock();
+    let Some(state) = state.as_mut() else {
+        *state = Some(TickState {
+            next_tick_ns: now + period,
+            stopped: false,
+        });
+        return 1;
+    };
+    if now + TICK_SLACK_NS < state.next_tick_ns {
+        return 0;
+    }
+    let due = 1 + now.saturating_sub(state.next_tick_ns) / period;
+    state.next_tick_ns += due * period;
+    due
+}
+
+/// Programs the local timer for the earlier of the next tick (skipped
+/// while stopped) and the nearest timer deadline.
+fn program_next_event(cpu: usize) {
+    let now = clocksource::now_ns();
+    let tick = match *STATES[cpu].lock() {
+        Some(TickState { stopped: true, .. }) => now + MAX_IDLE_NS,
+        Some(state) => state.next_tick_ns,
+        None => now + period_ns(),
+    };
+    let next = hrtimer::next_deadline().map_or(tick, |deadline| deadline.min(tick));
+    let _ = super::arm_oneshot_ns(next.saturating_sub(now).max(MIN_EVENT_NS));
+}
+
+fn set_stopped(cpu: usize, stopped: bool) {
+    if let Some(state) = STATES[cpu].lock().as_mut() {
+        state.stopped = stopped;
+    }
+}
+
+fn is_stopped(cpu: usize) -> bool {
+    STATES[cpu].lock().is_some_and(|state| state.stopped)
+}
+
+/// Brings the calling CPU's next interrupt forward after a timer was armed
+/// or moved. Needs interrupts disabled.
+pub fn timers_changed() {
+    if high_resolution() {
+        program_next_event(percpu::current().index());
+    }
+}
@@ -0,0 +1,75 @@
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::arch::x86::paging;
+
+const REG_CAPABILITIES: usize = 0x000;
+const REG_CONFIG: usize = 0x010;
+const REG_COUNTER: usize = 0x0F0;
+
+const CONFIG_ENABLE: u32 = 1 << 0;
+
+/// Longest counter period the specification allows (100 ns).
+const MAX_PERIOD_FS: u32 = 100_000_000;
+
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static PERIOD_FS: AtomicU32 = AtomicU32::new(0);
+
+/// Maps the HPET at `base` and starts its main counter. Returns false if
+/// the capabilities register does not look like an HPET.
+pub fn init(base: usize) -> bool {
+    paging::identity_map_range(
+        base,
+        0x400,
+        paging::PAGE_WRITABLE | paging::PAGE_CACHE_DISABLE | paging::PAGE_WRITE_THROUGH,
+    );
+    BASE.store(base, Ordering::SeqCst);
+
+    // The upper half of the capabilities register is the period in fs.
+    let period = read(REG_CAPABILITIES + 4);
+    if period == 0 || period > MAX_PERIOD_FS {
+        BASE.store(0, Ordering::SeqCst);
+        return false;
+    }
+    PERIOD_FS.store(period, Ordering::SeqCst);
+    write(REG_CONFIG, read(REG_CONFIG) | CONFIG_ENABLE);
+    true
+}
+
+pub fn available() -> bool {
+    PERIOD_FS.load(Ordering::Relaxed) != 0
+}
+
+/// Main counter increments per second.
+pub fn frequency() -> u64 {
+    1_000_000_000_000_000 / PERIOD_FS.load(Ordering::Relaxed).max(1) as u64
+}
+
+/// Main counter, read as two halves until the upper one is stable.
+pub fn counter() -> u64 {
+    loop {
+        let high = read(REG_COUNTER + 4);
+        let low = read(REG_COUNTER);
+        if read(REG_COUNTER + 4) == high {
+            return (high as u64) << 32 | low as u64;
+        }
+    }
+}
+
+/// Busy-waits `ns` nanoseconds on the main counter.
+pub fn wait_ns(ns: u64) {
+    let ticks = super::common::hpet_ticks(PERIOD_FS.load(Ordering::Relaxed), ns);
+    let start = counter();
+    while counter().wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+fn read(reg: usize) -> u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { core::ptr::read_volatile((base + reg) as *const u32) }
+}
+
+fn write(reg: usize, value: u32) {
+    let base = BASE.load(Ordering::Relaxed);
+    unsafe { core::ptr::write_volatile((base + reg) as *mut u32, value) }
+}
@@ -0,0 +1,63 @@
+use spin::Mutex;
+
+use super::{clocksource, events};
+use crate::arch::x86::without_interrupts;
+
+/// The deadline-ordered queue itself, shared with and tested in the
+/// kernel crate.
+#[path = "../../../../kernel/src/hrtimer.rs"]
+#[allow(dead_code)]
+mod queue;
+
+use queue::TimerQueue;
+pub use queue::{Callback, TimerId};
+
+static TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue::new());
+
+/// Runs `callback` once at `deadline_ns` on the `clocksource::now_ns` scale.
+/// Callbacks run in interrupt context on whichever CPU notices the expiry.
+pub fn arm_at(deadline_ns: u64, callback: Callback) -> TimerId {
+    changing(|timers| timers.arm(deadline_ns, callback))
+}
+
+pub fn arm_after(delay_ns: u64, callback: Callback) -> TimerId {
+    arm_at(clocksource::now_ns().saturating_add(delay_ns), callback)
+}
+
+pub fn arm_periodic(period_ns: u64, callback: Callback) -> Result<TimerId, &'static str> {
+    let first = clocksource::now_ns().saturating_add(period_ns);
+    changing(|timers| timers.arm_periodic(first, period_ns, callback))
+}
+
+pub fn cancel(id: TimerId) -> bool {
+    without_interrupts(|| TIMERS.lock().cancel(id))
+}
+
+pub fn next_deadline() -> Option<u64> {
+    without_interrupts(|| TIMERS.lock().next_deadline())
+}
+
+/// Runs every timer due at `now_ns`. The queue is unlocked while each
+/// callback runs, so callbacks may arm, cancel or rearm timers. Called from
+/// the timer interrupt.
+pub fn run_expired(now_ns: u64) -> usize {
+    let mut fired = 0;
+    loop {
+        let Some(mut expired) = TIMERS.lock().pop_expired(now_ns) else {
+            return fired;
+        };
+        expired.run();
+        TIMERS.lock().complete(expired, now_ns);
+        fired += 1;
+    }
+}
+
+/// Edits the queue, then lets the local timer fire earlier if the nearest
+/// deadline moved closer. The timer interrupt takes the same lock.
+fn changing<R>(edit: impl FnOnce(&mut TimerQueue) -> R) -> R {
+    without_interrupts(|| {
+        let result = edit(&mut TIMERS.lock());
+        events::timers_changed();
+        result
+    })
+}
@@ -1,21 +1,298 @@
-use core::sync::atomic::{AtomicU64, Ordering};
-use x86::io::outb;
+pub mod clocksource;
+/// Tick source choice, LAPIC timer encoding and calibration arithmetic,
+/// shared with and tested in the kernel crate.
+#[path = "../../../../kernel/src/timer.rs"]
+#[allow(dead_code)]
+pub mod common;
+pub mod events;
+pub mod hpet;
+pub mod hrtimer;
+pub mod pit;
+
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+use crate::acpi;
+use crate::arch::x86::cpu::{self, Feature};
+use crate::arch::x86::without_interrupts;
+use crate::drivers::{lapic, rtc};
+use crate::interrupts::irq::{self, IrqReturn};
+use crate::logging::LogLevel;
+use crate::process::ExecMode;
+use crate::smp;
+
+pub use common::TickSource;
+use common::{Calibration, TimerCaps};
+pub use syscall::Timespec;
 
 static TICKS: AtomicU64 = AtomicU64::new(0);
+static HZ: AtomicU32 = AtomicU32::new(0);
+
+/// TSC at the first and the most recent tick, used to estimate the TSC rate.
+static FIRST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
+static LAST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
 
+/// Calibration window for the LAPIC timer and the TSC.
+const CALIBRATION_MS: u32 = 10;
+
+static SOURCE: AtomicU8 = AtomicU8::new(TickSource::Pit as u8);
+/// Undivided LAPIC timer clock and TSC rate measured at boot, 0 if unknown.
+static LAPIC_HZ: AtomicU64 = AtomicU64::new(0);
+static CALIBRATED_TSC_HZ: AtomicU64 = AtomicU64::new(0);
+/// LAPIC initial count or TSC delta for one tick, depending on `SOURCE`.
+static TICK_PERIOD: AtomicU64 = AtomicU64::new(0);
+
+/// Unix time in nanoseconds at which `clocksource::now_ns` read zero.
+static BOOT_EPOCH_NS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime = 0,
+    Monotonic = 1,
+}
+
+impl ClockId {
+    pub fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(ClockId::Realtime),
+            1 => Some(ClockId::Monotonic),
+            _ => None,
+        }
+    }
+}
+
+/// Starts the PIT at `hz`. It drives the tick until `select_source` finds
+/// something better, and stays the fallback otherwise.
 pub fn init(hz: u32) {
-    let divisor: u16 = (1193180 / hz) as u16;
-    unsafe {
-        outb(0x43, 0x36);
-        outb(0x40, (divisor & 0xFF) as u8);
-        outb(0x40, ((divisor >> 8) & 0xFF) as u8);
+    HZ.store(hz, Ordering::Relaxed);
+    pit::init_periodic(hz);
+    irq::register(pit::IRQ, pit_interrupt, 0).expect("PIT IRQ line is free");
+}
+
+/// IRQ0: the tick while the PIT is the tick source, forwarded to the APs.
+fn pit_interrupt(_ctx: usize, _mode: ExecMode) -> IrqReturn {
+    tick();
+    smp::broadcast_tick();
+    smp::local_tick();
+    hrtimer::run_expired(clocksource::now_ns());
+    IrqReturn::Handled
+}
+
+pub fn hz() -> u32 {
+    HZ.load(Ordering::Relaxed)
+}
+
+/// Reads the CMOS RTC once so `ClockId::Realtime` has a wall-clock base.
+pub fn init_wall_clock() {
+    match rtc::read(acpi::century_register()) {
+        Ok(now) => {
+            let epoch_ns = now.unix_seconds() * clocksource::NSEC_PER_SEC;
+            BOOT_EPOCH_NS.store(
+                epoch_ns.saturating_sub(clocksource::now_ns()),
+                Ordering::Relaxed,
+            );
+            klog!(LogLevel::Info, "rtc: {}", now);
+        }
+        Err(err) => klog!(LogLevel::Warn, "rtc: {}, wall clock starts at 1970", err),
+    }
+}
+
+pub fn clock_gettime(clock: ClockId) -> Timespec {
+    let mut ns = clocksource::now_ns();
+    if clock == ClockId::Realtime {
+        ns += BOOT_EPOCH_NS.load(Ordering::Relaxed);
+    }
+    Timespec {
+        sec: ns / clocksource::NSEC_PER_SEC,
+        nsec: ns % clocksource::NSEC_PER_SEC,
+    }
+}
+
+pub fn source() -> TickSource {
+    match SOURCE.load(Ordering::Relaxed) {
+        1 => TickSource::LapicPeriodic,
+        2 => TickSource::TscDeadline,
+        _ => TickSource::Pit,
+    }
+}
+
+/// Picks the boot tick source: TSC-deadline if CPUID reports it, else the
+/// periodic LAPIC timer, else the PIT. Both LAPIC modes are calibrated
+/// against the HPET when ACPI lists one and against PIT channel 2
+/// otherwise. Runs on the BSP after its LAPIC is enabled.
+pub fn select_source(lapic_available: bool) {
+    if let Some(base) = acpi::hpet_address() {
+        if !hpet::init(base) {
+            klog!(LogLevel::Warn, "timer: HPET at {:#x} is not usable", base);
+        }
+    }
+
+    let hz = HZ.load(Ordering::Relaxed);
+    if !lapic_available {
+        klog!(
+            LogLevel::Info,
+            "timer: tick source pit at {} Hz (no local APIC)",
+            hz
+        );
+        return;
+    }
+
+    let reference = if hpet::available() { "hpet" } else { "pit" };
+    let calibrated = calibrate().and_then(|calibration| {
+        common::lapic_initial_count(calibration.lapic_hz, lapic::TIMER_DIVISOR, hz)
+            .map(|count| (calibration, count))
+    });
+    let (Calibration { lapic_hz, tsc_hz }, count) = match calibrated {
+        Ok(calibrated) => calibrated,
+        Err(err) => {
+            klog!(
+                LogLevel::Warn,
+                "timer: cannot tick from the LAPIC at {} Hz ({}), keeping the pit",
+                hz,
+                err
+            );
+            return;
+        }
+    };
+    LAPIC_HZ.store(lapic_hz, Ordering::Relaxed);
+    CALIBRATED_TSC_HZ.store(tsc_hz, Ordering::Relaxed);
+
+    let source = common::choose_tick_source(TimerCaps {
+        lapic_hz: Some(lapic_hz),
+        tsc_deadline: cpu::has(Feature::TscDeadline),
+        tsc_hz: (tsc_hz >= hz as u64).then_some(tsc_hz),
+    });
+    let period = match source {
+        TickSource::TscDeadline => common::tsc_deadline_delta(tsc_hz, hz),
+        _ => count as u64,
+    };
+    TICK_PERIOD.store(period, Ordering::Relaxed);
+    SOURCE.store(source as u8, Ordering::Release);
+
+    irq::mask(pit::IRQ);
+    init_local();
+    klog!(
+        LogLevel::Info,
+        "timer: tick source {} at {} Hz (lapic {} Hz, tsc {} Hz, calibrated via {})",
+        source.name(),
+        hz,
+        lapic_hz,
+        tsc_hz,
+        reference
+    );
+}
+
+/// Starts the calling CPU's LAPIC timer in the mode `select_source` chose.
+/// A no-op with the PIT, whose ticks reach the APs as IPIs.
+pub fn init_local() {
+    let period = TICK_PERIOD.load(Ordering::Relaxed);
+    match source() {
+        TickSource::Pit => {}
+        TickSource::LapicPeriodic => lapic::timer_periodic(smp::TICK_VECTOR, period as u32),
+        TickSource::TscDeadline => {
+            lapic::timer_tsc_deadline(smp::TICK_VECTOR);
+            lapic::set_tsc_deadline(tsc() + period);
+        }
     }
 }
 
+/// Called from the local timer interrupt; TSC-deadline mode has no
+/// periodic variant, so the next deadline is armed here.
+pub fn rearm_local() {
+    if source() == TickSource::TscDeadline {
+        lapic::set_tsc_deadline(tsc() + TICK_PERIOD.load(Ordering::Relaxed));
+    }
+}
+
+/// Fires the local timer vector once, `ns` nanoseconds from now, replacing
+/// the periodic tick on this CPU. Fails while the PIT is the tick source.
+pub fn arm_oneshot_ns(ns: u64) -> Result<(), &'static str> {
+    match source() {
+        TickSource::Pit => Err("one-shot timer needs the LAPIC"),
+        TickSource::LapicPeriodic => {
+            let rate = LAPIC_HZ.load(Ordering::Relaxed) / lapic::TIMER_DIVISOR as u64;
+            let count = (ns as u128 * rate as u128 / 1_000_000_000).clamp(1, u32::MAX as u128);
+            lapic::timer_oneshot(smp::TICK_VECTOR, count as u32);
+            Ok(())
+        }
+        TickSource::TscDeadline => {
+            let cycles =
+                ns as u128 * CALIBRATED_TSC_HZ.load(Ordering::Relaxed) as u128 / 1_000_000_000;
+            lapic::set_tsc_deadline(tsc() + cycles.max(1) as u64);
+            Ok(())
+        }
+    }
+}
+
+/// Counts LAPIC timer clocks and TSC cycles over `CALIBRATION_MS` of HPET
+/// or PIT time, with interrupts off so nothing stretches the window.
+fn calibrate() -> Result<Calibration, &'static str> {
+    let (lapic_elapsed, tsc_elapsed) = without_interrupts(|| {
+        lapic::timer_start_counting();
+        let tsc_start = tsc();
+        if hpet::available() {
+            hpet::wait_ns(CALIBRATION_MS as u64 * 1_000_000);
+        } else {
+            pit::wait_ms(CALIBRATION_MS);
+        }
+        let lapic_elapsed = u32::MAX - lapic::timer_current_count();
+        let tsc_elapsed = tsc().wrapping_sub(tsc_start);
+        lapic::timer_stop();
+        (lapic_elapsed, tsc_elapsed)
+    });
+
+    common::calibrate(
+        lapic_elapsed,
+        lapic::TIMER_DIVISOR,
+        tsc_elapsed,
+        CALIBRATION_MS as u64 * 1_000_000,
+    )
+}
+
 pub fn tick() {
-    TICKS.fetch_add(1, Ordering::Relaxed);
+    advance(1);
+}
+
+/// Counts `ticks` ticks at once, e.g. those CPU 0 slept through tickless.
+pub fn advance(ticks: u64) {
+    if ticks == 0 {
+        return;
+    }
+    let now = tsc();
+    if TICKS.fetch_add(ticks, Ordering::Relaxed) == 0 {
+        FIRST_TICK_TSC.store(now, Ordering::Relaxed);
+    }
+    LAST_TICK_TSC.store(now, Ordering::Relaxed);
 }
 
 pub fn uptime_ticks() -> u64 {
     TICKS.load(Ordering::Relaxed)
 }
+
+pub fn tsc() -> u64 {
+    unsafe { x86::time::rdtsc() }
+}
+
+/// TSC cycles per second: the boot calibration if one ran, otherwise
+/// measured against the tick, or 0 until at least two ticks have been seen.
+pub fn tsc_hz() -> u64 {
+    let calibrated = CALIBRATED_TSC_HZ.load(Ordering::Relaxed);
+    if calibrated != 0 {
+        return calibrated;
+    }
+    let ticks = uptime_ticks();
+    if ticks < 2 {
+        return 0;
+    }
+    let elapsed = LAST_TICK_TSC
+        .load(Ordering::Relaxed)
+        .saturating_sub(FIRST_TICK_TSC.load(Ordering::Relaxed));
+    elapsed * HZ.load(Ordering::Relaxed) as u64 / (ticks - 1)
+}
+
+/// Busy-waits for at least `ticks` timer interrupts. Interrupts must be enabled.
+pub fn sleep_ticks(ticks: u64) {
+    let deadline = uptime_ticks() + ticks + 1;
+    while uptime_ticks() < deadline {
+        core::hint::spin_loop();
+    }
+}
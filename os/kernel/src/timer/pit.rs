@@ -0,0 +1,51 @@
+use x86::io::{inb, outb};
+
+/// ISA line channel 0 raises.
+pub const IRQ: u8 = 0;
+/// 8254 input clock in Hz.
+pub const INPUT_HZ: u32 = 1_193_182;
+
+const CHANNEL0: u16 = 0x40;
+const CHANNEL2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Port B of the keyboard controller: channel 2 gate (bit 0), speaker
+/// enable (bit 1) and channel 2 output (bit 5).
+const PORT_B: u16 = 0x61;
+
+const GATE2: u8 = 1 << 0;
+const SPEAKER: u8 = 1 << 1;
+const OUT2: u8 = 1 << 5;
+
+/// Reload value for `hz`, clamped to what the 16-bit counter can hold.
+/// 0 would mean 65536, so the slowest rate uses `u16::MAX` instead.
+pub fn divisor_for(hz: u32) -> u16 {
+    (INPUT_HZ / hz.max(1)).clamp(1, u16::MAX as u32) as u16
+}
+
+/// Programs channel 0 as a rate generator firing IRQ0 at about `hz`.
+pub fn init_periodic(hz: u32) {
+    let divisor = divisor_for(hz);
+    unsafe {
+        outb(COMMAND, 0x36);
+        outb(CHANNEL0, (divisor & 0xFF) as u8);
+        outb(CHANNEL0, (divisor >> 8) as u8);
+    }
+}
+
+/// Busy-waits `ms` milliseconds (at most 54) on channel 2, which needs
+/// neither interrupts nor IRQ0.
+pub fn wait_ms(ms: u32) {
+    let count = (INPUT_HZ / 1000 * ms).clamp(1, u16::MAX as u32) as u16;
+    unsafe {
+        let port_b = inb(PORT_B);
+        outb(PORT_B, (port_b & !SPEAKER) | GATE2);
+        // Channel 2, lobyte/hibyte, mode 0: OUT2 goes high at terminal count.
+        outb(COMMAND, 0xB0);
+        outb(CHANNEL2, (count & 0xFF) as u8);
+        outb(CHANNEL2, (count >> 8) as u8);
+        while inb(PORT_B) & OUT2 == 0 {
+            core::hint::spin_loop();
+        }
+        outb(PORT_B, port_b);
+    }
+}
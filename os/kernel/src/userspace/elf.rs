@@ -0,0 +1,192 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use syscall::Errno;
+
+use crate::arch::x86::cpu::{self, Feature};
+use crate::arch::x86::paging::{PAGE_NO_EXECUTE, PAGE_SIZE, PAGE_WRITABLE};
+use crate::memory::address_space::{AddressSpace, USER_BASE, USER_END};
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3E;
+const PT_LOAD: u32 = 1;
+const PT_PHDR: u32 = 6;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const HEADER_SIZE: usize = 64;
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+/// Pages all `PT_LOAD` segments may span together, checked before any is
+/// mapped so a huge `p_memsz` fails fast instead of exhausting memory.
+const MAX_IMAGE_PAGES: usize = 4096;
+
+fn field<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], Errno> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Errno::ENOEXEC)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, Errno> {
+    field(data, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, Errno> {
+    field(data, offset).map(u32::from_le_bytes)
+}
+
+fn usize_at(data: &[u8], offset: usize) -> Result<usize, Errno> {
+    field(data, offset).map(|bytes| u64::from_le_bytes(bytes) as usize)
+}
+
+/// A `PT_LOAD` program header.
+struct Segment {
+    offset: usize,
+    vaddr: usize,
+    file_size: usize,
+    mem_size: usize,
+    flags: u32,
+}
+
+impl Segment {
+    fn pages(&self) -> impl Iterator<Item = usize> {
+        let start = self.vaddr - self.vaddr % PAGE_SIZE;
+        (start..self.vaddr + self.mem_size).step_by(PAGE_SIZE)
+    }
+
+    /// Pages `pages` yields, without walking them.
+    fn page_count(&self) -> usize {
+        (self.vaddr % PAGE_SIZE + self.mem_size).div_ceil(PAGE_SIZE)
+    }
+}
+
+/// A loaded executable, with what its aux vector reports about it.
+pub struct Executable {
+    pub space: AddressSpace,
+    pub entry: usize,
+    /// Where the program headers are mapped, or 0 if no segment covers
+    /// them.
+    pub phdr: usize,
+    pub phnum: usize,
+}
+
+/// Maps the `PT_LOAD` segments of the static x86-64 executable in `data`
+/// into a new address space. Pages are writable only for `PF_W` segments
+/// and executable only for `PF_X` ones, and none may be both.
+pub fn load(data: &[u8]) -> Result<Executable, Errno> {
+    if data.get(..4) != Some(&ELF_MAGIC[..])
+        || data.get(4) != Some(&ELFCLASS64)
+        || data.get(5) != Some(&ELFDATA2LSB)
+        || u16_at(data, 16)? != ET_EXEC
+        || u16_at(data, 18)? != EM_X86_64
+    {
+        return Err(Errno::ENOEXEC);
+    }
+    let entry = usize_at(data, 24)?;
+    let phoff = usize_at(data, 32)?;
+    let phentsize = u16_at(data, 54)? as usize;
+    let phnum = u16_at(data, 56)? as usize;
+    if data.len() < HEADER_SIZE || phentsize < PROGRAM_HEADER_SIZE {
+        return Err(Errno::ENOEXEC);
+    }
+    if !(USER_BASE..USER_END).contains(&entry) {
+        return Err(Errno::ENOEXEC);
+    }
+
+    let mut segments = Vec::new();
+    let mut image_pages = 0usize;
+    let mut phdr = None;
+    for index in 0..phnum {
+        let header = phoff
+            .checked_add(index * phentsize)
+            .and_then(|start| data.get(start..)?.get(..PROGRAM_HEADER_SIZE))
+            .ok_or(Errno::ENOEXEC)?;
+        let kind = u32_at(header, 0)?;
+        if kind == PT_PHDR {
+            phdr = Some(usize_at(header, 16)?);
+        }
+        if kind != PT_LOAD {
+            continue;
+        }
+        let segment = Segment {
+            flags: u32_at(header, 4)?,
+            offset: usize_at(header, 8)?,
+            vaddr: usize_at(header, 16)?,
+            file_size: usize_at(header, 32)?,
+            mem_size: usize_at(header, 40)?,
+        };
+        check_segment(data, &segment)?;
+        image_pages = image_pages.saturating_add(segment.page_count());
+        if image_pages > MAX_IMAGE_PAGES {
+            return Err(Errno::ENOMEM);
+        }
+        segments.push(segment);
+    }
+    // Without PT_PHDR, the headers are wherever the segment holding their
+    // file bytes puts them.
+    let phdr = phdr.or_else(|| {
+        segments.iter().find_map(|segment| {
+            let within = phoff.checked_sub(segment.offset)?;
+            (within < segment.file_size).then_some(segment.vaddr + within)
+        })
+    });
+
+    // Segments may share a page, which then gets the access of both.
+    let mut pages = BTreeMap::new();
+    for segment in &segments {
+        for page in segment.pages() {
+            *pages.entry(page).or_insert(0) |= segment.flags & (PF_W | PF_X);
+        }
+    }
+    if pages.values().any(|&flags| flags == PF_W | PF_X) {
+        return Err(Errno::ENOEXEC);
+    }
+
+    let mut space = AddressSpace::new().map_err(|_| Errno::ENOMEM)?;
+    let no_execute = cpu::enabled().has(Feature::Nx);
+    for (&page, &flags) in &pages {
+        let mut bits = 0;
+        if flags & PF_W != 0 {
+            bits |= PAGE_WRITABLE;
+        }
+        if flags & PF_X == 0 && no_execute {
+            bits |= PAGE_NO_EXECUTE;
+        }
+        space.map(page, bits).map_err(|_| Errno::ENOMEM)?;
+    }
+    for segment in &segments {
+        let bytes = &data[segment.offset..][..segment.file_size];
+        space
+            .write(segment.vaddr, bytes)
+            .map_err(|_| Errno::ENOEXEC)?;
+    }
+    Ok(Executable {
+        space,
+        entry,
+        phdr: phdr.unwrap_or(0),
+        phnum,
+    })
+}
+
+/// Checks that `segment` lies in the user half and its file bytes in
+/// `data`.
+fn check_segment(data: &[u8], segment: &Segment) -> Result<(), Errno> {
+    let end = segment
+        .vaddr
+        .checked_add(segment.mem_size)
+        .ok_or(Errno::ENOEXEC)?;
+    let in_file = segment
+        .offset
+        .checked_add(segment.file_size)
+        .is_some_and(|last| last <= data.len());
+    if segment.vaddr < USER_BASE
+        || end > USER_END
+        || segment.file_size > segment.mem_size
+        || !in_file
+    {
+        return Err(Errno::ENOEXEC);
+    }
+    Ok(())
+}
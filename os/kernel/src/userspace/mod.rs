@@ -0,0 +1,267 @@
+pub mod elf;
+pub mod signal;
+pub mod stack;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use syscall::{Errno, WaitStatus, AT_ENTRY, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM};
+
+use crate::arch::x86::cpu::{self, Feature};
+use crate::arch::x86::paging::{PAGE_NO_EXECUTE, PAGE_SIZE, PAGE_WRITABLE};
+use crate::arch::x86::usermode;
+use crate::filesystem::{fd, vfs};
+use crate::logging::LogLevel;
+use crate::memory::address_space::{AddressSpace, USER_END};
+use crate::memory::frames;
+use crate::process::{Reap, UserRegisters, INIT_PID};
+use crate::{scheduler, smp};
+
+core::arch::global_asm!(include_str!("programs.S"), options(att_syntax));
+
+const KERNEL_STACK_SIZE: usize = 16 * 1024;
+/// Every process's stack ends one page below the top of the user half.
+const USER_STACK_TOP: usize = USER_END - PAGE_SIZE;
+const USER_STACK_PAGES: usize = 4;
+
+extern "C" {
+    static program_init: u8;
+    static program_init_end: u8;
+    static program_hello: u8;
+    static program_hello_end: u8;
+}
+
+#[repr(align(16))]
+struct KernelStack([u8; KERNEL_STACK_SIZE]);
+
+impl KernelStack {
+    fn new() -> Box<Self> {
+        unsafe { Box::new_zeroed().assume_init() }
+    }
+
+    fn top(&self) -> usize {
+        self.0.as_ptr() as usize + KERNEL_STACK_SIZE
+    }
+}
+
+/// What a user process owns beyond its process table entry, kept until
+/// its parent reaps it.
+struct Image {
+    space: AddressSpace,
+    /// `None` for tasks that brought their own, like the usermode
+    /// self-test.
+    _kernel_stack: Option<Box<KernelStack>>,
+}
+
+static IMAGES: Mutex<BTreeMap<u32, Image>> = Mutex::new(BTreeMap::new());
+
+/// Installs the bundled programs in `/bin`, gives init (pid 1) the image
+/// of `/bin/init` and waits for it to end.
+pub fn init() {
+    let programs = unsafe {
+        [
+            ("/bin/init", blob(&program_init, &program_init_end)),
+            ("/bin/hello", blob(&program_hello, &program_hello_end)),
+        ]
+    };
+    for (path, program) in programs {
+        if let Err(errno) = vfs::write(path, program) {
+            klog!(
+                LogLevel::Error,
+                "userspace: cannot install {}: {}",
+                path,
+                errno
+            );
+        }
+    }
+
+    if let Err(errno) = start(INIT_PID, "/bin/init") {
+        klog!(
+            LogLevel::Error,
+            "userspace: cannot start /bin/init: {}",
+            errno
+        );
+        return;
+    }
+    let status = scheduler::join(INIT_PID).map(WaitStatus::decode);
+    let image = IMAGES.lock().remove(&INIT_PID);
+    drop(image);
+    let (free, total) = frames::stats();
+    klog!(
+        LogLevel::Info,
+        "userspace: /bin/init (pid {}) ended: {:?}; {} of {} page frames free",
+        INIT_PID,
+        status,
+        free,
+        total
+    );
+}
+
+/// # Safety
+///
+/// `start` and `end` must delimit one object.
+unsafe fn blob(start: &'static u8, end: &'static u8) -> &'static [u8] {
+    let start = start as *const u8;
+    core::slice::from_raw_parts(start, end as *const u8 as usize - start as usize)
+}
+
+/// Makes the existing process `pid` run the program at `path` in ring 3,
+/// with `path` as its only argument and no environment.
+pub fn start(pid: u32, path: &str) -> Result<(), Errno> {
+    let (space, registers) = load(path, &[path.as_bytes()], &[])?;
+    let kernel_stack = KernelStack::new();
+    let context = usermode::new_context(kernel_stack.top(), space.root());
+    if !scheduler::attach_user(pid, context, registers) {
+        return Err(Errno::ESRCH);
+    }
+    IMAGES.lock().insert(
+        pid,
+        Image {
+            space,
+            _kernel_stack: Some(kernel_stack),
+        },
+    );
+    Ok(())
+}
+
+/// Loads the executable at `path` into a new address space with a stack
+/// holding `argv`, `envp` and the aux vector, and returns it with the
+/// registers to start it with.
+fn load(
+    path: &str,
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+) -> Result<(AddressSpace, UserRegisters), Errno> {
+    let data = vfs::read(path)?;
+    let elf::Executable {
+        mut space,
+        entry,
+        phdr,
+        phnum,
+    } = elf::load(&data)?;
+    let stack_flags = if cpu::enabled().has(Feature::Nx) {
+        PAGE_WRITABLE | PAGE_NO_EXECUTE
+    } else {
+        PAGE_WRITABLE
+    };
+    for page in 1..=USER_STACK_PAGES {
+        space
+            .map(USER_STACK_TOP - page * PAGE_SIZE, stack_flags)
+            .map_err(|_| Errno::ENOMEM)?;
+    }
+    let auxv = [
+        (AT_PHDR, phdr as u64),
+        (AT_PHENT, elf::PROGRAM_HEADER_SIZE as u64),
+        (AT_PHNUM, phnum as u64),
+        (AT_PAGESZ, PAGE_SIZE as u64),
+        (AT_ENTRY, entry as u64),
+    ];
+    let rsp = stack::build(
+        &space,
+        USER_STACK_TOP,
+        USER_STACK_PAGES * PAGE_SIZE,
+        argv,
+        envp,
+        &auxv,
+    )?;
+    let registers = UserRegisters {
+        rip: entry as u64,
+        rsp: rsp as u64,
+        ..UserRegisters::default()
+    };
+    Ok((space, registers))
+}
+
+/// Copies `parent`, which is in a system call, into a new process that
+/// shares its pages copy-on-write and its open files, and returns from
+/// `fork` with 0. Returns the child's pid.
+pub fn fork(parent: u32) -> Result<u32, Errno> {
+    let mut registers = scheduler::user_registers(parent).ok_or(Errno::ESRCH)?;
+    registers.rax = 0;
+    let space = IMAGES
+        .lock()
+        .get(&parent)
+        .ok_or(Errno::EINVAL)?
+        .space
+        .fork()
+        .map_err(|_| Errno::ENOMEM)?;
+    // The parent's pages are read-only now, wherever they are cached.
+    smp::tlb_shootdown(smp::FLUSH_ALL);
+    let kernel_stack = KernelStack::new();
+    let context = usermode::new_context(kernel_stack.top(), space.root());
+    let child = scheduler::fork(parent, context, registers).ok_or(Errno::ESRCH)?;
+    fd::fork(parent, child);
+    IMAGES.lock().insert(
+        child,
+        Image {
+            space,
+            _kernel_stack: Some(kernel_stack),
+        },
+    );
+    Ok(child)
+}
+
+/// Replaces the image of `pid`, the calling process, with the executable
+/// at `path`, started with `argv` and `envp`, and returns the registers it
+/// starts with. The new address space is active on return and the old one
+/// is gone.
+pub fn exec(pid: u32, path: &str, argv: &[&[u8]], envp: &[&[u8]]) -> Result<UserRegisters, Errno> {
+    let (space, registers) = load(path, argv, envp)?;
+    space.activate();
+    scheduler::set_root(pid, space.root());
+    let old = {
+        let mut images = IMAGES.lock();
+        match images.get_mut(&pid) {
+            Some(image) => Some(core::mem::replace(&mut image.space, space)),
+            None => {
+                images.insert(
+                    pid,
+                    Image {
+                        space,
+                        _kernel_stack: None,
+                    },
+                );
+                None
+            }
+        }
+    };
+    drop(old);
+    scheduler::save_user_registers(pid, registers);
+    scheduler::signals(pid, |signals| signals.exec());
+    Ok(registers)
+}
+
+/// Waits for child `pid` of `parent`, or any child if `pid` is 0, to end
+/// or stop, and returns its pid and wait status. `parent` sleeps while its
+/// children run; a signal to act on ends the wait with `EINTR`.
+pub fn wait(parent: u32, pid: u32) -> Result<(u32, i32), Errno> {
+    loop {
+        match scheduler::reap_or_block(parent, pid) {
+            Reap::Exited { pid, status } => {
+                // Outside the lock: the teardown waits on the other CPUs.
+                let image = IMAGES.lock().remove(&pid);
+                drop(image);
+                return Ok((pid, status));
+            }
+            Reap::Stopped { pid, signal } => {
+                return Ok((pid, WaitStatus::Stopped(signal).encode()));
+            }
+            Reap::Alive(_) => {
+                if scheduler::signals(parent, |signals| signals.peek().is_some()).unwrap_or(true) {
+                    return Err(Errno::EINTR);
+                }
+                scheduler::schedule();
+            }
+            Reap::AllStopped => return Err(Errno::EAGAIN),
+            Reap::NoChildren => return Err(Errno::ECHILD),
+        }
+    }
+}
+
+/// Ends the calling process `pid` with wait status `status` and gives up
+/// the CPU for good.
+pub fn terminate(pid: u32, status: i32) -> ! {
+    scheduler::exit(pid, status);
+    scheduler::schedule();
+    unreachable!("pid {} ran again after it exited", pid);
+}
@@ -0,0 +1,144 @@
+use core::mem::size_of;
+use syscall::{Delivery, Errno, WaitStatus, SIGSEGV, SIG_SETMASK};
+
+use crate::arch::x86::usermode;
+use crate::logging::LogLevel;
+use crate::memory::address_space::USER_END;
+use crate::memory::user::{UserData, UserPtr};
+use crate::process::UserRegisters;
+use crate::scheduler;
+
+/// Bytes below RSP the SysV ABI lets leaf functions use unannounced.
+const RED_ZONE: u64 = 128;
+const STACK_ALIGN: u64 = 16;
+const RFLAGS_DF: u64 = 1 << 10;
+
+/// What a handler finds at its stack pointer: the return address, then
+/// what `sigreturn` needs to put the process back where it was.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SignalFrame {
+    restorer: u64,
+    signal: u64,
+    registers: UserRegisters,
+    blocked: u64,
+}
+
+unsafe impl UserData for SignalFrame {}
+
+/// How a process comes out of `deliver`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// It goes on with the registers as they now are, which may be a
+    /// handler's.
+    Resume,
+    /// Stopped; it resumes with the same registers once continued.
+    Stopped,
+    /// It has to die with this wait status.
+    Killed(i32),
+}
+
+/// Acts on the signals `pid` has pending and not blocked, about to return
+/// to ring 3 with `registers` in its own address space. Ignored signals
+/// are dropped; the first caught one gets a signal frame and `registers`
+/// are pointed at its handler. The rest wait for the next return.
+pub fn deliver(pid: u32, registers: &mut UserRegisters) -> Outcome {
+    let Some((signal, delivery)) = scheduler::signals(pid, |signals| signals.dequeue()).flatten()
+    else {
+        return Outcome::Resume;
+    };
+    match delivery {
+        Delivery::Terminate => Outcome::Killed(WaitStatus::Killed(signal as u8).encode()),
+        Delivery::Core => Outcome::Killed(WaitStatus::Dumped(signal as u8).encode()),
+        Delivery::Stop => {
+            scheduler::stop(pid, signal as u8);
+            Outcome::Stopped
+        }
+        Delivery::Handle(action) => {
+            let blocked = scheduler::signals(pid, |signals| signals.enter_handler(signal, &action))
+                .unwrap_or(0);
+            let frame = SignalFrame {
+                restorer: action.restorer as u64,
+                signal: signal as u64,
+                registers: *registers,
+                blocked,
+            };
+            // The handler starts as if called: RSP + 8 is 16-byte aligned.
+            let rsp = (registers
+                .rsp
+                .wrapping_sub(RED_ZONE + size_of::<SignalFrame>() as u64)
+                & !(STACK_ALIGN - 1))
+                .wrapping_sub(8);
+            if UserPtr::new(rsp as *mut SignalFrame).write(frame).is_err() {
+                klog!(
+                    LogLevel::Warn,
+                    "signal: pid {} has no room for a frame at {:#x}",
+                    pid,
+                    rsp
+                );
+                return Outcome::Killed(WaitStatus::Dumped(SIGSEGV as u8).encode());
+            }
+            registers.rip = action.handler as u64;
+            registers.rsp = rsp;
+            registers.rdi = signal as u64;
+            registers.rflags &= !RFLAGS_DF;
+            Outcome::Resume
+        }
+    }
+}
+
+/// `deliver` for `pid`, the process on this CPU, on its way back to ring 3
+/// from a system call or an exception. A stopped process gives up the CPU
+/// here until it is continued, then looks again; a killed one never comes
+/// back.
+pub fn deliver_on_return(pid: u32, registers: &mut UserRegisters) {
+    loop {
+        match deliver(pid, registers) {
+            Outcome::Resume => return,
+            Outcome::Stopped => scheduler::schedule(),
+            Outcome::Killed(status) => super::terminate(pid, status),
+        }
+    }
+}
+
+/// Acts on the signals that stop or kill `pid`, the process on this CPU,
+/// as an interrupt returns to it, so a process that never makes a system
+/// call can still be stopped or killed. Caught signals wait for its next
+/// system call or fault, which have its registers at hand.
+pub fn deliver_on_interrupt(pid: u32) {
+    loop {
+        let fatal = scheduler::signals(pid, |signals| match signals.peek() {
+            Some((_, Delivery::Handle(_))) | None => None,
+            Some(_) => signals.dequeue(),
+        });
+        match fatal.flatten() {
+            None => return,
+            Some((signal, Delivery::Stop)) => {
+                scheduler::stop(pid, signal as u8);
+                scheduler::schedule();
+            }
+            Some((signal, Delivery::Core)) => {
+                super::terminate(pid, WaitStatus::Dumped(signal as u8).encode())
+            }
+            Some((signal, _)) => super::terminate(pid, WaitStatus::Killed(signal as u8).encode()),
+        }
+    }
+}
+
+/// Undoes the signal frame the handler of `pid` returned from: `rsp` is
+/// the stack pointer of its `sigreturn`, one word past the frame. Returns
+/// the registers to go back to, and restores the blocked set.
+pub fn restore(pid: u32, rsp: u64) -> Result<UserRegisters, Errno> {
+    let address = rsp.wrapping_sub(8);
+    let frame = UserPtr::new(address as *mut SignalFrame).read()?;
+    let mut registers = frame.registers;
+    if registers.rip >= USER_END as u64 || registers.rsp >= USER_END as u64 {
+        return Err(Errno::EFAULT);
+    }
+    registers.rflags = usermode::user_rflags(registers.rflags);
+    scheduler::signals(pid, |signals| {
+        signals.set_blocked(SIG_SETMASK, frame.blocked)
+    })
+    .ok_or(Errno::ESRCH)??;
+    Ok(registers)
+}
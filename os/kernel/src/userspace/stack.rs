@@ -0,0 +1,87 @@
+use alloc::vec::Vec;
+use syscall::{Errno, AT_NULL, AT_RANDOM};
+
+use crate::arch::x86::cpu;
+use crate::memory::address_space::AddressSpace;
+use crate::timer;
+
+/// Most bytes of argument and environment strings, NULs included, one
+/// `exec` passes on.
+pub const ARG_MAX: usize = 4096;
+/// Most strings in each of argv and envp.
+pub const MAX_STRINGS: usize = 64;
+const RANDOM_BYTES: usize = 16;
+const WORD: usize = core::mem::size_of::<u64>();
+/// The SysV ABI wants RSP 16-byte aligned at the entry point.
+const STACK_ALIGN: usize = 16;
+
+/// Lays out the initial stack below `top` in `space` the way the SysV ABI
+/// describes: argc at the returned stack pointer, then the argv and envp
+/// pointer arrays, each NULL-terminated, then `auxv` with `AT_RANDOM` and
+/// `AT_NULL` appended. The strings and random bytes sit above them.
+/// `limit` is how much of the stack this may use.
+pub fn build(
+    space: &AddressSpace,
+    top: usize,
+    limit: usize,
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+    auxv: &[(u64, u64)],
+) -> Result<usize, Errno> {
+    let strings: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    if argv.len() > MAX_STRINGS || envp.len() > MAX_STRINGS || strings > ARG_MAX {
+        return Err(Errno::E2BIG);
+    }
+    let strings_start = top - strings - RANDOM_BYTES;
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (auxv.len() + 2);
+    let rsp = (strings_start - words * WORD) & !(STACK_ALIGN - 1);
+    if top - rsp > limit {
+        return Err(Errno::E2BIG);
+    }
+
+    let mut area = Vec::with_capacity(top - strings_start);
+    area.extend_from_slice(&random_bytes());
+    let mut pointers = Vec::with_capacity(words);
+    pointers.push(argv.len() as u64);
+    for list in [argv, envp] {
+        for string in list {
+            pointers.push((strings_start + area.len()) as u64);
+            area.extend_from_slice(string);
+            area.push(0);
+        }
+        pointers.push(0);
+    }
+    for &(key, value) in auxv {
+        pointers.extend_from_slice(&[key, value]);
+    }
+    pointers.extend_from_slice(&[AT_RANDOM, strings_start as u64, AT_NULL, 0]);
+
+    let words: Vec<u8> = pointers
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    space
+        .write(strings_start, &area)
+        .map_err(|_| Errno::EFAULT)?;
+    space.write(rsp, &words).map_err(|_| Errno::EFAULT)?;
+    Ok(rsp)
+}
+
+/// RDRAND output, or a TSC-seeded splitmix64 stream on CPUs without it.
+/// The latter only keeps layouts from repeating, it is no secret.
+fn random_bytes() -> [u8; RANDOM_BYTES] {
+    let mut seed = timer::tsc();
+    let mut next = || {
+        cpu::rdrand().unwrap_or_else(|| {
+            seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        })
+    };
+    let mut bytes = [0; RANDOM_BYTES];
+    bytes[..8].copy_from_slice(&next().to_le_bytes());
+    bytes[8..].copy_from_slice(&next().to_le_bytes());
+    bytes
+}
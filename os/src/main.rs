@@ -12,7 +12,7 @@ fn run(cmd: &mut Command) -> Result<()> {
 
 fn main() -> Result<()> {
     let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let kernel = root.join("target/i686-rustos/debug/kernel");
+    let kernel = root.join("target/x86_64-rustos/debug/kernel");
     let iso_dir = root.join("../iso");
     let boot_dir = iso_dir.join("boot");
     let grub_dir = boot_dir.join("grub");
@@ -23,7 +23,7 @@ fn main() -> Result<()> {
         .arg("-p")
         .arg("kernel")
         .arg("--target")
-        .arg("i686-rustos.json")
+        .arg("x86_64-rustos.json")
         .current_dir(&root))?;
 
     std::fs::copy(&kernel, boot_dir.join("kernel.elf"))
@@ -35,7 +35,7 @@ fn main() -> Result<()> {
         .arg(&iso_dir)
         .current_dir(&root))?;
 
-    run(Command::new("qemu-system-i386")
+    run(Command::new("qemu-system-x86_64")
         .arg("-cdrom")
         .arg(root.join("target/rustos.iso"))
         .arg("-serial")
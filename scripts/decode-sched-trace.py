@@ -0,0 +1,131 @@
+#!/usr/bin/env python3
+"""Decode SCHEDTRACE blocks from a RustOS serial log.
+
+Usage:
+    scripts/decode-sched-trace.py serial.log [--timeline]
+
+Prints, per pid, the share of CPU time it received, its switch count and
+wake-to-run latency, plus Jain's fairness index over the runnable pids.
+"""
+
+import argparse
+import sys
+from collections import defaultdict
+
+
+def parse(lines):
+    """Yields (tsc_hz, events) for every complete SCHEDTRACE block."""
+    tsc_hz = 0
+    events = None
+    for line in lines:
+        line = line.strip()
+        if line.startswith("SCHEDTRACE v1"):
+            fields = dict(f.split("=", 1) for f in line.split()[2:] if "=" in f)
+            tsc_hz = int(fields.get("tsc_hz", 0))
+            events = []
+        elif line.startswith("SCHEDTRACE end") and events is not None:
+            yield tsc_hz, events
+            events = None
+        elif line.startswith("T ") and events is not None:
+            parts = line.split()
+            if len(parts) != 7:
+                continue
+            _, seq, tsc, cpu, kind, pid, arg = parts
+            events.append(
+                {
+                    "seq": int(seq),
+                    "tsc": int(tsc),
+                    "cpu": int(cpu),
+                    "kind": kind,
+                    "pid": int(pid),
+                    "arg": int(arg),
+                }
+            )
+
+
+def to_us(cycles, tsc_hz):
+    return cycles * 1_000_000 / tsc_hz if tsc_hz else float(cycles)
+
+
+def analyse(events, tsc_hz, timeline):
+    unit = "us" if tsc_hz else "cycles"
+    events.sort(key=lambda e: e["seq"])
+    if not events:
+        print("no events")
+        return
+
+    runtime = defaultdict(int)
+    switches = defaultdict(int)
+    latencies = defaultdict(list)
+    running = {}
+    woken_at = {}
+    base = events[0]["tsc"]
+
+    for ev in events:
+        if timeline:
+            print(
+                f"{to_us(ev['tsc'] - base, tsc_hz):12.1f} {unit} cpu{ev['cpu']} "
+                f"{ev['kind']:<8} pid={ev['pid']} arg={ev['arg']}"
+            )
+
+        cpu, pid, tsc = ev["cpu"], ev["pid"], ev["tsc"]
+        if ev["kind"] == "switch":
+            prev = running.get(cpu)
+            if prev is not None:
+                runtime[prev[0]] += tsc - prev[1]
+            running[cpu] = (pid, tsc)
+            switches[pid] += 1
+            if pid in woken_at:
+                latencies[pid].append(tsc - woken_at.pop(pid))
+        elif ev["kind"] == "wake":
+            woken_at[pid] = tsc
+        elif ev["kind"] == "block":
+            prev = running.get(cpu)
+            if prev is not None and prev[0] == pid:
+                runtime[pid] += tsc - prev[1]
+                running[cpu] = (None, tsc)
+
+    end = events[-1]["tsc"]
+    for pid, start in running.values():
+        if pid is not None:
+            runtime[pid] += end - start
+    runtime.pop(None, None)
+
+    total = sum(runtime.values()) or 1
+    print(f"{'pid':>5} {'share':>7} {'runtime':>14} {'switches':>9} {'avg wake lat':>14}")
+    for pid in sorted(set(runtime) | set(switches)):
+        lat = latencies.get(pid)
+        avg = f"{to_us(sum(lat) / len(lat), tsc_hz):.1f}" if lat else "-"
+        print(
+            f"{pid:>5} {100 * runtime[pid] / total:6.1f}% "
+            f"{to_us(runtime[pid], tsc_hz):14.1f} {switches[pid]:>9} {avg:>14}"
+        )
+
+    shares = [v for v in runtime.values() if v > 0]
+    if shares:
+        jain = sum(shares) ** 2 / (len(shares) * sum(v * v for v in shares))
+        print(f"jain fairness index: {jain:.3f} over {len(shares)} pid(s) ({unit})")
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("log", nargs="?", help="serial log (default: stdin)")
+    parser.add_argument("--timeline", action="store_true", help="print every event")
+    args = parser.parse_args()
+
+    source = open(args.log, errors="replace") if args.log else sys.stdin
+    with source:
+        blocks = list(parse(source))
+
+    if not blocks:
+        print("no SCHEDTRACE blocks found", file=sys.stderr)
+        return 1
+
+    tsc_hz = next((hz for hz, _ in reversed(blocks) if hz), 0)
+    events = [ev for _, block in blocks for ev in block]
+    analyse(events, tsc_hz, args.timeline)
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
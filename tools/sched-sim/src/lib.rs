@@ -0,0 +1,27 @@
+//! Deterministic host-side simulation of the kernel schedulers.
+//!
+//! A [`Script`] of timer ticks, spawns, blocks, wakes and exits is replayed
+//! against a [`Policy`] adapter wrapping the real scheduler code, and every
+//! decision is checked against the invariants in [`sim`]. The resulting
+//! [`Trace`] prints in the kernel's `SCHEDTRACE` format, so
+//! `scripts/decode-sched-trace.py` reads it too.
+
+extern crate alloc;
+
+/// The os kernel's process table, compiled for the host as-is.
+#[path = "../../../os/kernel/src/process/mod.rs"]
+#[allow(dead_code)]
+mod process;
+
+/// Capabilities carried by each process table entry.
+#[path = "../../../os/kernel/src/security/mod.rs"]
+#[allow(dead_code)]
+mod security;
+
+pub mod policy;
+pub mod script;
+pub mod sim;
+
+pub use policy::{Policy, ProcessTablePolicy, RoundRobinPolicy};
+pub use script::{Event, Rng, Script, ScriptConfig};
+pub use sim::{check_fairness, run, Trace, TraceEvent, Violation};
@@ -0,0 +1,112 @@
+//! `sched-sim [--policy rr|proc] [--seed N] [--seeds N] [--cpus N] [--events N] [--trace]`
+//!
+//! With `--trace`, replays one seed and prints its SCHEDTRACE block.
+//! Otherwise runs `--seeds` consecutive seeds from `--seed` and reports the
+//! first invariant violation, if any.
+
+use std::process::ExitCode;
+
+use sched_sim::{
+    check_fairness, run, Policy, ProcessTablePolicy, RoundRobinPolicy, Script, ScriptConfig,
+};
+
+struct Args {
+    policy: String,
+    seed: u64,
+    seeds: u64,
+    config: ScriptConfig,
+    trace: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        policy: "proc".into(),
+        seed: 0,
+        seeds: 1000,
+        config: ScriptConfig::default(),
+        trace: false,
+    };
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(flag) = argv.next() {
+        if flag == "--trace" {
+            args.trace = true;
+            continue;
+        }
+        let value = argv.next().ok_or_else(|| format!("{flag} needs a value"))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{flag}: not a number: {value}"))
+        };
+        match flag.as_str() {
+            "--policy" => args.policy = value.clone(),
+            "--seed" => args.seed = number()?,
+            "--seeds" => args.seeds = number()?,
+            "--cpus" => args.config.cpus = number()?.clamp(1, 8) as usize,
+            "--events" => args.config.events = number()? as usize,
+            _ => return Err(format!("unknown flag {flag}")),
+        }
+    }
+    Ok(args)
+}
+
+fn policy(name: &str, cpus: usize) -> Result<Box<dyn Policy>, String> {
+    match name {
+        "rr" => Ok(Box::new(RoundRobinPolicy::new(cpus))),
+        "proc" => Ok(Box::new(ProcessTablePolicy::new(cpus))),
+        _ => Err(format!("unknown policy {name} (expected rr or proc)")),
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("sched-sim: {err}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let seeds = if args.trace { 1 } else { args.seeds };
+    for seed in args.seed..args.seed + seeds {
+        let mut policy = match policy(&args.policy, args.config.cpus) {
+            Ok(policy) => policy,
+            Err(err) => {
+                eprintln!("sched-sim: {err}");
+                return ExitCode::from(2);
+            }
+        };
+        let script = Script::random(seed, &args.config);
+        let (trace, result) = run(policy.as_mut(), &script);
+        if args.trace {
+            print!("{trace}");
+        }
+        if let Err(violation) = result {
+            println!("{} seed {seed}: {violation}", policy.name());
+            return ExitCode::FAILURE;
+        }
+    }
+
+    // Fairness needs a stable run queue, so check it on pure contention.
+    for cpus in 1..=args.config.cpus {
+        for tasks in 1..=8 {
+            let mut policy = policy(&args.policy, cpus).expect("policy name was checked");
+            let script = Script::contention(cpus, tasks, 5, 200);
+            let (trace, result) = run(policy.as_mut(), &script);
+            let result = result.and_then(|()| check_fairness(&trace, 1));
+            if let Err(violation) = result {
+                println!(
+                    "{} contention cpus={cpus} tasks={tasks}: {violation}",
+                    policy.name()
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if !args.trace {
+        println!("{}: {seeds} seed(s) passed", args.policy);
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use kernel::scheduler::{SmpScheduler, Task};
+
+use crate::process::{ProcessState, ProcessTable, AGING_TICKS};
+
+/// Task handle as the wrapped scheduler knows it.
+pub type TaskId = u64;
+
+/// Ticks between load-balancing passes on CPU 0, as in the os kernel.
+const BALANCE_INTERVAL_TICKS: u64 = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Migration {
+    pub task: TaskId,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Adapter between the simulator and one scheduler implementation.
+///
+/// `block`, `wake` and `exit` return the CPU that has to make a scheduling
+/// decision right away, mirroring what the kernel does after them.
+pub trait Policy {
+    fn name(&self) -> &'static str;
+    fn cpu_count(&self) -> usize;
+    /// Creates a runnable task, or returns `None` when the scheduler is full.
+    fn spawn(&mut self, priority: u8) -> Option<TaskId>;
+    fn block(&mut self, task: TaskId, now: u64) -> Option<usize>;
+    fn wake(&mut self, task: TaskId, now: u64) -> Option<usize>;
+    fn exit(&mut self, task: TaskId, now: u64) -> Option<usize>;
+    /// Periodic work on the `tick`th timer tick of `cpu`, before its decision.
+    fn balance(&mut self, cpu: usize, tick: u64) -> Vec<Migration>;
+    /// One scheduling decision; returns the task running on `cpu` afterwards.
+    fn schedule(&mut self, cpu: usize, now: u64) -> Option<TaskId>;
+    fn running(&self, cpu: usize) -> Option<TaskId>;
+    /// Every ready or running task, with the CPU whose queue holds it.
+    fn runnable(&self) -> Vec<(TaskId, usize)>;
+    /// Whether a task may only run ahead of a higher-priority one on its CPU
+    /// after waiting longer than it.
+    fn honours_priority(&self) -> bool;
+    /// Most decisions a runnable task may lose in a row on one CPU, given how
+    /// many distinct tasks were picked over it and their highest priority.
+    fn starvation_bound(
+        &self,
+        priority: u8,
+        max_competitor_priority: u8,
+        competitors: usize,
+    ) -> u64;
+}
+
+/// `kernel::scheduler::SmpScheduler`: per-CPU round robin, no priorities.
+pub struct RoundRobinPolicy {
+    scheduler: SmpScheduler,
+    blocked: HashMap<TaskId, Task>,
+}
+
+impl RoundRobinPolicy {
+    pub fn new(cpus: usize) -> Self {
+        Self {
+            scheduler: SmpScheduler::new(cpus),
+            blocked: HashMap::new(),
+        }
+    }
+}
+
+impl Policy for RoundRobinPolicy {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn cpu_count(&self) -> usize {
+        self.scheduler.cpu_count()
+    }
+
+    fn spawn(&mut self, _priority: u8) -> Option<TaskId> {
+        let task = Task::new(0x1000, 0x8000);
+        self.scheduler.add_task(task).ok()?;
+        Some(task.id)
+    }
+
+    // Removing the current task makes the next one current without a
+    // decision, so none of these ask for one.
+    fn block(&mut self, task: TaskId, _now: u64) -> Option<usize> {
+        if let Some(task) = self.scheduler.remove_task(task) {
+            self.blocked.insert(task.id, task);
+        }
+        None
+    }
+
+    fn wake(&mut self, task: TaskId, _now: u64) -> Option<usize> {
+        if let Some(task) = self.blocked.remove(&task) {
+            let _ = self.scheduler.add_task(task);
+        }
+        None
+    }
+
+    fn exit(&mut self, task: TaskId, _now: u64) -> Option<usize> {
+        if self.scheduler.remove_task(task).is_none() {
+            self.blocked.remove(&task);
+        }
+        None
+    }
+
+    fn balance(&mut self, cpu: usize, tick: u64) -> Vec<Migration> {
+        let mut migrations = Vec::new();
+        if cpu == 0 && tick.is_multiple_of(BALANCE_INTERVAL_TICKS) {
+            migrations.extend(self.scheduler.balance());
+        }
+        migrations.extend(self.scheduler.steal(cpu));
+        migrations
+            .into_iter()
+            .map(|m| Migration {
+                task: m.task,
+                from: m.from,
+                to: m.to,
+            })
+            .collect()
+    }
+
+    fn schedule(&mut self, cpu: usize, _now: u64) -> Option<TaskId> {
+        let _ = self.scheduler.on_timer_tick(cpu);
+        self.running(cpu)
+    }
+
+    fn running(&self, cpu: usize) -> Option<TaskId> {
+        self.scheduler.current_task(cpu).map(|task| task.id)
+    }
+
+    fn runnable(&self) -> Vec<(TaskId, usize)> {
+        (0..self.cpu_count())
+            .flat_map(|cpu| self.scheduler.tasks(cpu).map(move |task| (task.id, cpu)))
+            .collect()
+    }
+
+    fn honours_priority(&self) -> bool {
+        false
+    }
+
+    fn starvation_bound(&self, _priority: u8, _max_priority: u8, competitors: usize) -> u64 {
+        competitors as u64
+    }
+}
+
+/// The os kernel's `ProcessTable`: priorities with aging.
+pub struct ProcessTablePolicy {
+    table: ProcessTable,
+    cpus: usize,
+}
+
+impl ProcessTablePolicy {
+    pub fn new(cpus: usize) -> Self {
+        let mut table = ProcessTable::new();
+        table.set_cpu_count(cpus);
+        Self { table, cpus }
+    }
+
+    fn cpu_of(&self, task: TaskId) -> Option<usize> {
+        self.table.get(task as u32).map(|p| p.cpu)
+    }
+}
+
+impl Policy for ProcessTablePolicy {
+    fn name(&self) -> &'static str {
+        "process-table"
+    }
+
+    fn cpu_count(&self) -> usize {
+        self.cpus
+    }
+
+    fn spawn(&mut self, priority: u8) -> Option<TaskId> {
+        Some(self.table.spawn(None, "sim", priority) as TaskId)
+    }
+
+    fn block(&mut self, task: TaskId, now: u64) -> Option<usize> {
+        let cpu = self.cpu_of(task)?;
+        self.table.block(task as u32, now).then_some(cpu)
+    }
+
+    fn wake(&mut self, task: TaskId, now: u64) -> Option<usize> {
+        self.table.wake(task as u32, now)
+    }
+
+    fn exit(&mut self, task: TaskId, now: u64) -> Option<usize> {
+        let cpu = self.cpu_of(task)?;
+        self.table.exit(task as u32, now, 0).then_some(cpu)
+    }
+
+    fn balance(&mut self, cpu: usize, tick: u64) -> Vec<Migration> {
+        if cpu != 0 || !tick.is_multiple_of(BALANCE_INTERVAL_TICKS) {
+            return Vec::new();
+        }
+        self.table
+            .balance()
+            .map(|m| Migration {
+                task: m.pid as TaskId,
+                from: m.from,
+                to: m.to,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    fn schedule(&mut self, cpu: usize, now: u64) -> Option<TaskId> {
+        self.table.schedule_next(cpu, now).map(TaskId::from)
+    }
+
+    fn running(&self, cpu: usize) -> Option<TaskId> {
+        self.table
+            .list()
+            .find(|p| p.cpu == cpu && p.state == ProcessState::Running)
+            .map(|p| p.pid as TaskId)
+    }
+
+    fn runnable(&self) -> Vec<(TaskId, usize)> {
+        self.table
+            .list()
+            .filter(|p| matches!(p.state, ProcessState::Ready | ProcessState::Running))
+            .map(|p| (p.pid as TaskId, p.cpu))
+            .collect()
+    }
+
+    fn honours_priority(&self) -> bool {
+        true
+    }
+
+    fn starvation_bound(&self, priority: u8, max_priority: u8, competitors: usize) -> u64 {
+        // Past this many losses the task outranks every competitor that has
+        // run since; each of the others can still go first once.
+        let aged_out =
+            (max_priority as u64 + 1).saturating_sub(priority as u64) * AGING_TICKS as u64;
+        aged_out + competitors as u64
+    }
+}
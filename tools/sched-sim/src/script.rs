@@ -0,0 +1,148 @@
+/// One scripted input to the simulator. Tasks are referred to by the order
+/// they were spawned in, starting at 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Tick { cpu: usize },
+    Spawn { priority: u8 },
+    Block { task: usize },
+    Wake { task: usize },
+    Exit { task: usize },
+}
+
+/// SplitMix64: small, fast and identical on every host.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`; `n` must be non-zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> Option<T> {
+        if items.is_empty() {
+            return None;
+        }
+        Some(items[self.below(items.len() as u64) as usize])
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScriptConfig {
+    pub cpus: usize,
+    pub events: usize,
+    /// Most tasks alive (runnable or blocked) at once.
+    pub max_tasks: usize,
+    /// Spawned priorities are drawn from `1..=max_priority`.
+    pub max_priority: u8,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self {
+            cpus: 2,
+            events: 2000,
+            max_tasks: 12,
+            max_priority: 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub cpus: usize,
+    pub events: Vec<Event>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Liveness {
+    Runnable,
+    Blocked,
+    Exited,
+}
+
+impl Script {
+    /// A reproducible mix of mostly ticks with spawns, blocks, wakes and
+    /// exits. Every event is valid for the state the earlier ones leave.
+    pub fn random(seed: u64, config: &ScriptConfig) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut tasks = Vec::new();
+        let mut events = Vec::with_capacity(config.events);
+        let priority = |rng: &mut Rng| 1 + rng.below(config.max_priority.max(1) as u64) as u8;
+
+        let initial = 1 + rng.below(config.max_tasks.max(1) as u64) as usize;
+        for _ in 0..initial {
+            events.push(Event::Spawn {
+                priority: priority(&mut rng),
+            });
+            tasks.push(Liveness::Runnable);
+        }
+
+        while events.len() < config.events {
+            let with = |state| {
+                (0..tasks.len())
+                    .filter(|&t| tasks[t] == state)
+                    .collect::<Vec<_>>()
+            };
+            let live = tasks.iter().filter(|&&t| t != Liveness::Exited).count();
+
+            let event = match rng.below(100) {
+                0..=64 => None,
+                65..=74 if live < config.max_tasks => {
+                    tasks.push(Liveness::Runnable);
+                    Some(Event::Spawn {
+                        priority: priority(&mut rng),
+                    })
+                }
+                75..=84 => rng.pick(&with(Liveness::Runnable)).map(|task| {
+                    tasks[task] = Liveness::Blocked;
+                    Event::Block { task }
+                }),
+                85..=94 => rng.pick(&with(Liveness::Blocked)).map(|task| {
+                    tasks[task] = Liveness::Runnable;
+                    Event::Wake { task }
+                }),
+                95..=99 => {
+                    let mut live = with(Liveness::Runnable);
+                    live.extend(with(Liveness::Blocked));
+                    rng.pick(&live).map(|task| {
+                        tasks[task] = Liveness::Exited;
+                        Event::Exit { task }
+                    })
+                }
+                _ => None,
+            };
+
+            events.push(event.unwrap_or(Event::Tick {
+                cpu: rng.below(config.cpus as u64) as usize,
+            }));
+        }
+
+        Self {
+            cpus: config.cpus,
+            events,
+        }
+    }
+
+    /// `tasks` always-runnable tasks of equal priority, then `ticks` timer
+    /// ticks on every CPU in turn.
+    pub fn contention(cpus: usize, tasks: usize, priority: u8, ticks: usize) -> Self {
+        let mut events = vec![Event::Spawn { priority }; tasks];
+        for _ in 0..ticks {
+            events.extend((0..cpus).map(|cpu| Event::Tick { cpu }));
+        }
+        Self { cpus, events }
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::policy::{Policy, TaskId};
+use crate::script::{Event, Script};
+
+/// Simulated TSC cycles between two script events.
+const CYCLES_PER_EVENT: u64 = 1000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    Spawn {
+        task: usize,
+        cpu: usize,
+        priority: u8,
+    },
+    Block {
+        task: usize,
+        cpu: Option<usize>,
+    },
+    Wake {
+        task: usize,
+        cpu: usize,
+    },
+    Exit {
+        task: usize,
+        cpu: Option<usize>,
+    },
+    Migrate {
+        task: usize,
+        from: usize,
+        to: usize,
+    },
+    /// One scheduling decision and the tasks that were runnable on `cpu`.
+    Decision {
+        cpu: usize,
+        picked: Option<usize>,
+        runnable: Vec<usize>,
+    },
+    /// The task running on `cpu` changed.
+    Switch {
+        cpu: usize,
+        from: Option<usize>,
+        to: Option<usize>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceEntry {
+    /// Index of the script event that produced this entry.
+    pub step: usize,
+    pub time: u64,
+    pub event: TraceEvent,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    pub cpus: usize,
+    pub priorities: Vec<u8>,
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    /// Scheduling decisions that picked each task.
+    pub fn runs(&self) -> Vec<u64> {
+        let mut runs = vec![0; self.priorities.len()];
+        for entry in &self.entries {
+            if let TraceEvent::Decision {
+                picked: Some(task), ..
+            } = entry.event
+            {
+                runs[task] += 1;
+            }
+        }
+        runs
+    }
+}
+
+/// Prints the trace as a kernel `SCHEDTRACE` block. Task `n` shows up as
+/// pid `n + 1`, keeping pid 0 for an idle CPU; time is in simulated cycles.
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pid = |task: Option<usize>| task.map_or(0, |t| t + 1);
+        writeln!(f, "SCHEDTRACE v1 tsc_hz=0")?;
+
+        let mut seq = 0;
+        for entry in &self.entries {
+            let (cpu, kind, task, arg) = match entry.event {
+                TraceEvent::Spawn {
+                    task,
+                    cpu,
+                    priority,
+                } => (cpu, "spawn", Some(task), priority as usize),
+                TraceEvent::Block { task, cpu } => (cpu.unwrap_or(0), "block", Some(task), 0),
+                TraceEvent::Wake { task, cpu } => (cpu, "wake", Some(task), cpu),
+                TraceEvent::Exit { task, cpu } => (cpu.unwrap_or(0), "exit", Some(task), 0),
+                TraceEvent::Migrate { task, from, to } => (from, "migrate", Some(task), to),
+                TraceEvent::Switch { cpu, from, to } => (cpu, "switch", to, pid(from)),
+                TraceEvent::Decision { .. } => continue,
+            };
+            seq += 1;
+            writeln!(
+                f,
+                "T {} {} {} {} {} {}",
+                seq,
+                entry.time,
+                cpu,
+                kind,
+                pid(task),
+                arg
+            )?;
+        }
+
+        writeln!(f, "SCHEDTRACE end dropped=0")
+    }
+}
+
+/// A broken invariant, or a script the policy could not follow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub step: usize,
+    pub invariant: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}: {}: {}", self.step, self.invariant, self.detail)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Runnable,
+    Blocked,
+    Exited,
+}
+
+struct SimTask {
+    id: TaskId,
+    priority: u8,
+    state: State,
+    /// Decisions lost since last running or waking; what priority aging is
+    /// based on.
+    waited: u64,
+    /// Decisions lost since last running, waking or migrating, and the
+    /// distinct tasks picked instead, told apart by their `arrival`.
+    lost: u64,
+    picked_over: BTreeSet<(usize, u64)>,
+    /// Times this task entered a run queue. A round robin queues each
+    /// arrival at the back, so it may go again ahead of a waiting task.
+    arrival: u64,
+}
+
+struct Sim<'a> {
+    policy: &'a mut dyn Policy,
+    tasks: Vec<SimTask>,
+    by_id: HashMap<TaskId, usize>,
+    running: Vec<Option<usize>>,
+    ticks: Vec<u64>,
+    trace: Trace,
+    step: usize,
+}
+
+/// Replays `script` against `policy`, checking after every event that:
+///
+/// - the policy's runnable set matches the script (nothing lost or revived),
+/// - each CPU runs one of its own runnable tasks, and never idles while it
+///   has one (work conservation),
+/// - no task loses more decisions in a row than the policy's starvation
+///   bound, and
+/// - for priority policies, a task only runs ahead of a higher-priority one
+///   on its CPU after waiting longer than it.
+///
+/// Replay stops at the first violation; the trace up to it is returned
+/// either way.
+pub fn run(policy: &mut dyn Policy, script: &Script) -> (Trace, Result<(), Violation>) {
+    let cpus = policy.cpu_count();
+    if cpus != script.cpus {
+        let violation = Violation {
+            step: 0,
+            invariant: "script",
+            detail: format!("script has {} CPUs, policy {}", script.cpus, cpus),
+        };
+        return (Trace::default(), Err(violation));
+    }
+
+    let mut sim = Sim {
+        policy,
+        tasks: Vec::new(),
+        by_id: HashMap::new(),
+        running: vec![None; cpus],
+        ticks: vec![0; cpus],
+        trace: Trace {
+            cpus,
+            ..Trace::default()
+        },
+        step: 0,
+    };
+
+    let mut result = Ok(());
+    for (step, event) in script.events.iter().enumerate() {
+        sim.step = step;
+        result = sim
+            .apply(*event)
+            .and_then(|()| sim.sync_running())
+            .and_then(|()| sim.check_consistency());
+        if result.is_err() {
+            break;
+        }
+    }
+    (sim.trace, result)
+}
+
+impl Sim<'_> {
+    fn now(&self) -> u64 {
+        (self.step as u64 + 1) * CYCLES_PER_EVENT
+    }
+
+    fn fail<T>(&self, invariant: &'static str, detail: String) -> Result<T, Violation> {
+        Err(Violation {
+            step: self.step,
+            invariant,
+            detail,
+        })
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        self.trace.entries.push(TraceEntry {
+            step: self.step,
+            time: self.now(),
+            event,
+        });
+    }
+
+    fn task(&self, id: TaskId) -> Result<usize, Violation> {
+        match self.by_id.get(&id) {
+            Some(&task) => Ok(task),
+            None => self.fail("conservation", format!("unknown task id {id}")),
+        }
+    }
+
+    fn target(&self, task: usize, expected: &[State]) -> Result<TaskId, Violation> {
+        match self.tasks.get(task) {
+            Some(t) if expected.contains(&t.state) => Ok(t.id),
+            _ => self.fail("script", format!("task {task} is not in a valid state")),
+        }
+    }
+
+    /// Runnable tasks on each CPU according to the policy.
+    fn runnable(&self) -> Result<BTreeMap<usize, usize>, Violation> {
+        let mut runnable = BTreeMap::new();
+        for (id, cpu) in self.policy.runnable() {
+            let task = self.task(id)?;
+            if runnable.insert(task, cpu).is_some() {
+                return self.fail("conservation", format!("task {task} queued twice"));
+            }
+        }
+        Ok(runnable)
+    }
+
+    fn cpu_of(&self, task: usize) -> Result<Option<usize>, Violation> {
+        Ok(self.runnable()?.get(&task).copied())
+    }
+
+    fn apply(&mut self, event: Event) -> Result<(), Violation> {
+        let now = self.now();
+        match event {
+            Event::Tick { cpu } => {
+                if cpu >= self.running.len() {
+                    return self.fail("script", format!("no CPU {cpu}"));
+                }
+                self.ticks[cpu] += 1;
+                for migration in self.policy.balance(cpu, self.ticks[cpu]) {
+                    let task = self.task(migration.task)?;
+                    let t = &mut self.tasks[task];
+                    t.lost = 0;
+                    t.picked_over.clear();
+                    t.arrival += 1;
+                    self.record(TraceEvent::Migrate {
+                        task,
+                        from: migration.from,
+                        to: migration.to,
+                    });
+                }
+                self.decide(cpu)
+            }
+            Event::Spawn { priority } => {
+                let Some(id) = self.policy.spawn(priority) else {
+                    return self.fail("script", "policy has no room for another task".into());
+                };
+                let task = self.tasks.len();
+                self.tasks.push(SimTask {
+                    id,
+                    priority,
+                    state: State::Runnable,
+                    waited: 0,
+                    lost: 0,
+                    picked_over: BTreeSet::new(),
+                    arrival: 0,
+                });
+                self.by_id.insert(id, task);
+                self.trace.priorities.push(priority);
+                match self.cpu_of(task)? {
+                    Some(cpu) => {
+                        self.record(TraceEvent::Spawn {
+                            task,
+                            cpu,
+                            priority,
+                        });
+                        Ok(())
+                    }
+                    None => self.fail("conservation", format!("spawned task {task} not queued")),
+                }
+            }
+            Event::Block { task } => {
+                let id = self.target(task, &[State::Runnable])?;
+                let cpu = self.cpu_of(task)?;
+                let reschedule = self.policy.block(id, now);
+                self.tasks[task].state = State::Blocked;
+                self.record(TraceEvent::Block { task, cpu });
+                reschedule.map_or(Ok(()), |cpu| self.decide(cpu))
+            }
+            Event::Wake { task } => {
+                let id = self.target(task, &[State::Blocked])?;
+                let reschedule = self.policy.wake(id, now);
+                let t = &mut self.tasks[task];
+                t.state = State::Runnable;
+                t.waited = 0;
+                t.lost = 0;
+                t.picked_over.clear();
+                t.arrival += 1;
+                match self.cpu_of(task)? {
+                    Some(cpu) => self.record(TraceEvent::Wake { task, cpu }),
+                    None => {
+                        return self.fail("conservation", format!("woken task {task} not queued"))
+                    }
+                }
+                reschedule.map_or(Ok(()), |cpu| self.decide(cpu))
+            }
+            Event::Exit { task } => {
+                let id = self.target(task, &[State::Runnable, State::Blocked])?;
+                let cpu = self.cpu_of(task)?;
+                let reschedule = self.policy.exit(id, now);
+                self.tasks[task].state = State::Exited;
+                self.record(TraceEvent::Exit { task, cpu });
+                reschedule.map_or(Ok(()), |cpu| self.decide(cpu))
+            }
+        }
+    }
+
+    fn decide(&mut self, cpu: usize) -> Result<(), Violation> {
+        let candidates: Vec<usize> = self
+            .runnable()?
+            .into_iter()
+            .filter(|&(_, on)| on == cpu)
+            .map(|(task, _)| task)
+            .collect();
+        let picked = match self.policy.schedule(cpu, self.now()) {
+            Some(id) => Some(self.task(id)?),
+            None => None,
+        };
+
+        match picked {
+            None if !candidates.is_empty() => {
+                return self.fail(
+                    "work-conserving",
+                    format!("cpu{cpu} idles with {candidates:?} runnable"),
+                );
+            }
+            Some(task) if !candidates.contains(&task) => {
+                return self.fail(
+                    "runnable",
+                    format!("cpu{cpu} picked task {task}, not runnable there"),
+                );
+            }
+            _ => {}
+        }
+
+        if let Some(picked) = picked {
+            self.check_priority(cpu, picked, &candidates)?;
+            let turn = (picked, self.tasks[picked].arrival);
+            for &task in candidates.iter().filter(|&&t| t != picked) {
+                let t = &mut self.tasks[task];
+                t.waited += 1;
+                t.lost += 1;
+                t.picked_over.insert(turn);
+                self.check_starvation(cpu, task)?;
+            }
+            let t = &mut self.tasks[picked];
+            t.waited = 0;
+            t.lost = 0;
+            t.picked_over.clear();
+        }
+
+        self.record(TraceEvent::Decision {
+            cpu,
+            picked,
+            runnable: candidates,
+        });
+        Ok(())
+    }
+
+    fn check_priority(
+        &self,
+        cpu: usize,
+        picked: usize,
+        candidates: &[usize],
+    ) -> Result<(), Violation> {
+        if !self.policy.honours_priority() {
+            return Ok(());
+        }
+        let p = &self.tasks[picked];
+        for &other in candidates {
+            let o = &self.tasks[other];
+            if o.priority > p.priority && p.waited <= o.waited {
+                return self.fail(
+                    "priority",
+                    format!(
+                        "cpu{cpu} picked task {picked} (prio {}, waited {}) over task {other} (prio {}, waited {})",
+                        p.priority, p.waited, o.priority, o.waited
+                    ),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn check_starvation(&self, cpu: usize, task: usize) -> Result<(), Violation> {
+        let t = &self.tasks[task];
+        let max_priority = t
+            .picked_over
+            .iter()
+            .map(|&(other, _)| self.tasks[other].priority)
+            .max()
+            .unwrap_or(0);
+        let bound = self
+            .policy
+            .starvation_bound(t.priority, max_priority, t.picked_over.len());
+        if t.lost > bound {
+            return self.fail(
+                "starvation",
+                format!(
+                    "task {task} (prio {}) lost {} decisions in a row on cpu{cpu}, bound {bound}",
+                    t.priority, t.lost
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    fn sync_running(&mut self) -> Result<(), Violation> {
+        for cpu in 0..self.running.len() {
+            let now = match self.policy.running(cpu) {
+                Some(id) => Some(self.task(id)?),
+                None => None,
+            };
+            if now != self.running[cpu] {
+                // Some policies promote the next task when the running one
+                // leaves, without a decision; that still counts as a turn.
+                if let Some(task) = now {
+                    let t = &mut self.tasks[task];
+                    t.waited = 0;
+                    t.lost = 0;
+                    t.picked_over.clear();
+                }
+                self.record(TraceEvent::Switch {
+                    cpu,
+                    from: self.running[cpu],
+                    to: now,
+                });
+                self.running[cpu] = now;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_consistency(&self) -> Result<(), Violation> {
+        let runnable = self.runnable()?;
+        for (task, t) in self.tasks.iter().enumerate() {
+            let queued = runnable.contains_key(&task);
+            if queued != (t.state == State::Runnable) {
+                return self.fail(
+                    "conservation",
+                    format!("task {task} queued={queued} but should not be"),
+                );
+            }
+        }
+        for (cpu, running) in self.running.iter().enumerate() {
+            if let Some(task) = running {
+                if runnable.get(task) != Some(&cpu) {
+                    return self.fail(
+                        "runnable",
+                        format!("cpu{cpu} is running task {task}, not queued there"),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that equal-priority tasks sharing a CPU get equal turns.
+///
+/// For each CPU, only the decisions after its last membership change
+/// (spawn, block, wake, exit or migration) count; within that window every
+/// same-priority group of runnable tasks must differ by at most `slack`
+/// picks.
+pub fn check_fairness(trace: &Trace, slack: u64) -> Result<(), Violation> {
+    for cpu in 0..trace.cpus {
+        let touches = |event: &TraceEvent| match *event {
+            TraceEvent::Spawn { cpu: on, .. } | TraceEvent::Wake { cpu: on, .. } => on == cpu,
+            TraceEvent::Block { cpu: on, .. } | TraceEvent::Exit { cpu: on, .. } => on == Some(cpu),
+            TraceEvent::Migrate { from, to, .. } => from == cpu || to == cpu,
+            _ => false,
+        };
+        let start = trace
+            .entries
+            .iter()
+            .rposition(|e| touches(&e.event))
+            .map_or(0, |i| i + 1);
+
+        let mut runs: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut last_step = 0;
+        for entry in &trace.entries[start..] {
+            if let TraceEvent::Decision {
+                cpu: on,
+                picked,
+                ref runnable,
+            } = entry.event
+            {
+                if on != cpu {
+                    continue;
+                }
+                for &task in runnable {
+                    runs.entry(task).or_default();
+                }
+                if let Some(task) = picked {
+                    *runs.entry(task).or_default() += 1;
+                }
+                last_step = entry.step;
+            }
+        }
+
+        let mut groups: BTreeMap<u8, Vec<(usize, u64)>> = BTreeMap::new();
+        for (task, count) in runs {
+            groups
+                .entry(trace.priorities[task])
+                .or_default()
+                .push((task, count));
+        }
+        for (priority, group) in groups {
+            let min = group.iter().map(|&(_, c)| c).min().unwrap_or(0);
+            let max = group.iter().map(|&(_, c)| c).max().unwrap_or(0);
+            if max - min > slack {
+                return Err(Violation {
+                    step: last_step,
+                    invariant: "fairness",
+                    detail: format!("cpu{cpu} prio {priority} turns (task, runs) {group:?}"),
+                });
+            }
+        }
+    }
+    Ok(())
+}
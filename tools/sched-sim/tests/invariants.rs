@@ -0,0 +1,112 @@
+use sched_sim::{
+    check_fairness, run, Event, Policy, ProcessTablePolicy, RoundRobinPolicy, Script, ScriptConfig,
+};
+
+const SEEDS: u64 = 2000;
+
+fn policies(cpus: usize) -> [Box<dyn Policy>; 2] {
+    [
+        Box::new(RoundRobinPolicy::new(cpus)),
+        Box::new(ProcessTablePolicy::new(cpus)),
+    ]
+}
+
+#[test]
+fn random_scripts_keep_every_invariant() {
+    for seed in 0..SEEDS {
+        let config = ScriptConfig {
+            cpus: 1 + (seed % 4) as usize,
+            events: 500,
+            ..ScriptConfig::default()
+        };
+        let script = Script::random(seed, &config);
+        for mut policy in policies(config.cpus) {
+            let (_, result) = run(policy.as_mut(), &script);
+            if let Err(violation) = result {
+                panic!("{} seed {seed}: {violation}", policy.name());
+            }
+        }
+    }
+}
+
+#[test]
+fn equal_priority_tasks_share_each_cpu_evenly() {
+    for cpus in 1..=4 {
+        for tasks in 1..=10 {
+            let script = Script::contention(cpus, tasks, 5, 300);
+            for mut policy in policies(cpus) {
+                let (trace, result) = run(policy.as_mut(), &script);
+                if let Err(violation) = result.and_then(|()| check_fairness(&trace, 1)) {
+                    panic!("{} cpus={cpus} tasks={tasks}: {violation}", policy.name());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn aging_lets_low_priority_work_through_without_inverting_priorities() {
+    let mut events = vec![
+        Event::Spawn { priority: 200 },
+        Event::Spawn { priority: 200 },
+        Event::Spawn { priority: 1 },
+    ];
+    events.extend((0..5000).map(|_| Event::Tick { cpu: 0 }));
+    let script = Script { cpus: 1, events };
+
+    let mut policy = ProcessTablePolicy::new(1);
+    let (trace, result) = run(&mut policy, &script);
+    result.unwrap();
+
+    let runs = trace.runs();
+    assert!(runs[2] > 0, "low priority task never ran: {runs:?}");
+    assert!(runs[0] > 10 * runs[2] && runs[1] > 10 * runs[2], "{runs:?}");
+}
+
+#[test]
+fn blocked_task_is_off_cpu_until_woken() {
+    let mut events = vec![Event::Spawn { priority: 3 }, Event::Spawn { priority: 3 }];
+    events.extend([Event::Tick { cpu: 0 }, Event::Block { task: 0 }]);
+    events.extend((0..20).map(|_| Event::Tick { cpu: 0 }));
+    events.push(Event::Wake { task: 0 });
+    events.extend((0..20).map(|_| Event::Tick { cpu: 0 }));
+    let script = Script { cpus: 1, events };
+
+    for mut policy in policies(1) {
+        let (trace, result) = run(policy.as_mut(), &script);
+        result.unwrap();
+        let runs = trace.runs();
+        assert!(runs[0] >= 9 && runs[1] >= 20, "{}: {runs:?}", policy.name());
+    }
+}
+
+#[test]
+fn replay_is_deterministic_and_prints_as_schedtrace() {
+    let config = ScriptConfig::default();
+    let script = Script::random(7, &config);
+    let first = run(&mut ProcessTablePolicy::new(config.cpus), &script)
+        .0
+        .to_string();
+    let second = run(&mut ProcessTablePolicy::new(config.cpus), &script)
+        .0
+        .to_string();
+    assert_eq!(first, second);
+
+    let lines: Vec<&str> = first.lines().collect();
+    assert_eq!(lines.first(), Some(&"SCHEDTRACE v1 tsc_hz=0"));
+    assert_eq!(lines.last(), Some(&"SCHEDTRACE end dropped=0"));
+    assert!(lines
+        .iter()
+        .any(|l| l.starts_with("T ") && l.split(' ').nth(4) == Some("switch")));
+}
+
+#[test]
+fn script_errors_are_reported_not_panicked() {
+    let script = Script {
+        cpus: 1,
+        events: vec![Event::Spawn { priority: 1 }, Event::Wake { task: 0 }],
+    };
+    let (_, result) = run(&mut RoundRobinPolicy::new(1), &script);
+    let violation = result.unwrap_err();
+    assert_eq!((violation.step, violation.invariant), (1, "script"));
+}